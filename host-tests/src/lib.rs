@@ -0,0 +1,19 @@
+//! Unit tests of the parts of `arch` that only depend on `core`, run on the
+//! host since `arch` itself only builds for its kernel targets. Each module
+//! includes a source file of `arch` with its tests:
+//!
+//! ```text
+//! make test
+//! ```
+#![allow(dead_code)]
+
+extern crate alloc;
+
+#[path = "../../src/cpumask/mask.rs"]
+mod cpumask;
+#[path = "../../src/gdbstub/packet.rs"]
+mod gdbstub_packet;
+#[path = "../../src/ipi/queue.rs"]
+mod ipi_queue;
+#[path = "../../src/misaligned/insn.rs"]
+mod misaligned_insn;
@@ -3,7 +3,8 @@ mod percpu;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::{format_ident, quote};
-use syn::{Error, ItemFn, ItemStatic, parse_macro_input};
+use syn::punctuated::Punctuated;
+use syn::{Error, ItemFn, ItemStatic, Meta, Token, parse_macro_input};
 
 #[proc_macro_attribute]
 pub fn arch_entry(_input: TokenStream, annotated_item: TokenStream) -> TokenStream {
@@ -14,12 +15,60 @@ pub fn arch_entry(_input: TokenStream, annotated_item: TokenStream) -> TokenStre
     })
 }
 
+/// Registers a trap handler for a `TrapClass`.
+///
+/// The function must have the `TrapHandler` signature
+/// `fn(&mut TrapRegs, &TrapKind) -> bool`. An optional priority orders it in
+/// the handler chain, it defaults to `0`:
+///
+/// ```rust
+/// #[arch_interrupt(PageFault, priority = 10)]
+/// fn page_fault(tf: &mut TrapRegs, kind: &TrapKind) -> bool { ... }
+/// ```
+///
+/// The handler is kept in the final binary with `#[used(linker)]`, so the
+/// crate using this attribute needs `#![feature(used_with_arg)]`.
 #[proc_macro_attribute]
-pub fn arch_interrupt(_input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+pub fn arch_interrupt(attr: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr with Punctuated::<Meta, Token![,]>::parse_terminated);
     let annotated_item = parse_macro_input!(annotated_item as ItemFn);
+
+    let mut class = None;
+    let mut priority = None;
+    for arg in args {
+        match arg {
+            Meta::Path(path) if class.is_none() => class = Some(path),
+            Meta::NameValue(nv) if nv.path.is_ident("priority") && priority.is_none() => {
+                priority = Some(nv.value)
+            }
+            arg => {
+                return compiler_error(Error::new_spanned(
+                    arg,
+                    "expect `#[arch_interrupt(Class)]` or `#[arch_interrupt(Class, priority = N)]`",
+                ));
+            }
+        }
+    }
+    let Some(class) = class else {
+        return compiler_error(Error::new(
+            Span::call_site(),
+            "expect a trap class: `#[arch_interrupt(Class)]`",
+        ));
+    };
+    let priority = priority.map_or_else(|| quote!(0), |p| quote!(#p));
+
+    let name = &annotated_item.sig.ident;
+    let entry_name = format_ident!("__TRAP_HANDLER_{}", name.to_string().to_uppercase());
     TokenStream::from(quote! {
-        #[export_name = "_interrupt_for_arch"]
         #annotated_item
+
+        #[used(linker)]
+        #[unsafe(link_section = "trap_handlers")]
+        static #entry_name: ::arch::TrapHandlerEntry = ::arch::TrapHandlerEntry {
+            class: ::arch::TrapClass::#class,
+            priority: #priority,
+            handler: #name,
+        };
     })
 }
 
@@ -77,13 +126,13 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Returns the value of the per-CPU data on the current CPU. Preemption will
             /// be disabled during the call.
             pub fn read_current(&self) -> #ty {
-                unsafe { self.read_current_raw() }
+                self.with_current(|val| *val)
             }
 
             /// Set the value of the per-CPU data on the current CPU. Preemption will
             /// be disabled during the call.
             pub fn write_current(&self, val: #ty) {
-                unsafe { self.write_current_raw(val) }
+                self.with_current(|cur| *cur = val)
             }
         }
     } else {
@@ -92,7 +141,7 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let current_ptr = percpu::gen_current_ptr(inner_symbol_name, ty);
     quote! {
-        #[cfg_attr(not(target_os = "macos"), link_section = "percpu")] // unimplemented on macos
+        #[cfg_attr(not(target_os = "macos"), unsafe(link_section = "percpu"))] // unimplemented on macos
         #[used(linker)]
         #(#attrs)*
         static mut #inner_symbol_name: #ty = #init_expr;
@@ -108,11 +157,11 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Returns the offset relative to the per-CPU data area base on the current CPU.
             #[inline]
             pub fn offset(&self) -> usize {
-                extern "Rust" {
+                unsafe extern "Rust" {
                     fn __start_percpu();
                 }
                 unsafe {
-                    &#inner_symbol_name as *const _ as usize - __start_percpu as usize
+                    &raw const #inner_symbol_name as usize - __start_percpu as usize
                 }
             }
 
@@ -153,7 +202,15 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             where
                 F: FnOnce(&mut #ty) -> T,
             {
-                f(unsafe { self.current_ref_mut_raw() })
+                // Provided by the `arch` crate, see its `preempt` module.
+                unsafe extern "Rust" {
+                    fn __percpu_preempt_disable();
+                    fn __percpu_preempt_enable();
+                }
+                unsafe { __percpu_preempt_disable() };
+                let ret = f(unsafe { self.current_ref_mut_raw() });
+                unsafe { __percpu_preempt_enable() };
+                ret
             }
 
             #read_write_methods
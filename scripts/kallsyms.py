@@ -0,0 +1,82 @@
+#!/usr/bin/env python3
+"""Embed the kernel symbol table into a linked kernel image.
+
+Fills the `kallsyms` section reserved by the `kallsyms` feature with the text
+symbols of the image, in the layout documented in `src/kallsyms.rs`:
+
+    scripts/kallsyms.py <kernel-elf> [--nm NM] [--objdump OBJDUMP] [--objcopy OBJCOPY]
+
+The section keeps its size and address, so the image need not be relinked.
+"""
+import argparse
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+MAGIC = b"KSYMTAB\0"
+SECTION = "kallsyms"
+HASH_SUFFIX = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def text_symbols(nm, elf):
+    out = subprocess.run(
+        [nm, "-n", "-C", "--defined-only", elf],
+        check=True, capture_output=True, text=True,
+    ).stdout
+    symbols = []
+    for line in out.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) != 3 or parts[1] not in "tTwW":
+            continue
+        symbols.append((int(parts[0], 16), HASH_SUFFIX.sub("", parts[2])))
+    return symbols
+
+
+def section_size(objdump, elf):
+    out = subprocess.run(
+        [objdump, "-h", elf], check=True, capture_output=True, text=True
+    ).stdout
+    for line in out.splitlines():
+        parts = line.split()
+        if len(parts) > 2 and parts[1] == SECTION:
+            return int(parts[2], 16)
+    sys.exit(f"{elf}: no {SECTION} section, build with the kallsyms feature")
+
+
+def build_blob(symbols):
+    strings = bytearray()
+    entries = bytearray()
+    for addr, name in symbols:
+        encoded = name.encode()
+        entries += struct.pack("<QII", addr, len(strings), len(encoded))
+        strings += encoded
+    header = MAGIC + struct.pack("<QQ", len(symbols), 24 + len(entries))
+    return header + entries + strings
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("elf")
+    parser.add_argument("--nm", default="rust-nm")
+    parser.add_argument("--objdump", default="rust-objdump")
+    parser.add_argument("--objcopy", default="rust-objcopy")
+    args = parser.parse_args()
+
+    size = section_size(args.objdump, args.elf)
+    blob = build_blob(text_symbols(args.nm, args.elf))
+    if len(blob) > size:
+        sys.exit(f"symbol table needs {len(blob)} bytes, raise KALLSYMS_SIZE above {size}")
+
+    with tempfile.NamedTemporaryFile() as f:
+        f.write(blob.ljust(size, b"\0"))
+        f.flush()
+        subprocess.run(
+            [args.objcopy, f"--update-section={SECTION}={f.name}", args.elf],
+            check=True,
+        )
+
+
+if __name__ == "__main__":
+    main()
@@ -1,6 +1,14 @@
 use crate::arch::config::mm::{PAGE_TABLE_LEVELS, PTE_INDEX_BITS, PTE_INDEX_MASK};
 #[cfg(feature = "debug")]
 use core::fmt::{Debug, Formatter};
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// Conversions between physical addresses and the kernel linear map.
+///
+/// Every architecture maps all of physical memory into the kernel half of the
+/// address space. Physical memory must only be dereferenced through that
+/// mapping, never through the raw physical address.
+pub use crate::arch::mm::addr::{phys_to_virt, virt_to_phys};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 #[repr(C)]
@@ -45,10 +53,10 @@ impl From<VirtPageNum> for usize {
 
 impl PhysAddr {
     pub fn get_ref<T>(&self) -> &'static T {
-        unsafe { (self.0 as *const T).as_ref().unwrap() }
+        unsafe { self.as_ptr::<T>().as_ref().unwrap() }
     }
     pub fn get_mut<T>(&self) -> &'static mut T {
-        unsafe { (self.0 as *mut T).as_mut().unwrap() }
+        unsafe { self.as_mut_ptr::<T>().as_mut().unwrap() }
     }
 }
 
@@ -65,14 +73,14 @@ impl PhysPageNum {
 }
 
 impl PhysAddr {
-    /// Converts the physical address to a raw pointer.
+    /// Converts the physical address to a raw pointer into the linear map.
     fn as_ptr<T>(&self) -> *const T {
-        self.0 as *const T
+        phys_to_virt(*self).0 as *const T
     }
 
-    /// Converts the physical address to a mutable raw pointer.
+    /// Converts the physical address to a mutable raw pointer into the linear map.
     fn as_mut_ptr<T>(&self) -> *mut T {
-        self.0 as *mut T
+        phys_to_virt(*self).0 as *mut T
     }
 
     /// Unsafe: Converts the physical address to a reference of type U.
@@ -81,7 +89,7 @@ impl PhysAddr {
     /// The caller must ensure that:
     /// 1. The physical address points to valid, initialized memory for type `U`.
     /// 2. The address has the correct alignment for type `U`.
-    /// 3. The memory is accessible (e.g., kernel direct-mapped region).
+    /// 3. The memory is covered by the kernel linear map.
     /// 4. The resulting reference does not outlive the validity of the underlying memory.
     ///    The `'static` lifetime is a promise that the caller must uphold regarding
     ///    the actual lifetime of the physical memory.
@@ -110,6 +118,261 @@ impl PhysAddr {
     }
 }
 
+/// Raw access shared by the address and page number types.
+///
+/// Unlike `From<usize>`, `from_raw` never validates or masks the value.
+pub trait RawAddr: Copy + Ord {
+    fn raw(self) -> usize;
+    fn from_raw(raw: usize) -> Self;
+}
+
+/// Marker for the page number types, whose ranges can be iterated.
+pub trait PageNum: RawAddr {}
+
+impl PageNum for VirtPageNum {}
+impl PageNum for PhysPageNum {}
+
+/// Offset arithmetic and alignment, in bytes for addresses and in pages for
+/// page numbers. Alignments must be powers of two.
+macro_rules! impl_addr_ops {
+    ($($ty:ident),+) => {
+        $(
+            impl RawAddr for $ty {
+                #[inline]
+                fn raw(self) -> usize {
+                    self.0
+                }
+
+                #[inline]
+                fn from_raw(raw: usize) -> Self {
+                    Self(raw)
+                }
+            }
+
+            impl $ty {
+                /// Round down to a multiple of `align`.
+                #[inline]
+                pub const fn align_down(self, align: usize) -> Self {
+                    Self(memory_addr::align_down(self.0, align))
+                }
+
+                /// Round up to a multiple of `align`.
+                #[inline]
+                pub const fn align_up(self, align: usize) -> Self {
+                    Self(memory_addr::align_up(self.0, align))
+                }
+
+                #[inline]
+                pub const fn is_aligned(self, align: usize) -> bool {
+                    memory_addr::is_aligned(self.0, align)
+                }
+
+                #[inline]
+                pub const fn checked_add(self, offset: usize) -> Option<Self> {
+                    match self.0.checked_add(offset) {
+                        Some(raw) => Some(Self(raw)),
+                        None => None,
+                    }
+                }
+
+                #[inline]
+                pub const fn checked_sub(self, offset: usize) -> Option<Self> {
+                    match self.0.checked_sub(offset) {
+                        Some(raw) => Some(Self(raw)),
+                        None => None,
+                    }
+                }
+            }
+
+            impl Add<usize> for $ty {
+                type Output = Self;
+
+                #[inline]
+                fn add(self, offset: usize) -> Self {
+                    Self(self.0 + offset)
+                }
+            }
+
+            impl AddAssign<usize> for $ty {
+                #[inline]
+                fn add_assign(&mut self, offset: usize) {
+                    self.0 += offset;
+                }
+            }
+
+            impl Sub<usize> for $ty {
+                type Output = Self;
+
+                #[inline]
+                fn sub(self, offset: usize) -> Self {
+                    Self(self.0 - offset)
+                }
+            }
+
+            impl SubAssign<usize> for $ty {
+                #[inline]
+                fn sub_assign(&mut self, offset: usize) {
+                    self.0 -= offset;
+                }
+            }
+
+            /// Distance between two addresses.
+            impl Sub for $ty {
+                type Output = usize;
+
+                #[inline]
+                fn sub(self, rhs: Self) -> usize {
+                    self.0 - rhs.0
+                }
+            }
+        )+
+    };
+}
+
+impl_addr_ops!(PhysAddr, VirtAddr, PhysPageNum, VirtPageNum);
+
+/// A half-open range `start..end` of addresses or page numbers.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct AddrRange<T> {
+    pub start: T,
+    pub end: T,
+}
+
+pub type PhysAddrRange = AddrRange<PhysAddr>;
+pub type VirtAddrRange = AddrRange<VirtAddr>;
+pub type PhysPageRange = AddrRange<PhysPageNum>;
+pub type VirtPageRange = AddrRange<VirtPageNum>;
+
+impl<T: RawAddr> AddrRange<T> {
+    /// Create the range `start..end`.
+    ///
+    /// # Panics
+    /// Panics if `start > end`.
+    #[inline]
+    pub fn new(start: T, end: T) -> Self {
+        assert!(start <= end, "invalid range");
+        Self { start, end }
+    }
+
+    /// Create the range of `size` units starting at `start`.
+    /// Returns `None` if the range would overflow.
+    #[inline]
+    pub fn from_size(start: T, size: usize) -> Option<Self> {
+        let end = start.raw().checked_add(size)?;
+        Some(Self {
+            start,
+            end: T::from_raw(end),
+        })
+    }
+
+    /// Number of bytes, or pages for page ranges, covered by the range.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.end.raw() - self.start.raw()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: T) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Whether `other` lies completely inside this range.
+    #[inline]
+    pub fn contains_range(&self, other: &Self) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    #[inline]
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// Returns the common part of both ranges, `None` if they don't overlap.
+    #[inline]
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        (start < end).then_some(Self { start, end })
+    }
+}
+
+impl<T: PageNum> AddrRange<T> {
+    /// Iterate over the page numbers in the range.
+    #[inline]
+    pub fn iter(&self) -> AddrRangeIter<T> {
+        AddrRangeIter {
+            current: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl<T: PageNum> IntoIterator for AddrRange<T> {
+    type Item = T;
+    type IntoIter = AddrRangeIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the page numbers of an [`AddrRange`].
+pub struct AddrRangeIter<T> {
+    current: T,
+    end: T,
+}
+
+impl<T: PageNum> Iterator for AddrRangeIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.current >= self.end {
+            return None;
+        }
+        let page = self.current;
+        self.current = T::from_raw(page.raw() + 1);
+        Some(page)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end.raw().saturating_sub(self.current.raw());
+        (len, Some(len))
+    }
+}
+
+impl<T: PageNum> ExactSizeIterator for AddrRangeIter<T> {}
+
+impl VirtAddrRange {
+    /// Returns the pages touched by this range.
+    pub fn pages(&self) -> VirtPageRange {
+        VirtPageRange::new(self.start.floor(), self.end.ceil())
+    }
+}
+
+impl PhysAddrRange {
+    /// Returns the frames touched by this range.
+    pub fn pages(&self) -> PhysPageRange {
+        PhysPageRange::new(self.start.floor(), self.end.ceil())
+    }
+}
+
+impl From<VirtPageRange> for VirtAddrRange {
+    fn from(v: VirtPageRange) -> Self {
+        Self::new(v.start.into(), v.end.into())
+    }
+}
+
+impl From<PhysPageRange> for PhysAddrRange {
+    fn from(v: PhysPageRange) -> Self {
+        Self::new(v.start.into(), v.end.into())
+    }
+}
+
 impl VirtPageNum {
     pub fn indices(&self) -> [usize; PAGE_TABLE_LEVELS] {
         let mut indices = [0; PAGE_TABLE_LEVELS];
@@ -150,3 +413,10 @@ impl Debug for PhysPageNum {
         f.write_fmt(format_args!("PPN:{:#x}", self.0))
     }
 }
+
+#[cfg(feature = "debug")]
+impl<T: Debug> Debug for AddrRange<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!("{:?}..{:?}", self.start, self.end))
+    }
+}
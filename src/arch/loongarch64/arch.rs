@@ -1,17 +1,45 @@
 use crate::{
-    arch::config::mm::VIRT_ADDR_START,
-    {DEVICE_TREE_BLOB, MEMORY_AREAS},
+    addr::{PhysAddr, phys_to_virt},
+    memory::init_memory_regions,
+    numa::init_numa,
+    {DEVICE_TREE_BLOB, DTB_PTR},
 };
 
-use alloc::vec;
+use super::{eiointc, pch_pic};
 use alloc::vec::Vec;
+use core::slice;
+use fdt::Fdt;
 
+/// Bring up the heap, memory map, NUMA topology and interrupt controllers
+/// from the device tree. The memory map needs the `_skernel` and `_ekernel`
+/// symbols from the kernel linker script.
 pub fn arch_init() {
-    DEVICE_TREE_BLOB.init(Vec::new());
-    MEMORY_AREAS.init(vec![(VIRT_ADDR_START | 0x9000_0000, 0x2000_0000)]);
+    #[cfg(feature = "heap")]
+    crate::heap::init_heap();
+    let mut buffer = Vec::new();
+    if let Some(&dtb_ptr) = DTB_PTR.try_get() {
+        let dtb_ptr = phys_to_virt(PhysAddr(dtb_ptr)).0 as *const u8;
+        if let Ok(fdt) = unsafe { Fdt::from_ptr(dtb_ptr) } {
+            unsafe {
+                buffer.extend_from_slice(slice::from_raw_parts(dtb_ptr, fdt.total_size()));
+            }
+        }
+    }
+    DEVICE_TREE_BLOB.init(buffer);
+    let fdt = Fdt::new(&DEVICE_TREE_BLOB).ok();
+    init_memory_regions(fdt.as_ref(), 0x9000_0000..0xb000_0000);
+    init_numa(fdt.as_ref());
+    eiointc::init(fdt.as_ref());
+    pch_pic::init(fdt.as_ref());
 }
 
 #[inline]
 pub fn hart_id() -> usize {
     loongArch64::register::cpuid::read().core_id()
 }
+
+/// Point the current CPU at the per-CPU data area at `base`.
+#[inline]
+pub unsafe fn set_percpu_base(base: usize) {
+    unsafe { core::arch::asm!("move $r21, {}", in(reg) base) };
+}
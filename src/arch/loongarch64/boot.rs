@@ -69,12 +69,16 @@ unsafe extern "C" fn _start() -> ! {
 // Main entry point after initialization
 #[unsafe(no_mangle)]
 pub fn rust_main(hart_id: usize) -> ! {
+    crate::percpu::init_percpu(hart_id);
+    super::arch::arch_init();
     // Placeholder
     loop {}
 }
 
 #[unsafe(no_mangle)]
-pub fn rust_secondary_main(hart_id: usize) -> ! {
+pub fn rust_secondary_main(_hart_id: usize) -> ! {
+    // Secondary cores jump here from the mailbox without their ID.
+    crate::percpu::init_percpu(super::arch::hart_id());
     // Placeholder
     loop {}
 }
@@ -1,2 +1,3 @@
 pub const KERNEL_TRAPFRAME_SIZE: usize = 13 * 8;
+/// `size_of::<TrapRegs>()`, the frame kernel mode traps save on the stack.
 pub const USER_TRAPFRAME_SIZE: usize = 34 * 8;
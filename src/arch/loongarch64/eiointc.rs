@@ -0,0 +1,143 @@
+//! Extended I/O Interrupt Controller.
+//!
+//! The EIOINTC collects 256 vectors and raises each on one core. Vector `n`
+//! is reported as IRQ `n + 1`, keeping 0 free for spurious interrupts. It is programmed through IOCSR and discovered
+//! from the device tree node compatible with `loongson,ls2k2000-eiointc`,
+//! whose `interrupts` property names the CPU interrupt line it drives.
+//!
+//! Vectors start disabled and routed to the boot core. Only the first
+//! `MAX_ROUTE_CORES` cores can take vectors, the others have theirs routed to
+//! core 0.
+use fdt::Fdt;
+use loongArch64::iocsr::{iocsr_read_d, iocsr_write_b, iocsr_write_d};
+
+use super::arch::hart_id;
+use crate::utils::{MutexNoIrq, OnceCell};
+
+/// Number of vectors.
+pub const NR_VECTORS: usize = 256;
+
+const IOCSR_MISC_FUNC: usize = 0x420;
+const MISC_FUNC_EXT_IOI_EN: u64 = 1 << 48;
+
+const EIOINTC_IPMAP: usize = 0x14c0;
+const EIOINTC_ENABLE: usize = 0x1600;
+const EIOINTC_BOUNCE: usize = 0x1680;
+const EIOINTC_ISR: usize = 0x1800;
+const EIOINTC_COREMAP: usize = 0x1c00;
+
+/// Cores a vector can be routed to, one bit each in the core map.
+const MAX_ROUTE_CORES: usize = 4;
+
+/// The `ESTAT.IS` bit of `HWI0`, the first hardware interrupt line.
+const HWI0: usize = 2;
+
+/// `HWI1`, which the EIOINTC drives unless the device tree says otherwise.
+const DEFAULT_PARENT_IRQ: usize = 3;
+
+struct Eiointc {
+    /// Serializes read-modify-write of the enable bits.
+    enable_lock: MutexNoIrq,
+}
+
+static EIOINTC: OnceCell<Eiointc> = OnceCell::new();
+
+/// Discover the EIOINTC from `fdt` and reset it. Without one, external
+/// interrupts are never claimed.
+pub(crate) fn init(fdt: Option<&Fdt>) {
+    let Some(node) = fdt.and_then(|fdt| fdt.find_compatible(&["loongson,ls2k2000-eiointc"])) else {
+        log::warn!("EIOINTC not found in the device tree");
+        return;
+    };
+    let parent_irq = node
+        .property("interrupts")
+        .and_then(|p| p.as_usize())
+        .filter(|irq| (HWI0..HWI0 + 8).contains(irq))
+        .unwrap_or(DEFAULT_PARENT_IRQ);
+
+    iocsr_write_d(
+        IOCSR_MISC_FUNC,
+        iocsr_read_d(IOCSR_MISC_FUNC) | MISC_FUNC_EXT_IOI_EN,
+    );
+    // Every group of 32 vectors raises the parent line, `HWI0` being bit 0.
+    let line = 1u8 << (parent_irq - HWI0);
+    for group in 0..NR_VECTORS / 32 {
+        iocsr_write_b(EIOINTC_IPMAP + group, line);
+    }
+    let boot = 1u8 << route_core(hart_id());
+    for vector in 0..NR_VECTORS {
+        iocsr_write_b(EIOINTC_COREMAP + vector, boot);
+    }
+    for word in 0..NR_VECTORS / 64 {
+        iocsr_write_d(EIOINTC_ENABLE + word * 8, 0);
+        iocsr_write_d(EIOINTC_BOUNCE + word * 8, 0);
+    }
+    log::info!("EIOINTC on interrupt line {parent_irq}");
+    EIOINTC.init(Eiointc {
+        enable_lock: MutexNoIrq::new(()),
+    });
+}
+
+/// Returns `core` if vectors can be routed to it, or else core 0, with a
+/// warning.
+pub fn route_core(core: usize) -> usize {
+    if core < MAX_ROUTE_CORES {
+        core
+    } else {
+        log::warn!("EIOINTC cannot route to core {core}, using core 0");
+        0
+    }
+}
+
+fn set_enabled(vector: usize, enabled: bool) -> bool {
+    let Some(eiointc) = EIOINTC.try_get().filter(|_| vector < NR_VECTORS) else {
+        return false;
+    };
+    let offset = EIOINTC_ENABLE + vector / 64 * 8;
+    let mask = 1 << (vector % 64);
+    let _guard = eiointc.enable_lock.lock();
+    let bits = iocsr_read_d(offset);
+    iocsr_write_d(offset, if enabled { bits | mask } else { bits & !mask });
+    true
+}
+
+/// Enable `vector`.
+pub fn enable(vector: usize) -> bool {
+    set_enabled(vector, true)
+}
+
+/// Disable `vector`.
+pub fn disable(vector: usize) -> bool {
+    set_enabled(vector, false)
+}
+
+/// Route `vector` to `core`. Returns `false` if the core map cannot name
+/// `core`.
+pub fn set_affinity(vector: usize, core: usize) -> bool {
+    if EIOINTC.try_get().is_none() || vector >= NR_VECTORS || core >= MAX_ROUTE_CORES {
+        return false;
+    }
+    iocsr_write_b(EIOINTC_COREMAP + vector, 1 << core);
+    true
+}
+
+/// Whether `vector` is pending on the current core.
+pub fn is_pending(vector: usize) -> bool {
+    EIOINTC.try_get().is_some()
+        && vector < NR_VECTORS
+        && iocsr_read_d(EIOINTC_ISR + vector / 64 * 8) & (1 << (vector % 64)) != 0
+}
+
+/// Claim the lowest pending vector of the current core, if any, clearing its
+/// status bit.
+pub fn claim() -> Option<usize> {
+    EIOINTC.try_get()?;
+    (0..NR_VECTORS / 64).find_map(|word| {
+        let pending = iocsr_read_d(EIOINTC_ISR + word * 8);
+        (pending != 0).then(|| {
+            let bit = pending.trailing_zeros() as usize;
+            iocsr_write_d(EIOINTC_ISR + word * 8, 1 << bit);
+            word * 64 + bit
+        })
+    })
+}
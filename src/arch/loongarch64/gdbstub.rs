@@ -0,0 +1,123 @@
+//! Registers, breakpoints and step decoding of the GDB stub.
+use super::mm::pagetable::Loongarch64PTImpl;
+use super::trapframe::TrapRegs;
+use crate::addr::{VirtAddr, phys_to_virt};
+use crate::gdbstub::read_mem;
+use crate::pagetable::PageTable;
+use loongArch64::register::{badv, pgdh, pgdl};
+
+/// `r0` to `r31`, `orig_a0`, `pc` and `badv`, in the order of the GDB
+/// register numbers.
+pub(crate) const NUM_REGS: usize = 35;
+
+pub(crate) const STEP_BREAK_LEN: usize = 4;
+
+/// `break 0`. Any code raises the same trap.
+const BREAK: u32 = 0x002a_0000;
+const BREAK_CODE_MASK: u32 = 0x7fff;
+
+/// Extract `len` bits of `insn` starting at bit `lo`.
+#[inline]
+const fn bits(insn: u32, lo: u32, len: u32) -> usize {
+    ((insn >> lo) & ((1 << len) - 1)) as usize
+}
+
+/// Sign-extend the low `len` bits of `value`.
+#[inline]
+const fn sext(value: usize, len: u32) -> usize {
+    (((value << (64 - len)) as isize) >> (64 - len)) as usize
+}
+
+/// Returns GDB register `n`.
+pub(crate) fn read_reg(tf: &TrapRegs, n: usize) -> Option<usize> {
+    match n {
+        0..32 => Some(tf.gpr(n)),
+        // Traps do not save `orig_a0`, only system calls do.
+        32 => Some(0),
+        33 => Some(tf.era),
+        34 => Some(badv::read().vaddr()),
+        _ => None,
+    }
+}
+
+/// Set GDB register `n`. Writes to `r0`, `orig_a0` and `badv` are ignored.
+pub(crate) fn write_reg(tf: &mut TrapRegs, n: usize, value: usize) -> bool {
+    match n {
+        0..32 => tf.set_gpr(n, value),
+        32 | 34 => {}
+        33 => tf.era = value,
+        _ => return false,
+    }
+    true
+}
+
+/// Returns the breakpoint instruction for a `Z0` kind, which is its length.
+pub(crate) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    const BREAK_BYTES: [u8; 4] = BREAK.to_le_bytes();
+    (kind == 4).then_some(&BREAK_BYTES)
+}
+
+/// Returns the length of the breakpoint instruction at `pc`, if there is one.
+pub(crate) fn break_len(pc: usize) -> Option<usize> {
+    (fetch(pc)? & !BREAK_CODE_MASK == BREAK).then_some(4)
+}
+
+/// The trap handler has already advanced the PC past the breakpoint.
+pub(crate) fn breakpoint_pc(tf: &TrapRegs) -> usize {
+    tf.era - 4
+}
+
+fn fetch(pc: usize) -> Option<u32> {
+    let mut insn = [0u8; 4];
+    read_mem(pc, &mut insn).then(|| u32::from_le_bytes(insn))
+}
+
+/// Returns the addresses the instruction at the PC of `tf` may continue at.
+pub(crate) fn step_targets(tf: &TrapRegs) -> Option<[Option<usize>; 2]> {
+    let pc = tf.era;
+    let insn = fetch(pc)?;
+    let next = pc + 4;
+    let offs16 = sext(bits(insn, 10, 16), 16) << 2;
+    let offs21 = sext((bits(insn, 0, 5) << 16) | bits(insn, 10, 16), 21) << 2;
+    let offs26 = sext((bits(insn, 0, 10) << 16) | bits(insn, 10, 16), 26) << 2;
+    let targets = match insn >> 26 {
+        // beqz, bnez, bceqz / bcnez
+        0x10..=0x12 => [Some(pc.wrapping_add(offs21)), Some(next)],
+        // jirl
+        0x13 => [Some(tf.gpr(bits(insn, 5, 5)).wrapping_add(offs16)), None],
+        // b, bl
+        0x14 | 0x15 => [Some(pc.wrapping_add(offs26)), None],
+        // beq, bne, blt, bge, bltu, bgeu
+        0x16..=0x1b => [Some(pc.wrapping_add(offs16)), Some(next)],
+        _ => [Some(next), None],
+    };
+    Some(targets)
+}
+
+/// Returns the address of `va` in the kernel linear map. Addresses in the
+/// direct mapping windows are used as they are.
+pub(crate) fn translate(va: usize) -> Option<usize> {
+    let va = VirtAddr(va);
+    if va.is_direct_mapped() {
+        return Some(va.0);
+    }
+    let root = if va.is_user() {
+        pgdl::read().base()
+    } else {
+        pgdh::read().base()
+    };
+    let page_table = PageTable::<Loongarch64PTImpl>::from_token(root);
+    page_table.translate_va(va).map(|pa| phys_to_virt(pa).0)
+}
+
+/// Make written instructions visible to instruction fetch on this core.
+#[inline]
+pub(crate) fn flush_icache() {
+    unsafe { core::arch::asm!("ibar 0") };
+}
+
+/// Raise a breakpoint trap.
+#[inline(always)]
+pub(crate) fn breakpoint() {
+    unsafe { core::arch::asm!("break 0") };
+}
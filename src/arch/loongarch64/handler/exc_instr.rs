@@ -1,5 +0,0 @@
-use super::TrapFrame;
-
-pub fn handler(tf: &mut TrapFrame, token: usize) {
-    todo!();
-}
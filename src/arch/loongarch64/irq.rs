@@ -1,6 +1,12 @@
+use loongArch64::consts::{
+    LOONGARCH_IOCSR_IPI_CLEAR, LOONGARCH_IOCSR_IPI_EN, LOONGARCH_IOCSR_IPI_STATUS,
+};
+use loongArch64::iocsr::{iocsr_read_w, iocsr_write_w};
 use loongArch64::register::ecfg::LineBasedInterrupt;
 use loongArch64::register::{crmd, ecfg, eentry};
 
+use super::{arch::hart_id, eiointc, pch_pic};
+
 fn hardware_interrupts_bits() -> LineBasedInterrupt {
     LineBasedInterrupt::HWI0
         | LineBasedInterrupt::HWI1
@@ -86,6 +92,36 @@ impl Irq {
         ecfg::set_lie(new_lie);
     }
 
+    /// Enable the IPI line and every IPI vector of the current core.
+    pub fn enable_ipi() {
+        iocsr_write_w(LOONGARCH_IOCSR_IPI_EN, u32::MAX);
+        let cur_lie = ecfg::read().lie();
+        ecfg::set_lie(cur_lie | LineBasedInterrupt::IPI);
+    }
+
+    /// Send an IPI, on vector 0, to the cores set in `harts`.
+    pub fn send_ipi(harts: usize) -> bool {
+        for cpu in (0..usize::BITS as usize).filter(|cpu| harts & (1 << cpu) != 0) {
+            loongArch64::ipi::send_ipi_single(cpu, 1);
+        }
+        true
+    }
+
+    /// Clear the pending IPI vectors of the current core.
+    pub fn clear_ipi() {
+        iocsr_write_w(
+            LOONGARCH_IOCSR_IPI_CLEAR,
+            iocsr_read_w(LOONGARCH_IOCSR_IPI_STATUS),
+        );
+    }
+
+    /// Wait for an interrupt, which wakes the core up even if masked.
+    pub fn wait_for_interrupt() {
+        unsafe {
+            core::arch::asm!("idle 0");
+        }
+    }
+
     // similar to riscv's eternal interrupt
     pub fn enable_hardware_interrupt() {
         let cur_lie = ecfg::read().lie();
@@ -100,18 +136,76 @@ impl Irq {
     }
 }
 
+/// An EIOINTC vector.
 #[derive(Debug, Clone, Copy)]
 pub struct IRQVector(usize);
 
 impl IRQVector {
-    /// Get the irq number in this vector
+    /// Claim the pending EIOINTC vector of the current core, if any.
+    pub fn claim() -> Option<Self> {
+        eiointc::claim().map(Self)
+    }
+
+    /// Get the irq number in this vector, which is the vector plus one
     #[inline]
     pub fn irq_num(&self) -> usize {
-        unimplemented!()
+        self.0 + 1
     }
 
     /// Acknowledge the irq
     pub fn irq_ack(&self) {
-        unimplemented!()
+        pch_pic::ack(self.0);
+    }
+
+    /// Route `irq` to the current core, or core 0 if it cannot be routed
+    /// there, and unmask it, in the PCH-PIC too if it raises the vector.
+    pub fn enable(irq: usize) -> bool {
+        let Some(vector) = irq.checked_sub(1) else {
+            return false;
+        };
+        if !eiointc::set_affinity(vector, eiointc::route_core(hart_id())) || !eiointc::enable(vector) {
+            return false;
+        }
+        pch_pic::unmask(vector);
+        true
+    }
+
+    /// Mask `irq`.
+    pub fn disable(irq: usize) -> bool {
+        let Some(vector) = irq.checked_sub(1) else {
+            return false;
+        };
+        pch_pic::mask(vector);
+        eiointc::disable(vector)
+    }
+
+    /// Route `irq` to `core`.
+    pub fn set_affinity(irq: usize, core: usize) -> bool {
+        irq.checked_sub(1).is_some_and(|vector| eiointc::set_affinity(vector, core))
+    }
+
+    /// The EIOINTC has no priorities.
+    pub fn set_priority(_irq: usize, _priority: u32) -> bool {
+        false
+    }
+
+    /// The EIOINTC has no priorities.
+    pub fn priority(_irq: usize) -> Option<u32> {
+        None
+    }
+
+    /// The EIOINTC has no priorities.
+    pub fn set_threshold(_core: usize, _threshold: u32) -> bool {
+        false
+    }
+
+    /// Whether `irq` is pending on the current core.
+    pub fn is_pending(irq: usize) -> bool {
+        irq.checked_sub(1).is_some_and(eiointc::is_pending)
+    }
+
+    /// Set the trigger of the PCH-PIC input raising `irq`.
+    pub fn set_trigger(irq: usize, edge: bool, active_low: bool) -> bool {
+        irq.checked_sub(1).is_some_and(|vector| pch_pic::set_trigger(vector, edge, active_low))
     }
 }
@@ -0,0 +1,29 @@
+//! Load and store decoding for the misaligned access emulator.
+use super::trapframe::TrapRegs;
+use crate::misaligned::insn::loongarch64::decode_insn;
+use crate::misaligned::{Access, read_mem};
+
+/// Decode the load or store at the PC of `tf`.
+pub(crate) fn decode(tf: &TrapRegs) -> Option<Access> {
+    let mut insn = [0u8; 4];
+    if !read_mem(tf, tf.pc(), &mut insn) {
+        return None;
+    }
+    decode_insn(u32::from_le_bytes(insn), |n| tf.gpr(n))
+}
+
+#[cfg(feature = "fp")]
+crate::misaligned::fpr_access!(
+    0 => "$f0", 1 => "$f1", 2 => "$f2", 3 => "$f3", 4 => "$f4", 5 => "$f5", 6 => "$f6",
+    7 => "$f7", 8 => "$f8", 9 => "$f9", 10 => "$f10", 11 => "$f11", 12 => "$f12", 13 => "$f13",
+    14 => "$f14", 15 => "$f15", 16 => "$f16", 17 => "$f17", 18 => "$f18", 19 => "$f19",
+    20 => "$f20", 21 => "$f21", 22 => "$f22", 23 => "$f23", 24 => "$f24", 25 => "$f25",
+    26 => "$f26", 27 => "$f27", 28 => "$f28", 29 => "$f29", 30 => "$f30", 31 => "$f31",
+);
+
+/// Load `value` of `width` bytes into floating-point register `n`. The upper
+/// half of a single-precision load is left zero.
+#[cfg(feature = "fp")]
+pub(crate) fn set_fpr(_tf: &mut TrapRegs, n: usize, _width: usize, value: u64) {
+    write_fpr(n, value);
+}
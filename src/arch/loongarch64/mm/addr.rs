@@ -1,11 +1,30 @@
 //! Address space for LoongArch64
 //!
 //! This module implements basic address space operations for LoongArch64.
+//!
+//! The kernel linear map is the cached direct mapping window starting at
+//! `VIRT_ADDR_START`. The uncached window at `PHYS_ADDR_START` maps the same
+//! physical memory, so both translate back to physical addresses.
 use crate::addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use crate::arch::loongarch64::config::mm::{
-    PA_MASK, PAGE_MASK, PAGE_SIZE, PAGE_SIZE_BITS, PPN_MASK, VA_MASK, VPN_MASK,
+    PA_LEN, PA_MASK, PAGE_MASK, PAGE_SIZE, PAGE_SIZE_BITS, PHYS_ADDR_START, PPN_MASK, VA_LEN,
+    VA_MASK, VIRT_ADDR_START, VPN_MASK,
 };
 
+/// Returns the address of `pa` in the kernel linear map.
+#[inline]
+pub const fn phys_to_virt(pa: PhysAddr) -> VirtAddr {
+    VirtAddr(pa.0 | VIRT_ADDR_START)
+}
+
+/// Returns the physical address behind `va`, which must lie in a direct
+/// mapping window.
+#[inline]
+pub const fn virt_to_phys(va: VirtAddr) -> PhysAddr {
+    debug_assert!(va.is_direct_mapped(), "address is not in the linear map");
+    PhysAddr(va.0 & PA_MASK)
+}
+
 // PhysAddr implementations
 impl PhysAddr {
     #[inline]
@@ -80,6 +99,34 @@ impl VirtAddr {
     pub fn floor(self) -> VirtPageNum {
         VirtPageNum(self.0 / PAGE_SIZE)
     }
+
+    /// Whether the address lies in one of the direct mapping windows.
+    #[inline]
+    pub const fn is_direct_mapped(&self) -> bool {
+        let window = self.0 & !((1 << PA_LEN) - 1);
+        window == VIRT_ADDR_START || window == PHYS_ADDR_START
+    }
+
+    /// Paged addresses must have bits 63..48 equal to bit 47. The direct
+    /// mapping windows are canonical as well.
+    #[inline]
+    pub const fn is_canonical(&self) -> bool {
+        let high = self.0 as isize >> (VA_LEN - 1);
+        high == 0 || high == -1 || self.is_direct_mapped()
+    }
+
+    /// Whether the address is in the lower half, translated through `PGDL`.
+    #[inline]
+    pub const fn is_user(&self) -> bool {
+        self.0 as isize >> (VA_LEN - 1) == 0
+    }
+
+    /// Whether the address is in a direct mapping window or the upper half,
+    /// translated through `PGDH`.
+    #[inline]
+    pub const fn is_kernel(&self) -> bool {
+        self.0 as isize >> (VA_LEN - 1) == -1 || self.is_direct_mapped()
+    }
 }
 
 impl From<usize> for VirtAddr {
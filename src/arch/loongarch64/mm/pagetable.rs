@@ -1,4 +1,4 @@
-use crate::addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use crate::addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, phys_to_virt};
 use crate::arch::loongarch64::config::mm::{
     PAGE_SIZE, PAGE_SIZE_BITS, PAGE_TABLE_LEVELS, PPN_MASK, PPN_OFFSET_IN_PTE,
 };
@@ -146,8 +146,8 @@ impl PTOps for Loongarch64PTImpl {
     fn get_pte_array(ppn: PhysPageNum) -> &'static mut [PageTableEntry] {
         // Assuming PAGE_SIZE is divisible by size_of::<PageTableEntry>()
         const PTES_PER_PAGE: usize = PAGE_SIZE / core::mem::size_of::<PageTableEntry>();
-        let pa = Self::ppn_to_pa(ppn).0;
-        unsafe { core::slice::from_raw_parts_mut(pa as *mut PageTableEntry, PTES_PER_PAGE) }
+        let va = phys_to_virt(Self::ppn_to_pa(ppn)).0;
+        unsafe { core::slice::from_raw_parts_mut(va as *mut PageTableEntry, PTES_PER_PAGE) }
     }
 
     fn va_to_vpn(va: VirtAddr) -> VirtPageNum {
@@ -1,6 +1,7 @@
-use crate::addr::VirtAddr;
+use crate::addr::{VirtAddr, VirtAddrRange};
 use crate::arch::loongarch64::config::mm::PAGE_SIZE_BITS;
-use crate::tlb::{TLBOperation, Tlb};
+use crate::cpumask::CpuMask;
+use crate::tlb::{FLUSH_ALL_PAGES, TLBOperation, Tlb};
 use loongArch64::register::{stlbps, tlbidx, tlbrehi, tlbrentry};
 
 /// TLB operations
@@ -18,6 +19,31 @@ impl TLBOperation for Tlb {
             core::arch::asm!("dbar 0; invtlb 0x00, $r0, $r0");
         }
     }
+
+    fn flush_range(asid: usize, range: VirtAddrRange) {
+        let pages = range.pages();
+        unsafe {
+            core::arch::asm!("dbar 0");
+            if pages.size() > FLUSH_ALL_PAGES {
+                // Non-global entries of the ASID.
+                core::arch::asm!("invtlb 0x04, {asid}, $r0", asid = in(reg) asid);
+            } else {
+                for vpn in pages {
+                    // Non-global entries of the ASID mapping the address.
+                    core::arch::asm!(
+                        "invtlb 0x05, {asid}, {va}",
+                        asid = in(reg) asid,
+                        va = in(reg) VirtAddr::from(vpn).0,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// LoongArch has no remote TLB flush, other cores flush their own on an IPI.
+pub(crate) fn remote_flush_range(_cpus: CpuMask, _asid: usize, _range: VirtAddrRange) -> bool {
+    false
 }
 
 pub fn tlb_init() {
@@ -3,10 +3,16 @@ pub mod boot;
 pub mod config;
 pub mod console;
 pub mod context;
-pub mod handler;
+pub mod eiointc;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
 pub mod irq;
 pub mod macros;
+#[cfg(feature = "misaligned")]
+pub mod misaligned;
 pub mod mm;
+pub mod pch_pic;
+pub mod signal;
 pub mod time;
 pub mod trap;
 pub mod trapframe;
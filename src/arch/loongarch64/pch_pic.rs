@@ -0,0 +1,161 @@
+//! Platform Controller Hub Programmable Interrupt Controller.
+//!
+//! The PCH-PIC takes the interrupt lines of the devices and forwards input
+//! `n` to EIOINTC vector `base + n`, where `base` is the
+//! `loongson,pic-base-vec` property of its device tree node, compatible with
+//! `loongson,pch-pic-1.0`. Device tree `interrupts` of its children are
+//! input numbers.
+//!
+//! Inputs start masked and level-triggered, active high.
+use fdt::Fdt;
+
+use super::config::mm::PHYS_ADDR_START;
+use crate::utils::{MutexNoIrq, OnceCell};
+
+const PCH_PIC_ID: usize = 0x00;
+const PCH_PIC_MASK: usize = 0x20;
+const PCH_PIC_HTMSI_EN: usize = 0x40;
+const PCH_PIC_EDGE: usize = 0x60;
+const PCH_PIC_CLEAR: usize = 0x80;
+const PCH_PIC_ROUTE_ENTRY: usize = 0x100;
+const PCH_PIC_HTMSI_VEC: usize = 0x200;
+const PCH_PIC_POLARITY: usize = 0x3e0;
+
+/// Most inputs a PCH-PIC has.
+const MAX_INPUTS: usize = 64;
+
+struct PchPic {
+    base: usize,
+    /// EIOINTC vector of input 0.
+    vec_base: usize,
+    inputs: usize,
+    /// Serializes read-modify-write of the mask and trigger bits.
+    lock: MutexNoIrq,
+}
+
+static PCH_PIC: OnceCell<PchPic> = OnceCell::new();
+
+impl PchPic {
+    fn reg<T>(&self, offset: usize) -> *mut T {
+        (self.base + offset) as *mut T
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { self.reg::<u64>(offset).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { self.reg::<u64>(offset).write_volatile(value) }
+    }
+
+    fn write_byte(&self, offset: usize, value: u8) {
+        unsafe { self.reg::<u8>(offset).write_volatile(value) }
+    }
+
+    /// Set or clear the bit of `input` in the 64-bit register at `offset`.
+    fn update(&self, offset: usize, input: usize, set: bool) {
+        let _guard = self.lock.lock();
+        let bits = self.read(offset);
+        let mask = 1 << input;
+        self.write(offset, if set { bits | mask } else { bits & !mask });
+    }
+
+    /// Returns the input raising `vector`.
+    fn input(&self, vector: usize) -> Option<usize> {
+        vector
+            .checked_sub(self.vec_base)
+            .filter(|&input| input < self.inputs)
+    }
+}
+
+/// Discover the PCH-PIC from `fdt` and reset it.
+pub(crate) fn init(fdt: Option<&Fdt>) {
+    let Some(node) = fdt.and_then(|fdt| fdt.find_compatible(&["loongson,pch-pic-1.0"])) else {
+        log::warn!("PCH-PIC not found in the device tree");
+        return;
+    };
+    let Some(reg) = node.reg().and_then(|mut reg| reg.next()) else {
+        return;
+    };
+    let vec_base = node
+        .property("loongson,pic-base-vec")
+        .and_then(|p| p.as_usize())
+        .unwrap_or(0);
+
+    let mut pic = PchPic {
+        // Registers are accessed through the uncached window.
+        base: reg.starting_address as usize | PHYS_ADDR_START,
+        vec_base,
+        inputs: MAX_INPUTS,
+        lock: MutexNoIrq::new(()),
+    };
+    pic.inputs = (((pic.read(PCH_PIC_ID) >> 48) & 0xff) as usize + 1).min(MAX_INPUTS);
+    pic.write(PCH_PIC_MASK, u64::MAX);
+    for input in 0..pic.inputs {
+        // Every input goes out as a HyperTransport message to its vector.
+        pic.write_byte(PCH_PIC_ROUTE_ENTRY + input, 1);
+        pic.write_byte(PCH_PIC_HTMSI_VEC + input, (vec_base + input) as u8);
+    }
+    pic.write(PCH_PIC_HTMSI_EN, u64::MAX);
+    pic.write(PCH_PIC_EDGE, 0);
+    pic.write(PCH_PIC_POLARITY, 0);
+    pic.write(PCH_PIC_CLEAR, u64::MAX);
+    log::info!(
+        "PCH-PIC at {:#x}, {} inputs from vector {}",
+        reg.starting_address as usize,
+        pic.inputs,
+        vec_base
+    );
+    PCH_PIC.init(pic);
+}
+
+/// Unmask the input raising `vector`.
+pub fn unmask(vector: usize) -> bool {
+    let Some(pic) = PCH_PIC.try_get() else {
+        return false;
+    };
+    let Some(input) = pic.input(vector) else {
+        return false;
+    };
+    pic.update(PCH_PIC_MASK, input, false);
+    true
+}
+
+/// Mask the input raising `vector`.
+pub fn mask(vector: usize) -> bool {
+    let Some(pic) = PCH_PIC.try_get() else {
+        return false;
+    };
+    let Some(input) = pic.input(vector) else {
+        return false;
+    };
+    pic.update(PCH_PIC_MASK, input, true);
+    true
+}
+
+/// Set whether the input raising `vector` is edge-triggered rather than
+/// level-triggered, and whether it is active low rather than high.
+pub fn set_trigger(vector: usize, edge: bool, active_low: bool) -> bool {
+    let Some(pic) = PCH_PIC.try_get() else {
+        return false;
+    };
+    let Some(input) = pic.input(vector) else {
+        return false;
+    };
+    pic.update(PCH_PIC_EDGE, input, edge);
+    pic.update(PCH_PIC_POLARITY, input, active_low);
+    true
+}
+
+/// Acknowledge `vector` after handling it. Edge-triggered inputs latch until
+/// cleared.
+pub fn ack(vector: usize) {
+    let Some(pic) = PCH_PIC.try_get() else {
+        return;
+    };
+    if let Some(input) = pic.input(vector) {
+        if pic.read(PCH_PIC_EDGE) & (1 << input) != 0 {
+            pic.write(PCH_PIC_CLEAR, 1 << input);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Signal frames, laid out as the Linux loongarch64 `sigcontext` with its
+//! extended contexts.
+#[cfg(all(feature = "fp", not(feature = "lsx")))]
+use super::trapframe::FloatingPointRegisters;
+use super::trapframe::TrapFrame;
+#[cfg(feature = "lsx")]
+use super::trapframe::VR_WORDS;
+use crate::addr::VirtAddr;
+use crate::signal::{RtSigFrame, SignalDelivery, pop_frame, push_frame};
+
+/// `sc_flags`: the floating-point context is present.
+#[cfg(feature = "fp")]
+const SC_USED_FP: u32 = 1;
+/// Magic of the floating-point extended context.
+#[cfg(all(feature = "fp", not(feature = "lsx")))]
+const FPU_CTX_MAGIC: u32 = 0x4650_5501;
+/// Magic of the LSX extended context.
+#[cfg(all(feature = "lsx", not(feature = "lasx")))]
+const VECTOR_CTX_MAGIC: u32 = 0x5358_0001;
+/// Magic of the LASX extended context.
+#[cfg(feature = "lasx")]
+const VECTOR_CTX_MAGIC: u32 = 0x4153_5801;
+
+/// `struct sctx_info`, the header of every extended context. The list ends
+/// with a zero header.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SctxInfo {
+    magic: u32,
+    size: u32,
+    padding: u64,
+}
+
+impl SctxInfo {
+    const END: Self = Self {
+        magic: 0,
+        size: 0,
+        padding: 0,
+    };
+}
+
+/// `struct fpu_context` with its header.
+#[cfg(all(feature = "fp", not(feature = "lsx")))]
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct FpuContext {
+    info: SctxInfo,
+    regs: [u64; 32],
+    /// `fcc0` to `fcc7`, one per byte.
+    fcc: u64,
+    fcsr: u32,
+}
+
+#[cfg(all(feature = "fp", not(feature = "lsx")))]
+impl FpuContext {
+    /// The saved floating-point state of a context.
+    fn new(fr: &FloatingPointRegisters) -> Self {
+        let mut ctx = Self {
+            info: SctxInfo {
+                magic: FPU_CTX_MAGIC,
+                size: size_of::<Self>() as u32,
+                padding: 0,
+            },
+            regs: [0; 32],
+            fcc: fr.fcc,
+            fcsr: fr.fcsr,
+        };
+        for (dst, src) in ctx.regs.iter_mut().zip(fr.as_array()) {
+            *dst = src.to_bits();
+        }
+        ctx
+    }
+}
+
+/// `struct lsx_context`, or `struct lasx_context` with LASX, with its
+/// header. It holds the floating-point registers as the low 64 bits of the
+/// vector registers.
+#[cfg(feature = "lsx")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct VectorContext {
+    info: SctxInfo,
+    regs: [[u64; VR_WORDS]; 32],
+    /// `fcc0` to `fcc7`, one per byte.
+    fcc: u64,
+    fcsr: u32,
+}
+
+#[cfg(feature = "lsx")]
+impl VectorContext {
+    /// The saved vector state of a context, with the floating-point
+    /// registers, which may be newer, as the low halves.
+    fn new(tf: &TrapFrame) -> Self {
+        let mut ctx = Self {
+            info: SctxInfo {
+                magic: VECTOR_CTX_MAGIC,
+                size: size_of::<Self>() as u32,
+                padding: 0,
+            },
+            regs: tf.vr.vr,
+            fcc: tf.fr.fcc,
+            fcsr: tf.fr.fcsr,
+        };
+        for (dst, src) in ctx.regs.iter_mut().zip(tf.fr.as_array()) {
+            dst[0] = src.to_bits();
+        }
+        ctx
+    }
+}
+
+/// The extended contexts following `sigcontext`. Like Linux for a thread
+/// using the vector unit, the LSX or LASX context replaces the floating-point
+/// one when enabled.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct ExtContext {
+    #[cfg(all(feature = "fp", not(feature = "lsx")))]
+    fpu: FpuContext,
+    #[cfg(feature = "lsx")]
+    vector: VectorContext,
+    end: SctxInfo,
+}
+
+/// `struct sigcontext`.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct MContext {
+    pc: usize,
+    regs: [usize; 32],
+    flags: u32,
+    ext: ExtContext,
+}
+
+impl TrapFrame {
+    /// Push a signal frame for `delivery` onto the user stack, and enter its
+    /// handler when returning to user mode.
+    ///
+    /// Returns `false`, leaving the context unchanged, if the user stack is
+    /// not writable.
+    pub fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        // The live registers may be newer than the saved ones.
+        #[cfg(feature = "fp")]
+        self.fr.encounter_signal();
+        #[cfg(feature = "lsx")]
+        self.vr.encounter_signal();
+        let mcontext = MContext {
+            pc: self.era,
+            regs: *self.gr.as_array(),
+            #[cfg(feature = "fp")]
+            flags: SC_USED_FP,
+            #[cfg(not(feature = "fp"))]
+            flags: 0,
+            ext: ExtContext {
+                #[cfg(all(feature = "fp", not(feature = "lsx")))]
+                fpu: FpuContext::new(&self.fr),
+                #[cfg(feature = "lsx")]
+                vector: VectorContext::new(self),
+                end: SctxInfo::END,
+            },
+        };
+
+        let sp = self.gr.sp;
+        let frame = RtSigFrame::new(delivery, sp, mcontext);
+        let Some(addr) = push_frame(delivery, sp, &frame, 0) else {
+            return false;
+        };
+        self.gr.sp = addr;
+        self.gr.ra = delivery.restorer;
+        self.gr.a0 = delivery.info.signo as usize;
+        self.gr.a1 = addr + core::mem::offset_of!(RtSigFrame<MContext>, info);
+        self.gr.a2 = addr + core::mem::offset_of!(RtSigFrame<MContext>, uc);
+        self.era = delivery.handler;
+        true
+    }
+
+    /// Restore the context saved by [`setup_sigframe`](Self::setup_sigframe)
+    /// from the frame at the user stack pointer, on `rt_sigreturn`.
+    ///
+    /// Returns the signal mask to restore, or `None`, leaving the context
+    /// unchanged, if the frame is unreadable or corrupt. The PC is restored as
+    /// well, so it must not be advanced past the system call afterwards.
+    pub fn restore_from_sigframe(&mut self) -> Option<u64> {
+        let frame = pop_frame::<MContext>(self.gr.sp)?;
+        let mcontext = &frame.uc.mcontext;
+        let ext = &mcontext.ext;
+        if ext.end.magic != 0 || !VirtAddr(mcontext.pc).is_user() {
+            return None;
+        }
+        #[cfg(all(feature = "fp", not(feature = "lsx")))]
+        if mcontext.flags & SC_USED_FP == 0
+            || ext.fpu.info.magic != FPU_CTX_MAGIC
+            || ext.fpu.info.size as usize != size_of::<FpuContext>()
+        {
+            return None;
+        }
+        #[cfg(feature = "lsx")]
+        if mcontext.flags & SC_USED_FP == 0
+            || ext.vector.info.magic != VECTOR_CTX_MAGIC
+            || ext.vector.info.size as usize != size_of::<VectorContext>()
+        {
+            return None;
+        }
+
+        self.era = mcontext.pc;
+        self.gr.as_array_mut()[1..].copy_from_slice(&mcontext.regs[1..]);
+        #[cfg(all(feature = "fp", not(feature = "lsx")))]
+        {
+            for (dst, src) in self.fr.as_array_mut().iter_mut().zip(ext.fpu.regs) {
+                *dst = f64::from_bits(src);
+            }
+            self.fr.fcc = ext.fpu.fcc;
+            self.fr.fcsr = ext.fpu.fcsr;
+        }
+        #[cfg(feature = "lsx")]
+        {
+            for (dst, src) in self.fr.as_array_mut().iter_mut().zip(ext.vector.regs) {
+                *dst = f64::from_bits(src[0]);
+            }
+            self.fr.fcc = ext.vector.fcc;
+            self.fr.fcsr = ext.vector.fcsr;
+            // Loaded on the next vector instruction, the live registers are
+            // stale.
+            self.vr.vr = ext.vector.regs;
+            self.vr.v_need_save = 0;
+            self.vr.v_need_restore = 1;
+        }
+        #[cfg(feature = "fp")]
+        {
+            self.fr.f_need_restore = 1;
+            self.fr.restore();
+        }
+        Some(frame.uc.sigmask)
+    }
+}
@@ -2,11 +2,24 @@ use super::config::{
     time::TIMER_IRQ,
     trapframe::{KERNEL_TRAPFRAME_SIZE, USER_TRAPFRAME_SIZE},
 };
-use super::handler::TrapType;
-use super::{handler, irq, time, trapframe};
-use core::arch::naked_asm;
-use loongArch64::register::badv;
-use loongArch64::register::estat::{self, Exception, Trap};
+use super::{irq, time, trapframe};
+use crate::extable::search_exception_table;
+use crate::softirq::run_on_interrupt_exit;
+use crate::stats::record_trap;
+use crate::trap::{AccessType, PageFaultInfo, TrapKind, handle_trap};
+use core::arch::{global_asm, naked_asm};
+use loongArch64::register::estat::{self, Exception, Interrupt, Trap};
+#[cfg(feature = "fp")]
+use loongArch64::register::euen;
+use loongArch64::register::{badi, badv};
+
+global_asm!(include_str!("uaccess.asm"));
+
+/// `ESTAT.Ecode` of the LSX and LASX disabled exceptions.
+#[cfg(feature = "lsx")]
+const ECODE_SXD: usize = 0x10;
+#[cfg(feature = "lsx")]
+const ECODE_ASXD: usize = 0x11;
 
 macro_rules! include_asm_macros {
     () => {
@@ -183,10 +196,49 @@ pub extern "C" fn user_restore(context: *mut trapframe::TrapFrame) {
 
 /// 1、the first time transform to user mode
 /// 2、when user trap in kernel, it will trap into the context of this function
-pub fn run_user_task(context: &mut trapframe::TrapFrame) -> TrapType {
-    user_restore(context);
-    // user trap arrive here
-    loongarch64_trap_handler(context)
+pub fn run_user_task(context: &mut trapframe::TrapFrame) -> TrapKind {
+    loop {
+        // Contexts without their registers loaded trap on their first
+        // floating-point instruction.
+        #[cfg(feature = "fp")]
+        euen::set_fpe(context.fr.f_need_restore == 0);
+        #[cfg(feature = "lsx")]
+        trapframe::set_vector_enabled(context.vr.v_need_restore == 0);
+        user_restore(context);
+        // user trap arrive here
+        #[cfg(feature = "fp")]
+        {
+            context.fr.mark_save_if_needed(euen::read().fpe() as u8);
+            // Load the registers and retry the instruction.
+            if matches!(
+                estat::read().cause(),
+                Trap::Exception(Exception::FloatingPointUnavailable)
+            ) {
+                context.fr.restore();
+                continue;
+            }
+        }
+        #[cfg(feature = "lsx")]
+        {
+            context.vr.mark_save_if_needed(euen::read().sxe() as u8);
+            // The vector disabled exceptions are not decoded by `estat`.
+            let ecode = estat::read().ecode();
+            if ecode == ECODE_SXD || (cfg!(feature = "lasx") && ecode == ECODE_ASXD) {
+                context.load_vector();
+                continue;
+            }
+        }
+        // Misaligned accesses are done on behalf of the task, resume it.
+        #[cfg(feature = "misaligned")]
+        if matches!(
+            estat::read().cause(),
+            Trap::Exception(Exception::AddressNotAligned)
+        ) && crate::misaligned::emulate(context)
+        {
+            continue;
+        }
+        return loongarch64_trap_handler(context);
+    }
 }
 
 /// check the privilege of the source and goto suitable save function
@@ -214,18 +266,33 @@ pub extern "C" fn trap_vector_base() {
             ",
             trapframe_size = const USER_TRAPFRAME_SIZE,
             user_vec = sym user_vec,
-            trap_handler = sym loongarch64_trap_handler,
+            trap_handler = sym kernel_trap_entry,
         )
     }
 }
 
+/// Resume a user access routine that faulted in kernel mode at its exception
+/// table fixup.
+fn resume_at_fixup(tf: &mut trapframe::TrapRegs) -> bool {
+    tf.prmd & 0b11 == 0
+        && search_exception_table(tf.era)
+            .map(|fixup| tf.era = fixup)
+            .is_some()
+}
+
+/// Called by `trap_vector_base` with the frame it built on the stack.
+#[unsafe(no_mangle)]
+extern "C" fn kernel_trap_entry(tf: &mut trapframe::TrapRegs) {
+    loongarch64_trap_handler(tf);
+}
+
 /// classify the trap type to handle type and pass it to specify handler
-fn loongarch64_trap_handler(tf: &mut trapframe::TrapFrame) -> TrapType {
+fn loongarch64_trap_handler(tf: &mut trapframe::TrapRegs) -> TrapKind {
     let estat = estat::read();
     let trap = estat.cause();
-    let mut token: usize = 0;
 
-    let handle_type = match trap {
+    let mut vector = None;
+    let trap_kind = match trap {
         // Interrupt
         Trap::Interrupt(_) => {
             let irq_num: usize = estat.is().trailing_zeros() as usize;
@@ -233,16 +300,23 @@ fn loongarch64_trap_handler(tf: &mut trapframe::TrapFrame) -> TrapType {
                 // TIMER_IRQ
                 TIMER_IRQ => {
                     time::clear_timer();
-                    TrapType::Time
+                    TrapKind::Timer
                 }
-                // others
-                _ => {
-                    unimplemented!();
-                    // panic!("unknown interrupt: {}", irq_num)
+                irq if irq == Interrupt::IPI as usize => {
+                    irq::Irq::clear_ipi();
+                    TrapKind::Ipi
                 }
+                // The hardware lines are raised by the EIOINTC, which holds
+                // the vector until the handlers ran.
+                irq if (Interrupt::HWI0 as usize..=Interrupt::HWI7 as usize).contains(&irq) => {
+                    vector = irq::IRQVector::claim();
+                    TrapKind::Irq(vector.map_or(0, |vector| vector.irq_num()))
+                }
+                // others
+                _ => TrapKind::Unknown,
             }
         }
-        
+
         // Exception
         Trap::Exception(
             page_fault @ (Exception::LoadPageFault
@@ -253,53 +327,86 @@ fn loongarch64_trap_handler(tf: &mut trapframe::TrapFrame) -> TrapType {
             | Exception::PageNonExecutableFault
             | Exception::PagePrivilegeIllegal),
         ) => {
-            token = page_fault as usize;
-            TrapType::PageFault
+            let access = match page_fault {
+                Exception::LoadPageFault | Exception::PageNonReadableFault => AccessType::Read,
+                Exception::StorePageFault | Exception::PageModifyFault => AccessType::Write,
+                Exception::FetchPageFault | Exception::PageNonExecutableFault => {
+                    AccessType::Execute
+                }
+                // PPI does not tell the access type, guess it from the faulting address.
+                _ if badv::read().vaddr() == tf.era => AccessType::Execute,
+                _ => AccessType::Read,
+            };
+            TrapKind::PageFault(PageFaultInfo {
+                addr: badv::read().vaddr(),
+                access,
+                protection: !matches!(
+                    page_fault,
+                    Exception::LoadPageFault
+                        | Exception::StorePageFault
+                        | Exception::FetchPageFault
+                ),
+            })
         }
 
+        Trap::Exception(Exception::FetchInstructionAddressError) => TrapKind::AddressError {
+            addr: badv::read().vaddr(),
+            access: AccessType::Execute,
+        },
+
         Trap::Exception(
-            address_error @ (Exception::FetchInstructionAddressError
-            | Exception::MemoryAccessAddressError
+            Exception::MemoryAccessAddressError
             | Exception::AddressNotAligned
-            | Exception::BoundsCheckFault),
-        ) => {
-            token = (address_error as usize) - 7;
-            TrapType::AddressError
-        }
+            | Exception::BoundsCheckFault,
+        ) => TrapKind::AddressError {
+            addr: badv::read().vaddr(),
+            // ADEM/ALE/BCE do not report the direction of the access.
+            access: AccessType::Read,
+        },
 
-        Trap::Exception(Exception::Syscall) => TrapType::SysCall,
+        Trap::Exception(Exception::Syscall) => TrapKind::SysCall,
 
         Trap::Exception(Exception::Breakpoint) => {
             tf.era += 4;
-            TrapType::Breakpoint
+            TrapKind::Breakpoint
         }
 
         Trap::Exception(
-            instr_error @ (Exception::InstructionNotExist
+            Exception::InstructionNotExist
             | Exception::InstructionPrivilegeIllegal
-            | Exception::FloatingPointUnavailable),
-        ) => {
-            token = (instr_error as usize) - 13;
-            TrapType::InstrError
-        }
-
-        Trap::Exception(Exception::TLBRFill) => TrapType::TLBRefill,
+            // User contexts with the `fp` feature load their registers in
+            // `run_user_task` instead.
+            | Exception::FloatingPointUnavailable,
+        ) => TrapKind::IllegalInstruction(badi::read().inst() as usize),
 
-        // Machine Error
-        Trap::MachineError(_) => todo!(),
-        // Unknown
-        Trap::Unknown => todo!(),
-        _ => {
-            panic!(
-                "Unhandled trap {:?} @ {:#x} BADV: {:#x}:\n{:#x?}",
+        // Machine errors are left to the default handler. TLB refill has its
+        // own entry and never arrives here.
+        Trap::MachineError(_) | Trap::Exception(Exception::TLBRFill) | Trap::Unknown => {
+            log::warn!(
+                "Unhandled trap {:?} @ {:#x} BADV: {:#x}",
                 estat.cause(),
                 tf.era,
-                badv::read().vaddr(),
-                tf
+                badv::read().vaddr()
             );
+            TrapKind::Unknown
         }
     };
+    record_trap(&trap_kind);
 
-    handler::specify_handler(tf, handle_type, token);
-    handle_type
+    // A user access routine hit a bad address, resume at its fixup.
+    if matches!(trap_kind, TrapKind::AddressError { .. }) && resume_at_fixup(tf) {
+        return trap_kind;
+    }
+    // Page faults go to their handlers first, the fixup only takes the ones
+    // no handler resolved.
+    handle_trap(tf, &trap_kind, |tf| {
+        matches!(trap_kind, TrapKind::PageFault(_)) && resume_at_fixup(tf)
+    });
+    if let Some(vector) = vector {
+        vector.irq_ack();
+    }
+    if trap_kind.is_interrupt() {
+        run_on_interrupt_exit();
+    }
+    trap_kind
 }
@@ -1,4 +1,12 @@
+#[cfg(feature = "fp")]
 use core::arch::asm;
+use core::ops::{Deref, DerefMut, Index, IndexMut};
+
+use super::config::trapframe::USER_TRAPFRAME_SIZE;
+use crate::trap::{TrapFrameArgs, UserContext};
+#[cfg(feature = "fp")]
+use loongArch64::register::euen;
+
 /// General registers of Loongarch64.
 #[allow(missing_docs)]
 #[repr(C)]
@@ -39,9 +47,22 @@ pub struct GeneralRegisters {
     pub s8: usize,
 }
 
+impl GeneralRegisters {
+    /// The registers indexed by number, `r0` to `r31`.
+    #[inline]
+    pub fn as_array(&self) -> &[usize; 32] {
+        unsafe { &*(self as *const Self).cast() }
+    }
+
+    #[inline]
+    pub fn as_array_mut(&mut self) -> &mut [usize; 32] {
+        unsafe { &mut *(self as *mut Self).cast() }
+    }
+}
+
 #[cfg(feature = "fp")]
 /// Floating point registers of Loongarch64.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct FloatingPointRegisters {
     pub fa0: f64,
@@ -76,37 +97,112 @@ pub struct FloatingPointRegisters {
     pub fs5: f64,
     pub fs6: f64,
     pub fs7: f64,
+    /// Condition flags `fcc0` to `fcc7`, one per byte.
+    pub fcc: u64,
     pub fcsr: u32, // floating point control and status register
     pub f_need_save: u8,  // for lazy save
     pub f_need_restore: u8,
     pub f_signal_dirty: u8
 }
 
-/// Saved registers when a trap (interrupt or exception) occurs.
+/// 64-bit words of a vector register.
+#[cfg(all(feature = "lsx", not(feature = "lasx")))]
+pub(crate) const VR_WORDS: usize = 2;
+#[cfg(feature = "lasx")]
+pub(crate) const VR_WORDS: usize = 4;
+
+/// Vector registers of Loongarch64, 128-bit with LSX and 256-bit with LASX.
+///
+/// Their low 64 bits are the floating-point registers, which `fr` holds as
+/// well. Signal frames carry them in an LSX or LASX context instead of the
+/// floating-point one.
+#[cfg(feature = "lsx")]
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(16))]
+pub struct VectorRegisters {
+    /// `vr0` to `vr31`, or `xr0` to `xr31` with LASX.
+    pub vr: [[u64; VR_WORDS]; 32],
+    pub v_need_save: u8, // for lazy save
+    pub v_need_restore: u8,
+}
+
+/// Saved registers when a trap (interrupt or exception) occurs, the whole
+/// frame of kernel mode traps and what trap handlers see. A [`TrapFrame`]
+/// starts with them and dereferences to them.
 #[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
-pub struct TrapFrame {
+pub struct TrapRegs {
     pub gr: GeneralRegisters, // general purpose registers
-    pub era: usize,           // exception return address
     pub prmd: usize,          // pre-exception mode information
-    // #[cfg(feature = "fp")]
-    // pub fr: FloatingPointRegisters, // floating point registers
+    pub era: usize,           // exception return address
+}
+
+// Kernel mode traps save a frame of this size on the stack.
+const _: () = assert!(size_of::<TrapRegs>() == USER_TRAPFRAME_SIZE);
+
+/// A user context. The floating-point and vector registers are only saved
+/// here, not by kernel mode traps.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub regs: TrapRegs,
+    pub last_a0: usize, // a0 before the syscall return value overwrote it
+    #[cfg(feature = "fp")]
+    pub fr: FloatingPointRegisters, // floating point registers
+    #[cfg(feature = "lsx")]
+    pub vr: VectorRegisters, // vector registers
 }
 
 impl TrapFrame {
     #[inline]
     pub fn new() -> Self {
         Self {
-            prmd: (0b0111),
+            regs: TrapRegs {
+                prmd: (0b0111),
+                ..Default::default()
+            },
             ..Default::default()
         }
     }
 
     /// If the syscall is successful, the return address is incremented by 4.
+    /// Forwards to [`UserContext::syscall_ok`], for callers that do not
+    /// import the trait.
+    #[inline]
     pub fn syscall_ok(&mut self) {
-        self.era += 4;
+        UserContext::syscall_ok(self);
     }
 
+    /// Load the vector registers of a context trapping on its first vector
+    /// instruction since they were switched out. The live floating-point
+    /// registers, i.e. their low halves, are kept.
+    #[cfg(feature = "lsx")]
+    pub fn load_vector(&mut self) {
+        self.fr.save_fr();
+        self.vr.restore();
+        self.fr.f_need_restore = 1;
+        self.fr.restore();
+    }
+}
+
+impl Deref for TrapFrame {
+    type Target = TrapRegs;
+
+    #[inline]
+    fn deref(&self) -> &TrapRegs {
+        &self.regs
+    }
+}
+
+impl DerefMut for TrapFrame {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut TrapRegs {
+        &mut self.regs
+    }
+}
+
+impl TrapRegs {
+
     #[inline]
     pub const fn arg0(&self) -> usize {
         self.gr.a0
@@ -147,15 +243,163 @@ impl TrapFrame {
         self.gr.a7
     }
 
+    /// Address of the trapping instruction.
+    #[inline]
+    pub const fn pc(&self) -> usize {
+        self.era
+    }
+
+    /// Frame pointer at the time of the trap.
+    #[inline]
+    pub const fn fp(&self) -> usize {
+        self.gr.fp
+    }
+
+    /// Resume execution at `pc`.
+    #[inline]
+    pub fn set_pc(&mut self, pc: usize) {
+        self.era = pc;
+    }
+
+    /// Whether the trap was taken from user mode, i.e. `PRMD.PPLV` is not 0.
+    #[inline]
+    pub const fn is_user(&self) -> bool {
+        self.prmd & 0b11 != 0
+    }
+
+    /// Returns general-purpose register `r<n>`.
+    #[inline]
+    pub fn gpr(&self, n: usize) -> usize {
+        self.gr.as_array()[n]
+    }
+
+    /// Set general-purpose register `r<n>`. Writes to `r0` are ignored.
+    #[inline]
+    pub fn set_gpr(&mut self, n: usize, value: usize) {
+        if n != 0 {
+            self.gr.as_array_mut()[n] = value;
+        }
+    }
+}
+
+impl Index<TrapFrameArgs> for TrapRegs {
+    type Output = usize;
+
+    fn index(&self, index: TrapFrameArgs) -> &Self::Output {
+        match index {
+            TrapFrameArgs::SEPC => &self.era,
+            TrapFrameArgs::RA => &self.gr.ra,
+            TrapFrameArgs::SP => &self.gr.sp,
+            TrapFrameArgs::RET => &self.gr.a0,
+            TrapFrameArgs::ARG0 => &self.gr.a0,
+            TrapFrameArgs::ARG1 => &self.gr.a1,
+            TrapFrameArgs::ARG2 => &self.gr.a2,
+            TrapFrameArgs::TLS => &self.gr.tp,
+            TrapFrameArgs::SYSCALL => &self.gr.a7,
+        }
+    }
+}
+
+impl IndexMut<TrapFrameArgs> for TrapRegs {
+    fn index_mut(&mut self, index: TrapFrameArgs) -> &mut Self::Output {
+        match index {
+            TrapFrameArgs::SEPC => &mut self.era,
+            TrapFrameArgs::RA => &mut self.gr.ra,
+            TrapFrameArgs::SP => &mut self.gr.sp,
+            TrapFrameArgs::RET => &mut self.gr.a0,
+            TrapFrameArgs::ARG0 => &mut self.gr.a0,
+            TrapFrameArgs::ARG1 => &mut self.gr.a1,
+            TrapFrameArgs::ARG2 => &mut self.gr.a2,
+            TrapFrameArgs::TLS => &mut self.gr.tp,
+            TrapFrameArgs::SYSCALL => &mut self.gr.a7,
+        }
+    }
+}
+
+impl Index<TrapFrameArgs> for TrapFrame {
+    type Output = usize;
+
+    fn index(&self, index: TrapFrameArgs) -> &Self::Output {
+        &self.regs[index]
+    }
+}
+
+impl IndexMut<TrapFrameArgs> for TrapFrame {
+    fn index_mut(&mut self, index: TrapFrameArgs) -> &mut Self::Output {
+        &mut self.regs[index]
+    }
+}
+
+impl UserContext for TrapFrame {
+    fn new_user(entry: usize, sp: usize) -> Self {
+        let mut tf = Self::new();
+        tf.era = entry;
+        tf.gr.sp = sp;
+        tf
+    }
+
+    fn init_user(&mut self, user_sp: usize, entry: usize, argc: usize, argv: usize, envp: usize) {
+        self.gr.sp = user_sp;
+        self.gr.a0 = argc;
+        self.gr.a1 = argv;
+        self.gr.a2 = envp;
+        self.era = entry;
+        #[cfg(feature = "fp")]
+        {
+            self.fr = FloatingPointRegisters::new();
+        }
+        #[cfg(feature = "lsx")]
+        {
+            self.vr = VectorRegisters::new();
+        }
+    }
+
+    fn syscall_args(&self) -> [usize; 6] {
+        [
+            self.gr.a0, self.gr.a1, self.gr.a2, self.gr.a3, self.gr.a4, self.gr.a5,
+        ]
+    }
+
+    fn save_last_user_a0(&mut self) {
+        self.last_a0 = self.gr.a0;
+    }
+
+    fn restore_last_user_a0(&mut self) {
+        self.gr.a0 = self.last_a0;
+    }
 }
 
+#[cfg(feature = "fp")]
+impl Default for FloatingPointRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+#[cfg(feature = "fp")]
 impl FloatingPointRegisters {
     // implementation of lazy save for floating point registers
     pub fn new() -> Self {
-        unsafe { core::mem::zeroed() }
+        let mut fr: Self = unsafe { core::mem::zeroed() };
+        // Load the zeroed state on first use.
+        fr.f_need_restore = 1;
+        fr
     }
 
+    /// `f0` to `f31`.
+    #[inline]
+    pub fn as_array(&self) -> &[f64; 32] {
+        unsafe { &*(self as *const Self).cast() }
+    }
+
+    #[inline]
+    pub fn as_array_mut(&mut self) -> &mut [f64; 32] {
+        unsafe { &mut *(self as *mut Self).cast() }
+    }
+
+    /// Called on every trap from user mode with whether the floating-point
+    /// unit is enabled, i.e. the context has its registers loaded and may
+    /// have changed them.
     pub fn mark_save_if_needed(&mut self, need_save: u8) {
         self.f_need_save |= need_save;
         self.f_signal_dirty |= need_save;
@@ -169,97 +413,233 @@ impl FloatingPointRegisters {
         self.save_fr();
     }
 
+    /// Save reg -> mem
     pub fn save_fr(&mut self) {
         if self.f_need_save == 0 {
             return;
         }
         self.f_need_save = 0;
-
         unsafe {
-            let mut _t: usize = 1; // alloc a register but not zero.
-            asm!("
-            fsd  f0,  0*8({0})
-            fsd  f1,  1*8({0})
-            fsd  f2,  2*8({0})
-            fsd  f3,  3*8({0})
-            fsd  f4,  4*8({0})
-            fsd  f5,  5*8({0})
-            fsd  f6,  6*8({0})
-            fsd  f7,  7*8({0})
-            fsd  f8,  8*8({0})
-            fsd  f9,  9*8({0})
-            fsd f10, 10*8({0})
-            fsd f11, 11*8({0})
-            fsd f12, 12*8({0})
-            fsd f13, 13*8({0})
-            fsd f14, 14*8({0})
-            fsd f15, 15*8({0})
-            fsd f16, 16*8({0})
-            fsd f17, 17*8({0})
-            fsd f18, 18*8({0})
-            fsd f19, 19*8({0})
-            fsd f20, 20*8({0})
-            fsd f21, 21*8({0})
-            fsd f22, 22*8({0})
-            fsd f23, 23*8({0})
-            fsd f24, 24*8({0})
-            fsd f25, 25*8({0})
-            fsd f26, 26*8({0})
-            fsd f27, 27*8({0})
-            fsd f28, 28*8({0})
-            fsd f29, 29*8({0})
-            fsd f30, 30*8({0})
-            fsd f31, 31*8({0})
-            csrr {1}, fcsr
-            sw  {1}, 32*8({0})
-        ", in(reg) &self,
-                inout(reg) _t
+            asm!(
+                "
+                fst.d $f0,  {regs}, 0*8
+                fst.d $f1,  {regs}, 1*8
+                fst.d $f2,  {regs}, 2*8
+                fst.d $f3,  {regs}, 3*8
+                fst.d $f4,  {regs}, 4*8
+                fst.d $f5,  {regs}, 5*8
+                fst.d $f6,  {regs}, 6*8
+                fst.d $f7,  {regs}, 7*8
+                fst.d $f8,  {regs}, 8*8
+                fst.d $f9,  {regs}, 9*8
+                fst.d $f10, {regs}, 10*8
+                fst.d $f11, {regs}, 11*8
+                fst.d $f12, {regs}, 12*8
+                fst.d $f13, {regs}, 13*8
+                fst.d $f14, {regs}, 14*8
+                fst.d $f15, {regs}, 15*8
+                fst.d $f16, {regs}, 16*8
+                fst.d $f17, {regs}, 17*8
+                fst.d $f18, {regs}, 18*8
+                fst.d $f19, {regs}, 19*8
+                fst.d $f20, {regs}, 20*8
+                fst.d $f21, {regs}, 21*8
+                fst.d $f22, {regs}, 22*8
+                fst.d $f23, {regs}, 23*8
+                fst.d $f24, {regs}, 24*8
+                fst.d $f25, {regs}, 25*8
+                fst.d $f26, {regs}, 26*8
+                fst.d $f27, {regs}, 27*8
+                fst.d $f28, {regs}, 28*8
+                fst.d $f29, {regs}, 29*8
+                fst.d $f30, {regs}, 30*8
+                fst.d $f31, {regs}, 31*8
+                movcf2gr {fcc}, $fcc0
+                movcf2gr {t}, $fcc1
+                bstrins.d {fcc}, {t}, 15, 8
+                movcf2gr {t}, $fcc2
+                bstrins.d {fcc}, {t}, 23, 16
+                movcf2gr {t}, $fcc3
+                bstrins.d {fcc}, {t}, 31, 24
+                movcf2gr {t}, $fcc4
+                bstrins.d {fcc}, {t}, 39, 32
+                movcf2gr {t}, $fcc5
+                bstrins.d {fcc}, {t}, 47, 40
+                movcf2gr {t}, $fcc6
+                bstrins.d {fcc}, {t}, 55, 48
+                movcf2gr {t}, $fcc7
+                bstrins.d {fcc}, {t}, 63, 56
+                movfcsr2gr {fcsr}, $fcsr0
+                ",
+                regs = in(reg) self.as_array_mut().as_mut_ptr(),
+                fcc = out(reg) self.fcc,
+                fcsr = out(reg) self.fcsr,
+                t = out(reg) _,
             );
-        };
+        }
     }
+
+    /// Restore mem -> reg, and enable the floating-point unit.
     pub fn restore(&mut self) {
         if self.f_need_restore == 0 {
             return;
         }
         self.f_need_restore = 0;
+        euen::set_fpe(true);
         unsafe {
-            asm!("
-            fld  f0,  0*8({0})
-            fld  f1,  1*8({0})
-            fld  f2,  2*8({0})
-            fld  f3,  3*8({0})
-            fld  f4,  4*8({0})
-            fld  f5,  5*8({0})
-            fld  f6,  6*8({0})
-            fld  f7,  7*8({0})
-            fld  f8,  8*8({0})
-            fld  f9,  9*8({0})
-            fld f10, 10*8({0})
-            fld f11, 11*8({0})
-            fld f12, 12*8({0})
-            fld f13, 13*8({0})
-            fld f14, 14*8({0})
-            fld f15, 15*8({0})
-            fld f16, 16*8({0})
-            fld f17, 17*8({0})
-            fld f18, 18*8({0})
-            fld f19, 19*8({0})
-            fld f20, 20*8({0})
-            fld f21, 21*8({0})
-            fld f22, 22*8({0})
-            fld f23, 23*8({0})
-            fld f24, 24*8({0})
-            fld f25, 25*8({0})
-            fld f26, 26*8({0})
-            fld f27, 27*8({0})
-            fld f28, 28*8({0})
-            fld f29, 29*8({0})
-            fld f30, 30*8({0})
-            fld f31, 31*8({0})
-            lw  {0}, 32*8({0})
-            csrw fcsr, {0}
-        ", in(reg) &self
+            asm!(
+                "
+                fld.d $f0,  {regs}, 0*8
+                fld.d $f1,  {regs}, 1*8
+                fld.d $f2,  {regs}, 2*8
+                fld.d $f3,  {regs}, 3*8
+                fld.d $f4,  {regs}, 4*8
+                fld.d $f5,  {regs}, 5*8
+                fld.d $f6,  {regs}, 6*8
+                fld.d $f7,  {regs}, 7*8
+                fld.d $f8,  {regs}, 8*8
+                fld.d $f9,  {regs}, 9*8
+                fld.d $f10, {regs}, 10*8
+                fld.d $f11, {regs}, 11*8
+                fld.d $f12, {regs}, 12*8
+                fld.d $f13, {regs}, 13*8
+                fld.d $f14, {regs}, 14*8
+                fld.d $f15, {regs}, 15*8
+                fld.d $f16, {regs}, 16*8
+                fld.d $f17, {regs}, 17*8
+                fld.d $f18, {regs}, 18*8
+                fld.d $f19, {regs}, 19*8
+                fld.d $f20, {regs}, 20*8
+                fld.d $f21, {regs}, 21*8
+                fld.d $f22, {regs}, 22*8
+                fld.d $f23, {regs}, 23*8
+                fld.d $f24, {regs}, 24*8
+                fld.d $f25, {regs}, 25*8
+                fld.d $f26, {regs}, 26*8
+                fld.d $f27, {regs}, 27*8
+                fld.d $f28, {regs}, 28*8
+                fld.d $f29, {regs}, 29*8
+                fld.d $f30, {regs}, 30*8
+                fld.d $f31, {regs}, 31*8
+                bstrpick.d {t}, {fcc}, 7, 0
+                movgr2cf $fcc0, {t}
+                bstrpick.d {t}, {fcc}, 15, 8
+                movgr2cf $fcc1, {t}
+                bstrpick.d {t}, {fcc}, 23, 16
+                movgr2cf $fcc2, {t}
+                bstrpick.d {t}, {fcc}, 31, 24
+                movgr2cf $fcc3, {t}
+                bstrpick.d {t}, {fcc}, 39, 32
+                movgr2cf $fcc4, {t}
+                bstrpick.d {t}, {fcc}, 47, 40
+                movgr2cf $fcc5, {t}
+                bstrpick.d {t}, {fcc}, 55, 48
+                movgr2cf $fcc6, {t}
+                bstrpick.d {t}, {fcc}, 63, 56
+                movgr2cf $fcc7, {t}
+                movgr2fcsr $fcsr0, {fcsr}
+                ",
+                regs = in(reg) self.as_array().as_ptr(),
+                fcc = in(reg) self.fcc,
+                fcsr = in(reg) self.fcsr,
+                t = out(reg) _,
             );
         }
     }
-}
\ No newline at end of file
+}
+
+/// Enable or disable the vector instructions, which trap with SXD or ASXD
+/// while disabled.
+#[cfg(feature = "lsx")]
+pub fn set_vector_enabled(enable: bool) {
+    euen::set_sxe(enable);
+    #[cfg(feature = "lasx")]
+    euen::set_asxe(enable);
+}
+
+#[cfg(feature = "lsx")]
+impl Default for VectorRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "lsx")]
+impl VectorRegisters {
+    // implementation of lazy save for vector registers
+    pub fn new() -> Self {
+        let mut vr: Self = unsafe { core::mem::zeroed() };
+        // Load the zeroed state on first use.
+        vr.v_need_restore = 1;
+        vr
+    }
+
+    /// Called on every trap from user mode with whether the vector
+    /// instructions are enabled, see
+    /// [`FloatingPointRegisters::mark_save_if_needed`].
+    pub fn mark_save_if_needed(&mut self, need_save: u8) {
+        self.v_need_save |= need_save;
+    }
+
+    pub fn yield_task(&mut self) {
+        self.save_vr();
+        self.v_need_restore = 1;
+    }
+
+    pub fn encounter_signal(&mut self) {
+        self.save_vr();
+    }
+
+    /// Save reg -> mem
+    pub fn save_vr(&mut self) {
+        if self.v_need_save == 0 {
+            return;
+        }
+        self.v_need_save = 0;
+        let regs = self.vr.as_mut_ptr();
+        unsafe {
+            #[cfg(not(feature = "lasx"))]
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "vst $vr\\n, {regs}, \\n*16",
+                ".endr",
+                regs = in(reg) regs,
+            );
+            #[cfg(feature = "lasx")]
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "xvst $xr\\n, {regs}, \\n*32",
+                ".endr",
+                regs = in(reg) regs,
+            );
+        }
+    }
+
+    /// Restore mem -> reg, and enable the vector instructions along with the
+    /// floating-point unit they build on.
+    pub fn restore(&mut self) {
+        if self.v_need_restore == 0 {
+            return;
+        }
+        self.v_need_restore = 0;
+        euen::set_fpe(true);
+        set_vector_enabled(true);
+        let regs = self.vr.as_ptr();
+        unsafe {
+            #[cfg(not(feature = "lasx"))]
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "vld $vr\\n, {regs}, \\n*16",
+                ".endr",
+                regs = in(reg) regs,
+            );
+            #[cfg(feature = "lasx")]
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "xvld $xr\\n, {regs}, \\n*32",
+                ".endr",
+                regs = in(reg) regs,
+            );
+        }
+    }
+}
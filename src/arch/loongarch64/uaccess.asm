@@ -0,0 +1,62 @@
+# Record that the instruction at \insn may fault on user memory, and that
+# execution continues at \fixup if it does. "R" keeps the section alive
+# under --gc-sections.
+.macro EXTABLE insn, fixup
+    .pushsection ex_table, "aR"
+    .balign 8
+    .dword \insn, \fixup
+    .popsection
+.endm
+
+.equ EFAULT, 14
+
+    .section .text
+    .globl __probe_user_read
+    .globl __probe_user_write
+    .globl __copy_user
+    .balign 4
+
+# arg: (user_ptr)
+# return: 0, or -EFAULT if the byte is not readable
+__probe_user_read:
+.Lprobe_read:
+    ld.b    $t0, $a0, 0
+    move    $a0, $zero
+    jr      $ra
+    EXTABLE .Lprobe_read, __uaccess_fault
+
+# arg: (user_ptr)
+# return: 0, or -EFAULT if the byte is not writable
+__probe_user_write:
+.Lprobe_write_load:
+    ld.b    $t0, $a0, 0
+.Lprobe_write_store:
+    st.b    $t0, $a0, 0
+    move    $a0, $zero
+    jr      $ra
+    EXTABLE .Lprobe_write_load, __uaccess_fault
+    EXTABLE .Lprobe_write_store, __uaccess_fault
+
+# args: (dst, src, len), either of dst and src may be a user pointer
+# return: 0, or -EFAULT if a byte could not be copied
+__copy_user:
+    beqz    $a2, 2f
+1:
+.Lcopy_load:
+    ld.b    $t0, $a1, 0
+.Lcopy_store:
+    st.b    $t0, $a0, 0
+    addi.d  $a0, $a0, 1
+    addi.d  $a1, $a1, 1
+    addi.d  $a2, $a2, -1
+    bnez    $a2, 1b
+2:
+    move    $a0, $zero
+    jr      $ra
+    EXTABLE .Lcopy_load, __uaccess_fault
+    EXTABLE .Lcopy_store, __uaccess_fault
+
+# Shared fixup of the leaf routines above.
+__uaccess_fault:
+    addi.d  $a0, $zero, -EFAULT
+    jr      $ra
@@ -1,45 +1,49 @@
+use super::plic;
 use crate::CPU_ID;
-use crate::{
-    arch::config::mm::VIRT_ADDR_START, DEVICE_TREE_BLOB, DTB_PTR, MEMORY_AREAS
-};
+use crate::addr::{PhysAddr, phys_to_virt};
+use crate::irq::without_interrupts;
+use crate::memory::init_memory_regions;
+use crate::numa::init_numa;
+use crate::{DEVICE_TREE_BLOB, DTB_PTR};
 use core::slice;
-use alloc::vec;
 use alloc::vec::Vec;
 use fdt::Fdt;
 
+/// Bring up the heap, memory map, NUMA topology and interrupt controllers
+/// from the device tree. The memory map needs the `_skernel` and `_ekernel`
+/// symbols from the kernel linker script.
 pub fn arch_init() {
+    #[cfg(feature = "heap")]
+    crate::heap::init_heap();
     let mut buffer = Vec::new();
-    if let Ok(fdt) = unsafe { Fdt::from_ptr(*DTB_PTR as *const u8) } {
-        unsafe {
-            buffer.extend_from_slice(slice::from_raw_parts(
-                *DTB_PTR as *const u8,
-                fdt.total_size(),
-            ));
+    if let Some(&dtb_ptr) = DTB_PTR.try_get() {
+        let dtb_ptr = phys_to_virt(PhysAddr(dtb_ptr)).0 as *const u8;
+        if let Ok(fdt) = unsafe { Fdt::from_ptr(dtb_ptr) } {
+            unsafe {
+                buffer.extend_from_slice(slice::from_raw_parts(dtb_ptr, fdt.total_size()));
+            }
         }
     }
     DEVICE_TREE_BLOB.init(buffer);
-    let mut mem_area = Vec::new();
-    if let Ok(fdt) = Fdt::new(&DEVICE_TREE_BLOB) {
+    let fdt = Fdt::new(&DEVICE_TREE_BLOB).ok();
+    if let Some(fdt) = &fdt {
         log::info!("There has {} CPU(s)", fdt.cpus().count());
-        fdt.memory().regions().for_each(|x| {
-            log::info!(
-                "memory region {:#X} - {:#X}",
-                x.starting_address as usize,
-                x.starting_address as usize + x.size.unwrap()
-            );
-            mem_area.push((
-                x.starting_address as usize | VIRT_ADDR_START,
-                x.size.unwrap_or(0),
-            ));
-        });
-    } else {
-        mem_area.push((0x8000_0000 | VIRT_ADDR_START, 0x1000_0000));
     }
-    MEMORY_AREAS.init(mem_area);
+    init_memory_regions(fdt.as_ref(), 0x8000_0000..0x9000_0000);
+    init_numa(fdt.as_ref());
+    plic::init(fdt.as_ref());
 }
 
+/// Returns the hart ID the current hart passed to `init_percpu`.
 #[inline]
 pub fn hart_id() -> usize {
-    CPU_ID.read_current()
-    
+    // Located and read with interrupts disabled, so the task cannot migrate
+    // in between.
+    without_interrupts(|| unsafe { CPU_ID.read_current_raw() })
+}
+
+/// Point the current hart at the per-CPU data area at `base`.
+#[inline]
+pub unsafe fn set_percpu_base(base: usize) {
+    unsafe { core::arch::asm!("mv gp, {}", in(reg) base) };
 }
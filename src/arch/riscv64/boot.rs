@@ -116,12 +116,15 @@ unsafe extern "C" fn _start(hart_id: usize, dtb_addr: usize) -> ! {
 // Main entry point after initialization
 #[unsafe(no_mangle)]
 pub fn rust_main(hart_id: usize) -> ! {
+    crate::percpu::init_percpu(hart_id);
+    super::arch::arch_init();
     // Placeholder
     loop {}
 }
 
 #[unsafe(no_mangle)]
 pub fn rust_secondary_main(hart_id: usize) -> ! {
+    crate::percpu::init_percpu(hart_id);
     // Placeholder
     loop {}
 }
@@ -1,3 +1,7 @@
 pub const MAX_HARTS: usize = 4;
 
 pub const CLOCK_FREQ: usize =10000000;
+
+/// Largest `vlenb` the vector context has room for, i.e. a `VLEN` of 512.
+/// Harts with longer vectors run without the vector extension.
+pub const MAX_VLENB: usize = 64;
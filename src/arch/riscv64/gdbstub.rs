@@ -0,0 +1,182 @@
+//! Registers, breakpoints and step decoding of the GDB stub.
+use super::config::mm::PAGE_SIZE_BITS;
+use super::mm::pagetable::Riscv64PTEFlags;
+use super::trapframe::TrapRegs;
+use crate::addr::{VirtAddr, phys_to_virt};
+use crate::gdbstub::read_mem;
+use crate::pagetable::PageTable;
+use riscv::register::satp;
+
+/// `x0` to `x31` and `pc`, in the order of the GDB register numbers.
+pub(crate) const NUM_REGS: usize = 33;
+
+/// `c.ebreak` fits in front of every instruction, compressed or not.
+pub(crate) const STEP_BREAK_LEN: usize = 2;
+
+const C_EBREAK: u32 = 0x9002;
+const EBREAK: u32 = 0x0010_0073;
+
+const OP_BRANCH: u32 = 0x63;
+const OP_JALR: u32 = 0x67;
+const OP_JAL: u32 = 0x6f;
+
+/// Extract `len` bits of `insn` starting at bit `lo`.
+#[inline]
+const fn bits(insn: u32, lo: u32, len: u32) -> usize {
+    ((insn >> lo) & ((1 << len) - 1)) as usize
+}
+
+/// Sign-extend the low `len` bits of `value`.
+#[inline]
+const fn sext(value: usize, len: u32) -> usize {
+    (((value << (64 - len)) as isize) >> (64 - len)) as usize
+}
+
+/// Returns GDB register `n`.
+pub(crate) fn read_reg(tf: &TrapRegs, n: usize) -> Option<usize> {
+    match n {
+        0..32 => Some(tf.gpr(n)),
+        32 => Some(tf.sepc),
+        _ => None,
+    }
+}
+
+/// Set GDB register `n`. Writes to `x0` are ignored.
+pub(crate) fn write_reg(tf: &mut TrapRegs, n: usize, value: usize) -> bool {
+    match n {
+        0..32 => tf.set_gpr(n, value),
+        32 => tf.sepc = value,
+        _ => return false,
+    }
+    true
+}
+
+/// Returns the breakpoint instruction for a `Z0` kind, which is its length.
+pub(crate) fn break_insn(kind: usize) -> Option<&'static [u8]> {
+    const C_EBREAK_BYTES: [u8; 2] = (C_EBREAK as u16).to_le_bytes();
+    const EBREAK_BYTES: [u8; 4] = EBREAK.to_le_bytes();
+    match kind {
+        2 => Some(&C_EBREAK_BYTES),
+        4 => Some(&EBREAK_BYTES),
+        _ => None,
+    }
+}
+
+/// Returns the length of the breakpoint instruction at `pc`, if there is one.
+pub(crate) fn break_len(pc: usize) -> Option<usize> {
+    match fetch(pc)? {
+        (C_EBREAK, 2) => Some(2),
+        (EBREAK, 4) => Some(4),
+        _ => None,
+    }
+}
+
+/// The trap leaves the PC at the breakpoint instruction.
+pub(crate) fn breakpoint_pc(tf: &TrapRegs) -> usize {
+    tf.sepc
+}
+
+/// Fetch the instruction at `pc`, with its length.
+fn fetch(pc: usize) -> Option<(u32, usize)> {
+    let mut low = [0u8; 2];
+    if !read_mem(pc, &mut low) {
+        return None;
+    }
+    let low = u16::from_le_bytes(low) as u32;
+    if low & 0b11 != 0b11 {
+        return Some((low, 2));
+    }
+    let mut high = [0u8; 2];
+    if !read_mem(pc + 2, &mut high) {
+        return None;
+    }
+    Some((low | ((u16::from_le_bytes(high) as u32) << 16), 4))
+}
+
+/// Returns the addresses the instruction at the PC of `tf` may continue at.
+pub(crate) fn step_targets(tf: &TrapRegs) -> Option<[Option<usize>; 2]> {
+    let pc = tf.sepc;
+    let (insn, len) = fetch(pc)?;
+    let next = pc + len;
+    let targets = if len == 4 {
+        match insn & 0x7f {
+            OP_JAL => {
+                let offset = (bits(insn, 31, 1) << 20)
+                    | (bits(insn, 21, 10) << 1)
+                    | (bits(insn, 20, 1) << 11)
+                    | (bits(insn, 12, 8) << 12);
+                [Some(pc.wrapping_add(sext(offset, 21))), None]
+            }
+            OP_JALR => {
+                let base = tf.gpr(bits(insn, 15, 5));
+                [
+                    Some(base.wrapping_add(sext(bits(insn, 20, 12), 12)) & !1),
+                    None,
+                ]
+            }
+            OP_BRANCH => {
+                let offset = (bits(insn, 31, 1) << 12)
+                    | (bits(insn, 7, 1) << 11)
+                    | (bits(insn, 25, 6) << 5)
+                    | (bits(insn, 8, 4) << 1);
+                [Some(pc.wrapping_add(sext(offset, 13))), Some(next)]
+            }
+            _ => [Some(next), None],
+        }
+    } else {
+        let rs1 = bits(insn, 7, 5);
+        match (bits(insn, 0, 2), bits(insn, 13, 3)) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = (bits(insn, 12, 1) << 11)
+                    | (bits(insn, 11, 1) << 4)
+                    | (bits(insn, 9, 2) << 8)
+                    | (bits(insn, 8, 1) << 10)
+                    | (bits(insn, 7, 1) << 6)
+                    | (bits(insn, 6, 1) << 7)
+                    | (bits(insn, 3, 3) << 1)
+                    | (bits(insn, 2, 1) << 5);
+                [Some(pc.wrapping_add(sext(offset, 12))), None]
+            }
+            // c.beqz, c.bnez
+            (0b01, 0b110 | 0b111) => {
+                let offset = (bits(insn, 12, 1) << 8)
+                    | (bits(insn, 10, 2) << 3)
+                    | (bits(insn, 5, 2) << 6)
+                    | (bits(insn, 3, 2) << 1)
+                    | (bits(insn, 2, 1) << 5);
+                [Some(pc.wrapping_add(sext(offset, 9))), Some(next)]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if bits(insn, 2, 5) == 0 && rs1 != 0 => [Some(tf.gpr(rs1) & !1), None],
+            _ => [Some(next), None],
+        }
+    };
+    Some(targets)
+}
+
+/// Returns the address of `va` in the kernel linear map.
+///
+/// The kernel half is mapped linearly with gigapages, which the page table
+/// walker does not follow, so only user addresses are translated.
+pub(crate) fn translate(va: usize) -> Option<usize> {
+    let va = VirtAddr(va);
+    if va.is_kernel() {
+        return Some(va.0);
+    }
+    let root = satp::read().ppn() << PAGE_SIZE_BITS;
+    let page_table = PageTable::<Riscv64PTEFlags>::from_token(root);
+    page_table.translate_va(va).map(|pa| phys_to_virt(pa).0)
+}
+
+/// Make written instructions visible to instruction fetch on this hart.
+#[inline]
+pub(crate) fn flush_icache() {
+    unsafe { core::arch::asm!("fence.i") };
+}
+
+/// Raise a breakpoint trap.
+#[inline(always)]
+pub(crate) fn breakpoint() {
+    unsafe { core::arch::asm!("ebreak") };
+}
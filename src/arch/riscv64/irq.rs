@@ -1,7 +1,10 @@
 use riscv::register::{
-    sie, sstatus,
+    sie, sip, sstatus,
     stvec::{self, TrapMode},
 };
+use sbi_rt::HartMask;
+
+use crate::cpumask::CpuMask;
 
 pub struct Irq;
 
@@ -81,6 +84,30 @@ impl Irq {
             sie::clear_sext();
         }
     }
+    /// IPIs are supervisor software interrupts.
+    pub fn enable_ipi() {
+        Irq::enable_software_interrupt();
+    }
+
+    /// Send an IPI to the harts set in `harts`.
+    pub fn send_ipi(harts: usize) -> bool {
+        sbi_rt::send_ipi(HartMask::from_mask_base(harts, 0)).is_ok()
+    }
+
+    /// Clear the pending IPI of the current hart.
+    pub fn clear_ipi() {
+        unsafe {
+            sip::clear_ssoft();
+        }
+    }
+
+    /// Wait for an interrupt, which wakes the hart up even if masked.
+    pub fn wait_for_interrupt() {
+        unsafe {
+            core::arch::asm!("wfi");
+        }
+    }
+
     pub fn get_trap_handler() -> usize {
         stvec::read().bits()
     }
@@ -100,21 +127,70 @@ impl Irq {
     }
 }
 
-/// Disable interrupt and resume to the interrupt state before when it gets
-/// dropped.
-
+/// An external interrupt claimed from the PLIC on the current hart.
 #[derive(Debug, Clone, Copy)]
 pub struct IRQVector(usize);
 
 impl IRQVector {
+    /// Claim the pending external interrupt of the current hart, if any.
+    pub fn claim() -> Option<Self> {
+        super::plic::claim().map(Self)
+    }
+
     /// Get the irq number in this vector
     #[inline]
     pub fn irq_num(&self) -> usize {
-        unimplemented!()
+        self.0
     }
 
     /// Acknowledge the irq
     pub fn irq_ack(&self) {
-        unimplemented!()
+        super::plic::complete(self.0);
+    }
+
+    /// Route `irq` to the current hart.
+    pub fn enable(irq: usize) -> bool {
+        super::plic::enable(irq, super::arch::hart_id())
+    }
+
+    /// Stop routing `irq` to any hart.
+    pub fn disable(irq: usize) -> bool {
+        super::plic::disable_all(irq)
+    }
+
+    /// Route `irq` to `hart` alone.
+    pub fn set_affinity(irq: usize, hart: usize) -> bool {
+        if !super::plic::enable(irq, hart) {
+            return false;
+        }
+        for other in CpuMask::all().iter().filter(|&other| other != hart) {
+            super::plic::disable(irq, other);
+        }
+        true
+    }
+
+    /// Set the priority of `irq`. Priority 0 never interrupts.
+    pub fn set_priority(irq: usize, priority: u32) -> bool {
+        super::plic::set_priority(irq, priority)
+    }
+
+    /// Returns the priority of `irq`.
+    pub fn priority(irq: usize) -> Option<u32> {
+        super::plic::priority(irq)
+    }
+
+    /// Set the priority threshold of `hart`.
+    pub fn set_threshold(hart: usize, threshold: u32) -> bool {
+        super::plic::set_threshold(hart, threshold)
+    }
+
+    /// Whether `irq` is pending.
+    pub fn is_pending(irq: usize) -> bool {
+        super::plic::is_pending(irq)
+    }
+
+    /// The PLIC gateways fix the trigger of every source.
+    pub fn set_trigger(_irq: usize, _edge: bool, _active_low: bool) -> bool {
+        false
     }
 }
@@ -0,0 +1,65 @@
+//! Load and store decoding for the misaligned access emulator.
+use super::trapframe::TrapRegs;
+use crate::misaligned::insn::riscv64::{decode_16, decode_32};
+use crate::misaligned::{Access, read_mem};
+
+#[cfg(feature = "fp")]
+use riscv::register::sstatus::FS;
+
+/// Fetch the instruction at the PC of `tf`, with its length.
+fn fetch(tf: &TrapRegs) -> Option<(u32, usize)> {
+    let mut low = [0u8; 2];
+    if !read_mem(tf, tf.pc(), &mut low) {
+        return None;
+    }
+    let low = u16::from_le_bytes(low) as u32;
+    if low & 0b11 != 0b11 {
+        return Some((low, 2));
+    }
+    let mut high = [0u8; 2];
+    if !read_mem(tf, tf.pc() + 2, &mut high) {
+        return None;
+    }
+    Some((low | ((u16::from_le_bytes(high) as u32) << 16), 4))
+}
+
+/// Decode the load or store at the PC of `tf`.
+pub(crate) fn decode(tf: &TrapRegs) -> Option<Access> {
+    let (insn, insn_len) = fetch(tf)?;
+    let (store, signed, width, reg, base, offset) = if insn_len == 4 {
+        decode_32(insn)?
+    } else {
+        decode_16(insn)?
+    };
+    Some(Access {
+        store,
+        signed,
+        width,
+        reg,
+        addr: tf.gpr(base).wrapping_add(offset),
+        insn_len,
+    })
+}
+
+#[cfg(feature = "fp")]
+crate::misaligned::fpr_access!(
+    0 => "f0", 1 => "f1", 2 => "f2", 3 => "f3", 4 => "f4", 5 => "f5", 6 => "f6", 7 => "f7",
+    8 => "f8", 9 => "f9", 10 => "f10", 11 => "f11", 12 => "f12", 13 => "f13", 14 => "f14",
+    15 => "f15", 16 => "f16", 17 => "f17", 18 => "f18", 19 => "f19", 20 => "f20", 21 => "f21",
+    22 => "f22", 23 => "f23", 24 => "f24", 25 => "f25", 26 => "f26", 27 => "f27", 28 => "f28",
+    29 => "f29", 30 => "f30", 31 => "f31",
+);
+
+/// Load `value` of `width` bytes into floating-point register `n`, and mark
+/// the floating-point state of the trapped context dirty.
+#[cfg(feature = "fp")]
+pub(crate) fn set_fpr(tf: &mut TrapRegs, n: usize, width: usize, value: u64) {
+    // Single-precision values are NaN-boxed in the 64-bit registers.
+    let value = if width == 4 {
+        value | 0xffff_ffff_0000_0000
+    } else {
+        value
+    };
+    write_fpr(n, value);
+    tf.sstatus.set_fs(FS::Dirty);
+}
@@ -1,6 +1,20 @@
 use crate::{arch::config::mm::{PAGE_MASK, PAGE_SIZE, PAGE_SIZE_BITS, PAGE_TABLE_LEVELS, PPN_WIDTH_SV39, PTES_PER_PAGE, PTE_SIZE, VA_WIDTH_SV39, VIRT_RAM_OFFSET, VPN_WIDTH_SV39}, {addr::{PhysAddr, PhysPageNum, VirtAddr}, pagetable::PageTableEntry}};
 use crate::addr::VirtPageNum;
 
+/// Returns the address of `pa` in the kernel linear map.
+#[inline]
+pub const fn phys_to_virt(pa: PhysAddr) -> VirtAddr {
+    VirtAddr(pa.0 + VIRT_RAM_OFFSET)
+}
+
+/// Returns the physical address behind `va`, which must lie in the kernel
+/// linear map.
+#[inline]
+pub const fn virt_to_phys(va: VirtAddr) -> PhysAddr {
+    debug_assert!(va.0 >= VIRT_RAM_OFFSET, "address is not in the linear map");
+    PhysAddr(va.0 - VIRT_RAM_OFFSET)
+}
+
 // PhysAddr implementations
 impl PhysAddr {
 
@@ -28,7 +42,7 @@ impl PhysAddr {
     }
 
     pub fn to_vaddr(&self) -> VirtAddr {
-        (self.bits() + VIRT_RAM_OFFSET).into()
+        phys_to_virt(*self)
     }
 }
 
@@ -72,14 +86,14 @@ impl PhysPageNum {
         self.0
     }
 
-    /// Get reference to `PhysPageNum` value
+    /// Get reference to the start of the frame through the linear map
     pub fn get_ref<T>(&self) -> &'static T {
-        unsafe { (self.0 as *const T).as_ref().unwrap() }
+        self.to_paddr().get_ref()
     }
 
-    /// Get mutable reference to `PhysAddr` value
+    /// Get mutable reference to the start of the frame through the linear map
     pub fn get_mut<T>(&self) -> &'static mut T {
-        unsafe { (self.0 as *mut T).as_mut().unwrap() }
+        self.to_paddr().get_mut()
     }
 
     pub fn to_paddr(&self) -> PhysAddr {
@@ -88,7 +102,7 @@ impl PhysPageNum {
 
     pub fn get_pte_array(&self) -> &'static mut [PageTableEntry] {
 
-        let vaddr: VirtAddr = self.to_paddr().to_vaddr();
+        let vaddr: VirtAddr = phys_to_virt(self.to_paddr());
         unsafe {
             core::slice::from_raw_parts_mut(vaddr.bits() as *mut PageTableEntry, PTES_PER_PAGE)
         }
@@ -109,12 +123,31 @@ impl VirtAddr {
 
     pub fn to_paddr(&self) -> Option<PhysAddr> {
         if self.bits() >= VIRT_RAM_OFFSET {
-            Some((self.bits() - VIRT_RAM_OFFSET).into())
+            Some(virt_to_phys(*self))
         } else {
            None
     }
     }
 
+    /// Sv39 addresses must have bits 63..39 equal to bit 38.
+    #[inline]
+    pub const fn is_canonical(&self) -> bool {
+        let high = self.0 as isize >> (VA_WIDTH_SV39 - 1);
+        high == 0 || high == -1
+    }
+
+    /// Whether the address is in the lower, user half.
+    #[inline]
+    pub const fn is_user(&self) -> bool {
+        self.0 as isize >> (VA_WIDTH_SV39 - 1) == 0
+    }
+
+    /// Whether the address is in the upper, kernel half.
+    #[inline]
+    pub const fn is_kernel(&self) -> bool {
+        self.0 as isize >> (VA_WIDTH_SV39 - 1) == -1
+    }
+
     pub fn is_null(&self) -> bool {
         self.0 == 0
     }
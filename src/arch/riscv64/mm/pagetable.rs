@@ -4,7 +4,7 @@ use crate::{
     arch::config::mm::{PAGE_SIZE, PAGE_TABLE_LEVELS, PPN_MASK, PPN_OFFSET_IN_PTE},
     bit,
     {
-        addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
+        addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, phys_to_virt},
         pagetable::{PTEFlags, PTOps, PageTableEntry},
     },
 };
@@ -113,7 +113,7 @@ impl PTOps for Riscv64PTEFlags {
     const PAGE_TABLE_LEVELS: usize = PAGE_TABLE_LEVELS;
     fn get_pte_array(ppn: PhysPageNum) -> &'static mut [PageTableEntry] {
         const PTES_PER_PAGE: usize = PAGE_SIZE / core::mem::size_of::<PageTableEntry>();
-        let va = Self::ppn_to_pa(ppn).to_vaddr().0;
+        let va = phys_to_virt(Self::ppn_to_pa(ppn)).0;
         unsafe { core::slice::from_raw_parts_mut(va as *mut PageTableEntry, PTES_PER_PAGE) }
     }
 
@@ -1,4 +1,9 @@
-use crate::{addr::VirtAddr, tlb::{TLBOperation, Tlb}};
+use crate::{
+    addr::{VirtAddr, VirtAddrRange},
+    cpumask::CpuMask,
+    tlb::{FLUSH_ALL_PAGES, TLBOperation, Tlb},
+};
+use sbi_rt::HartMask;
 
 impl TLBOperation for Tlb {
 
@@ -14,6 +19,32 @@ impl TLBOperation for Tlb {
             core::arch::riscv64::sfence_vma_all();
         }
     }
+
+    fn flush_range(asid: usize, range: VirtAddrRange) {
+        let pages = range.pages();
+        unsafe {
+            if pages.size() > FLUSH_ALL_PAGES {
+                core::arch::riscv64::sfence_vma_asid(asid);
+            } else {
+                for vpn in pages {
+                    core::arch::riscv64::sfence_vma(VirtAddr::from(vpn).0, asid);
+                }
+            }
+        }
+    }
+}
+
+/// Flush `range` of address space `asid` on the harts of `cpus` with SBI
+/// RFENCE. Returns `false` if the firmware does not support it.
+pub(crate) fn remote_flush_range(cpus: CpuMask, asid: usize, range: VirtAddrRange) -> bool {
+    // A size of `usize::MAX` flushes the whole address space.
+    let size = if range.pages().size() > FLUSH_ALL_PAGES {
+        usize::MAX
+    } else {
+        range.size()
+    };
+    let harts = HartMask::from_mask_base(cpus.bits(), 0);
+    sbi_rt::remote_sfence_vma_asid(harts, range.start.0, size, asid).is_ok()
 }
 
 
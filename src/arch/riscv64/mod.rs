@@ -3,10 +3,15 @@ pub mod config;
 pub mod console;
 pub mod irq;
 pub mod context;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
 pub mod macros;
+#[cfg(feature = "misaligned")]
+pub mod misaligned;
 pub mod mm;
+pub mod plic;
 pub mod time;
 pub mod trapframe;
+pub mod signal;
 pub mod trap;
-pub mod handler;
 pub mod arch;
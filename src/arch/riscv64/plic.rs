@@ -0,0 +1,243 @@
+//! Platform-Level Interrupt Controller.
+//!
+//! The PLIC is discovered from the device tree node compatible with
+//! `riscv,plic0` or `sifive,plic-1.0.0`. Its `interrupts-extended` property
+//! lists one context per hart and privilege mode, of which the supervisor
+//! external interrupt (`9`) contexts are used.
+//!
+//! Sources start disabled on every hart, with priority and thresholds 0.
+use alloc::vec::Vec;
+use fdt::Fdt;
+
+use crate::addr::{PhysAddr, phys_to_virt};
+use crate::arch::arch::hart_id;
+use crate::utils::{MutexNoIrq, OnceCell};
+
+const PRIORITY_BASE: usize = 0;
+const PENDING_BASE: usize = 0x1000;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0;
+const CONTEXT_CLAIM: usize = 4;
+
+/// The supervisor external interrupt of a hart's interrupt controller.
+const IRQ_S_EXT: u32 = 9;
+
+/// Priority set by [`enable`] for sources left at 0, which never interrupt.
+const DEFAULT_PRIORITY: u32 = 1;
+
+struct Plic {
+    base: usize,
+    /// Number of sources, which are numbered from 1.
+    ndev: usize,
+    /// Supervisor context of each hart, indexed by hart ID.
+    contexts: Vec<Option<usize>>,
+    /// Serializes read-modify-write of the enable bits.
+    enable_lock: MutexNoIrq,
+}
+
+static PLIC: OnceCell<Plic> = OnceCell::new();
+
+impl Plic {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { self.reg(offset).write_volatile(value) }
+    }
+
+    /// Offset of register `reg` of `context`.
+    fn context_reg(context: usize, reg: usize) -> usize {
+        CONTEXT_BASE + context * CONTEXT_STRIDE + reg
+    }
+
+    fn context(&self, hart: usize) -> Option<usize> {
+        self.contexts.get(hart).copied().flatten()
+    }
+
+    fn valid(&self, irq: usize) -> bool {
+        (1..=self.ndev).contains(&irq)
+    }
+
+    fn set_enabled(&self, irq: usize, context: usize, enabled: bool) {
+        let offset = ENABLE_BASE + context * ENABLE_STRIDE + irq / 32 * 4;
+        let _guard = self.enable_lock.lock();
+        let bits = self.read(offset);
+        let mask = 1 << (irq % 32);
+        self.write(offset, if enabled { bits | mask } else { bits & !mask });
+    }
+}
+
+/// Discover the PLIC from `fdt` and reset it. Without one, external
+/// interrupts are never claimed.
+pub(crate) fn init(fdt: Option<&Fdt>) {
+    let Some(fdt) = fdt else {
+        return;
+    };
+    let Some(node) = fdt.find_compatible(&["riscv,plic0", "sifive,plic-1.0.0"]) else {
+        log::warn!("PLIC not found in the device tree");
+        return;
+    };
+    let Some(reg) = node.reg().and_then(|mut reg| reg.next()) else {
+        return;
+    };
+    let ndev = node
+        .property("riscv,ndev")
+        .and_then(|p| p.as_usize())
+        .unwrap_or(0);
+
+    // Map the phandle of each hart's interrupt controller to its hart ID.
+    let mut intcs = Vec::new();
+    for cpu in fdt.find_all_nodes("/cpus/cpu") {
+        let Some(hart) = cpu.reg().and_then(|mut reg| reg.next()) else {
+            continue;
+        };
+        let phandle = cpu
+            .children()
+            .find(|child| child.name == "interrupt-controller")
+            .and_then(|intc| intc.property("phandle"))
+            .and_then(|p| p.as_usize());
+        if let Some(phandle) = phandle {
+            intcs.push((phandle, hart.starting_address as usize));
+        }
+    }
+
+    // `interrupts-extended` is a list of `<phandle irq>` pairs, one per context.
+    let mut contexts = Vec::new();
+    if let Some(prop) = node.property("interrupts-extended") {
+        for (context, pair) in prop.value.chunks_exact(8).enumerate() {
+            let phandle = u32::from_be_bytes(pair[..4].try_into().unwrap()) as usize;
+            let irq = u32::from_be_bytes(pair[4..].try_into().unwrap());
+            let Some(&(_, hart)) = intcs.iter().find(|(p, _)| *p == phandle) else {
+                continue;
+            };
+            if irq == IRQ_S_EXT {
+                if contexts.len() <= hart {
+                    contexts.resize(hart + 1, None);
+                }
+                contexts[hart] = Some(context);
+            }
+        }
+    }
+
+    let plic = Plic {
+        base: phys_to_virt(PhysAddr(reg.starting_address as usize)).0,
+        ndev,
+        contexts,
+        enable_lock: MutexNoIrq::new(()),
+    };
+    for context in plic.contexts.iter().flatten() {
+        for word in 0..=ndev / 32 {
+            plic.write(ENABLE_BASE + context * ENABLE_STRIDE + word * 4, 0);
+        }
+        plic.write(Plic::context_reg(*context, CONTEXT_THRESHOLD), 0);
+    }
+    log::info!(
+        "PLIC at {:#x}, {} sources, contexts {:?}",
+        reg.starting_address as usize,
+        ndev,
+        plic.contexts
+    );
+    PLIC.init(plic);
+}
+
+/// Set the priority of source `irq`. Sources with priority 0 never interrupt.
+pub fn set_priority(irq: usize, priority: u32) -> bool {
+    let Some(plic) = PLIC.try_get().filter(|plic| plic.valid(irq)) else {
+        return false;
+    };
+    plic.write(PRIORITY_BASE + irq * 4, priority);
+    true
+}
+
+/// Returns the priority of source `irq`.
+pub fn priority(irq: usize) -> Option<u32> {
+    let plic = PLIC.try_get().filter(|plic| plic.valid(irq))?;
+    Some(plic.read(PRIORITY_BASE + irq * 4))
+}
+
+/// Whether source `irq` is pending.
+pub fn is_pending(irq: usize) -> bool {
+    PLIC.try_get()
+        .filter(|plic| plic.valid(irq))
+        .is_some_and(|plic| plic.read(PENDING_BASE + irq / 32 * 4) & (1 << (irq % 32)) != 0)
+}
+
+/// Set the priority threshold of `hart`, which only takes sources of a higher
+/// priority.
+pub fn set_threshold(hart: usize, threshold: u32) -> bool {
+    let Some(plic) = PLIC.try_get() else {
+        return false;
+    };
+    let Some(context) = plic.context(hart) else {
+        return false;
+    };
+    plic.write(Plic::context_reg(context, CONTEXT_THRESHOLD), threshold);
+    true
+}
+
+/// Route source `irq` to `hart`. A source still at priority 0 is raised to
+/// the lowest priority that interrupts.
+pub fn enable(irq: usize, hart: usize) -> bool {
+    let Some(plic) = PLIC.try_get().filter(|plic| plic.valid(irq)) else {
+        return false;
+    };
+    let Some(context) = plic.context(hart) else {
+        return false;
+    };
+    if plic.read(PRIORITY_BASE + irq * 4) == 0 {
+        plic.write(PRIORITY_BASE + irq * 4, DEFAULT_PRIORITY);
+    }
+    plic.set_enabled(irq, context, true);
+    true
+}
+
+/// Stop routing source `irq` to `hart`.
+pub fn disable(irq: usize, hart: usize) -> bool {
+    let Some(plic) = PLIC.try_get().filter(|plic| plic.valid(irq)) else {
+        return false;
+    };
+    let Some(context) = plic.context(hart) else {
+        return false;
+    };
+    plic.set_enabled(irq, context, false);
+    true
+}
+
+/// Stop routing source `irq` to any hart.
+pub fn disable_all(irq: usize) -> bool {
+    let Some(plic) = PLIC.try_get().filter(|plic| plic.valid(irq)) else {
+        return false;
+    };
+    for context in plic.contexts.iter().flatten() {
+        plic.set_enabled(irq, *context, false);
+    }
+    true
+}
+
+/// Claim the highest priority pending source of the current hart, if any.
+pub fn claim() -> Option<usize> {
+    let plic = PLIC.try_get()?;
+    let context = plic.context(hart_id())?;
+    match plic.read(Plic::context_reg(context, CONTEXT_CLAIM)) {
+        0 => None,
+        irq => Some(irq as usize),
+    }
+}
+
+/// Signal that source `irq`, claimed on the current hart, has been handled.
+pub fn complete(irq: usize) {
+    let Some(plic) = PLIC.try_get() else {
+        return;
+    };
+    if let Some(context) = plic.context(hart_id()) {
+        plic.write(Plic::context_reg(context, CONTEXT_CLAIM), irq as u32);
+    }
+}
@@ -0,0 +1,221 @@
+//! Signal frames, laid out as the Linux riscv64 `sigcontext` with its
+//! extended contexts.
+#[cfg(feature = "rvv")]
+use super::config::board::MAX_VLENB;
+use super::trapframe::TrapFrame;
+#[cfg(feature = "rvv")]
+use super::trapframe::vlenb;
+use crate::addr::VirtAddr;
+#[cfg(feature = "rvv")]
+use crate::extable::{copy_from_user, copy_to_user};
+use crate::signal::{RtSigFrame, SignalDelivery, pop_frame, push_frame};
+#[cfg(feature = "rvv")]
+use crate::signal::{read_user, write_user};
+
+/// Magic of the vector extended context.
+#[cfg(feature = "rvv")]
+const RISCV_V_MAGIC: u32 = 0x5346_5457;
+
+/// `struct __riscv_ctx_hdr`, the header of every extended context. The list
+/// ends with a zero header.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CtxHdr {
+    magic: u32,
+    /// Size of the context, header included.
+    size: u32,
+}
+
+impl CtxHdr {
+    const END: Self = Self { magic: 0, size: 0 };
+}
+
+/// `union __riscv_fp_state`, sized for the Q extension and overlaid by
+/// `struct __riscv_extra_ext_header`. Only the D extension state is used.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct FpState {
+    f: [u64; 32],
+    fcsr: u32,
+    padding: [u32; 64],
+    /// Must be zero.
+    reserved: u32,
+    /// Header of the first extended context, whose body follows `sigcontext`.
+    hdr: CtxHdr,
+}
+
+/// `struct sigcontext`.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct MContext {
+    /// `pc` followed by `x1` to `x31`.
+    regs: [usize; 32],
+    fpregs: FpState,
+}
+
+/// `struct __riscv_v_ext_state`, the body of the vector extended context,
+/// followed by the `32 * vlenb` bytes of the registers.
+#[cfg(feature = "rvv")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct VState {
+    vstart: usize,
+    vl: usize,
+    vtype: usize,
+    vcsr: usize,
+    vlenb: usize,
+    /// Address of the registers.
+    datap: usize,
+}
+
+/// Size of the vector extended context for `vlenb`, header included.
+#[cfg(feature = "rvv")]
+const fn v_ctx_size(vlenb: usize) -> usize {
+    size_of::<CtxHdr>() + size_of::<VState>() + 32 * vlenb
+}
+
+impl TrapFrame {
+    /// Push a signal frame for `delivery` onto the user stack, and enter its
+    /// handler when returning to user mode. Contexts using the vector unit
+    /// get a vector extended context.
+    ///
+    /// Returns `false`, leaving the context unchanged, if the user stack is
+    /// not writable.
+    pub fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        let mut regs = self.user_x;
+        regs[0] = self.sepc;
+        // The live registers may be newer than the saved ones.
+        self.user_fx.encounter_signal();
+        #[cfg(feature = "rvv")]
+        let hdr = if self.user_vx.is_enabled(&self.sstatus) {
+            self.user_vx.save();
+            CtxHdr {
+                magic: RISCV_V_MAGIC,
+                size: v_ctx_size(vlenb()) as u32,
+            }
+        } else {
+            CtxHdr::END
+        };
+        #[cfg(not(feature = "rvv"))]
+        let hdr = CtxHdr::END;
+        let mut fpregs = FpState {
+            f: [0; 32],
+            fcsr: self.user_fx.fcsr,
+            padding: [0; 64],
+            reserved: 0,
+            hdr,
+        };
+        for (dst, src) in fpregs.f.iter_mut().zip(self.user_fx.user_fx) {
+            *dst = src.to_bits();
+        }
+
+        let sp = self.user_x[2];
+        let frame = RtSigFrame::new(delivery, sp, MContext { regs, fpregs });
+        // The body of the first context and the end header follow the frame.
+        let Some(addr) = push_frame(delivery, sp, &frame, hdr.size as usize) else {
+            return false;
+        };
+        #[cfg(feature = "rvv")]
+        if hdr.magic == RISCV_V_MAGIC
+            && !self.push_vector_context(addr + size_of::<RtSigFrame<MContext>>())
+        {
+            return false;
+        }
+        self.user_x[2] = addr;
+        self.user_x[1] = delivery.restorer;
+        self.user_x[10] = delivery.info.signo as usize;
+        self.user_x[11] = addr + core::mem::offset_of!(RtSigFrame<MContext>, info);
+        self.user_x[12] = addr + core::mem::offset_of!(RtSigFrame<MContext>, uc);
+        self.sepc = delivery.handler;
+        true
+    }
+
+    /// Write the body of the vector extended context at `body`, followed by
+    /// the end header.
+    #[cfg(feature = "rvv")]
+    fn push_vector_context(&self, body: usize) -> bool {
+        let len = 32 * vlenb();
+        let datap = body + size_of::<VState>();
+        let state = VState {
+            vstart: self.user_vx.vstart,
+            vl: self.user_vx.vl,
+            vtype: self.user_vx.vtype,
+            vcsr: self.user_vx.vcsr,
+            vlenb: vlenb(),
+            datap,
+        };
+        write_user(body, &state)
+            && unsafe { copy_to_user(datap as *mut u8, self.user_vx.v.as_ptr(), len) } == 0
+            && write_user(datap + len, &CtxHdr::END)
+    }
+
+    /// Restore the context saved by [`setup_sigframe`](Self::setup_sigframe)
+    /// from the frame at the user stack pointer, on `rt_sigreturn`.
+    ///
+    /// Returns the signal mask to restore, or `None`, leaving the context
+    /// unchanged, if the frame is unreadable or corrupt. The PC is restored as
+    /// well, so it must not be advanced past the system call afterwards.
+    pub fn restore_from_sigframe(&mut self) -> Option<u64> {
+        let sp = self.user_x[2];
+        let frame = pop_frame::<MContext>(sp)?;
+        let mcontext = &frame.uc.mcontext;
+        let hdr = mcontext.fpregs.hdr;
+        if mcontext.fpregs.reserved != 0 || !VirtAddr(mcontext.regs[0]).is_user() {
+            return None;
+        }
+        #[cfg(feature = "rvv")]
+        let vector = match hdr.magic {
+            0 if hdr.size == 0 => None,
+            RISCV_V_MAGIC => Some(read_vector_context(
+                sp + size_of::<RtSigFrame<MContext>>(),
+                hdr.size as usize,
+            )?),
+            _ => return None,
+        };
+        #[cfg(not(feature = "rvv"))]
+        if hdr.magic != 0 || hdr.size != 0 {
+            return None;
+        }
+
+        self.sepc = mcontext.regs[0];
+        self.user_x[1..].copy_from_slice(&mcontext.regs[1..]);
+        for (dst, src) in self.user_fx.user_fx.iter_mut().zip(mcontext.fpregs.f) {
+            *dst = f64::from_bits(src);
+        }
+        self.user_fx.fcsr = mcontext.fpregs.fcsr;
+        self.user_fx.need_restore = 1;
+        self.user_fx.restore();
+        #[cfg(feature = "rvv")]
+        if let Some((state, v)) = vector {
+            self.user_vx.v = v;
+            self.user_vx.vstart = state.vstart;
+            self.user_vx.vl = state.vl;
+            self.user_vx.vtype = state.vtype;
+            self.user_vx.vcsr = state.vcsr;
+            // Loaded when returning to user mode, the live registers are
+            // stale.
+            self.user_vx.need_save = 0;
+            self.user_vx.enable(&mut self.regs.sstatus);
+        }
+        Some(frame.uc.sigmask)
+    }
+}
+
+/// Read the vector extended context of `size` bytes whose body is at `body`,
+/// checking that the end header follows.
+#[cfg(feature = "rvv")]
+fn read_vector_context(body: usize, size: usize) -> Option<(VState, [u8; 32 * MAX_VLENB])> {
+    let vlenb = vlenb();
+    if vlenb == 0 || size != v_ctx_size(vlenb) {
+        return None;
+    }
+    let state = read_user::<VState>(body)?;
+    let end = read_user::<CtxHdr>(body - size_of::<CtxHdr>() + size)?;
+    if end.magic != 0 || end.size != 0 {
+        return None;
+    }
+    // The handler may have pointed `datap` elsewhere.
+    let mut v = [0; 32 * MAX_VLENB];
+    let ret = unsafe { copy_from_user(v.as_mut_ptr(), state.datap as *const u8, 32 * vlenb) };
+    (ret == 0).then_some((state, v))
+}
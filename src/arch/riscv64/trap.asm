@@ -62,9 +62,10 @@ __trap_from_user:
     ld s10, 46*8(sp)
     ld s11, 47*8(sp)
 
-    # Load kernel frame pointer (fp) and thread pointer (tp)
+    # Load kernel frame pointer (fp), thread pointer (tp) and per-CPU base (gp)
     ld fp, 48*8(sp)
     ld tp, 49*8(sp)
+    ld gp, 50*8(sp)
 
     # Finally, load the kernel stack pointer from the TrapContext
     ld sp, 34*8(sp)
@@ -95,6 +96,7 @@ __return_to_user:
     sd s11, 47*8(a0)   # Save callee-saved register s11
     sd fp, 48*8(a0)    # Save frame pointer
     sd tp, 49*8(a0)    # Save thread pointer
+    sd gp, 50*8(a0)    # Save per-CPU base
 
     # Move sp to point to TrapContext in kernel space
     mv sp, a0
@@ -116,48 +118,48 @@ __return_to_user:
     sret
 
 # kernel -> kernel
+# Builds the TrapRegs of a TrapFrame below the interrupted stack pointer, so
+# handlers can inspect and modify every register.
 __trap_from_kernel:
-    # only need to save caller-saved regs
-    # note that we don't save sepc & stvec here
     csrrw   sp, sscratch, sp
     bnez    sp, __trap_from_user
-    csrr    sp, sscratch
-    addi sp, sp, -17*8
-    sd  ra,  1*8(sp)
-    sd  t0,  2*8(sp)
-    sd  t1,  3*8(sp)
-    sd  t2,  4*8(sp)
-    sd  t3,  5*8(sp)
-    sd  t4,  6*8(sp)
-    sd  t5,  7*8(sp)
-    sd  t6,  8*8(sp)
-    sd  a0,  9*8(sp)
-    sd  a1, 10*8(sp)
-    sd  a2, 11*8(sp)
-    sd  a3, 12*8(sp)
-    sd  a4, 13*8(sp)
-    sd  a5, 14*8(sp)
-    sd  a6, 15*8(sp)
-    sd  a7, 16*8(sp)
-    csrw    sscratch, x0
-    call kernel_trap_handler
-    ld  ra,  1*8(sp)
-    ld  t0,  2*8(sp)
-    ld  t1,  3*8(sp)
-    ld  t2,  4*8(sp)
-    ld  t3,  5*8(sp)
-    ld  t4,  6*8(sp)
-    ld  t5,  7*8(sp)
-    ld  t6,  8*8(sp)
-    ld  a0,  9*8(sp)
-    ld  a1, 10*8(sp)
-    ld  a2, 11*8(sp)
-    ld  a3, 12*8(sp)
-    ld  a4, 13*8(sp)
-    ld  a5, 14*8(sp)
-    ld  a6, 15*8(sp)
-    ld  a7, 16*8(sp)
-    addi sp, sp, 17*8
+    # The frame may be too large for an immediate, borrow t0 to size it.
+    csrrw   sp, sscratch, t0
+    li      t0, {trap_frame_size}
+    sub     sp, sp, t0
+    csrrw   t0, sscratch, x0
+    sd x1, 1*8(sp)
+    .set n, 3
+    .rept 29
+        SAVE_GP %n
+        .set n, n+1
+    .endr
+    # Save the interrupted stack pointer
+    li t0, {trap_frame_size}
+    add t0, sp, t0
+    sd t0, 2*8(sp)
+    csrr t0, sstatus
+    csrr t1, sepc
+    sd t0, 32*8(sp)
+    sd t1, 33*8(sp)
+
+    mv a0, sp
+    call kernel_trap_entry
+
+    # A nested trap may have overwritten sepc and sstatus.SPP/SPIE, restore
+    # them from the frame. Its SIE bit is clear, so interrupts stay disabled
+    # until sret.
+    ld t0, 32*8(sp)
+    ld t1, 33*8(sp)
+    csrw sstatus, t0
+    csrw sepc, t1
+    ld x1, 1*8(sp)
+    .set n, 3
+    .rept 29
+        LOAD_GP %n
+        .set n, n+1
+    .endr
+    ld sp, 2*8(sp)
     sret
 
 # arg: (user_ptr)
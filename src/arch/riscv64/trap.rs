@@ -1,13 +1,24 @@
 use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use riscv::{interrupt::{supervisor, Exception, Trap}, register::{scause, sepc, sstatus, stval}};
+#[cfg(feature = "fp")]
+use riscv::register::sstatus::FS;
 
-use super::{handler::{self, TrapType}, time::set_next_timer_irq, trapframe::{self, TrapFrame}};
+use super::{time::set_next_timer_irq, trapframe::{self, TrapFrame, TrapRegs}};
+use crate::extable::search_exception_table;
+use crate::softirq::run_on_interrupt_exit;
+use crate::stats::record_trap;
+use crate::trap::{handle_trap, AccessType, PageFaultInfo, TrapKind};
 
-use super::irq::Irq;
+use super::irq::{IRQVector, Irq};
 
 
-global_asm!(include_str!("trap.asm"));
+global_asm!(
+    include_str!("trap.asm"),
+    trap_frame_size = const core::mem::size_of::<TrapRegs>(),
+);
+global_asm!(include_str!("uaccess.asm"));
 
 unsafe extern "C" {
     fn __trap_from_user();
@@ -15,6 +26,31 @@ unsafe extern "C" {
     fn __return_to_user(cx: *mut TrapFrame);
 }
 
+/// Whether exception handlers run with interrupts enabled.
+static NESTED_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// Let interrupts pre-empt exception handling, e.g. of page faults. Only
+/// available on RISC-V.
+///
+/// Off by default. Interrupt handlers always run with interrupts disabled, as
+/// the pending interrupt would trap again right away.
+pub fn set_nested_interrupts(enable: bool) {
+    NESTED_INTERRUPTS.store(enable, Ordering::Relaxed);
+}
+
+/// Run `f` with interrupts enabled if nesting is on and `kind` is an
+/// exception. Interrupts are disabled again before returning, as the trap
+/// return path must not be interrupted.
+fn with_nesting<T>(kind: &TrapKind, f: impl FnOnce() -> T) -> T {
+    if kind.is_interrupt() || !NESTED_INTERRUPTS.load(Ordering::Relaxed) {
+        return f();
+    }
+    unsafe { Irq::enable_interrupt() };
+    let ret = f();
+    unsafe { Irq::disable_interrupt() };
+    ret
+}
+
 pub fn init() {
     unsafe { set_kernel_trap() };
 }
@@ -24,38 +60,76 @@ pub unsafe fn set_kernel_trap() {
 }
 
 
-pub fn run_user_task(context: &mut trapframe::TrapFrame) -> TrapType {
-    unsafe {
-        __return_to_user(context);
+pub fn run_user_task(context: &mut trapframe::TrapFrame) -> TrapKind {
+    loop {
+        // Contexts that never used the floating-point registers skip loading
+        // them.
+        #[cfg(feature = "fp")]
+        if context.sstatus.fs() != FS::Off {
+            context.user_fx.restore();
+        }
+        #[cfg(feature = "rvv")]
+        if context.user_vx.is_enabled(&context.regs.sstatus) {
+            context.user_vx.restore();
+        }
+        unsafe {
+            __return_to_user(context);
+        }
+        // user trap arrive here
+        #[cfg(feature = "fp")]
+        {
+            context.user_fx.mark_save_if_needed(&mut context.regs.sstatus);
+            // The first floating-point instruction of the context, retry it.
+            if context.sstatus.fs() == FS::Off
+                && scause::read().cause() == Trap::Exception(Exception::IllegalInstruction as usize)
+            {
+                context.user_fx.enable(&mut context.regs.sstatus);
+                continue;
+            }
+        }
+        #[cfg(feature = "rvv")]
+        {
+            context.user_vx.mark_save_if_needed(&mut context.regs.sstatus);
+            // The first vector instruction of the context, retry it.
+            if !context.user_vx.is_enabled(&context.regs.sstatus)
+                && scause::read().cause() == Trap::Exception(Exception::IllegalInstruction as usize)
+                && context.user_vx.enable(&mut context.regs.sstatus)
+            {
+                continue;
+            }
+        }
+        // Misaligned accesses are done on behalf of the task, resume it.
+        #[cfg(feature = "misaligned")]
+        if matches!(
+            scause::read().cause(),
+            Trap::Exception(e) if e == Exception::LoadMisaligned as usize
+                || e == Exception::StoreMisaligned as usize
+        ) && crate::misaligned::emulate(context)
+        {
+            continue;
+        }
+        return trap_handler(context);
     }
-    // user trap arrive here
-    trap_handler(context)
 }
 
-pub fn trap_handler(cx: &mut TrapFrame) -> TrapType {
+pub fn trap_handler(cx: &mut TrapFrame) -> TrapKind {
     let scause = scause::read();
     let stval = stval::read();
-    let sepc = sepc::read();
     let cause = scause.cause();
+    let mut vector = None;
 
-    // unsafe { enable_interrupt() };
-
-    let trap_type = match cause.try_into() {
+    let trap_kind = match cause.try_into() {
         Ok(Trap::Exception(e)) => match e {
             Exception::Breakpoint => {
                 cx.sepc += 2;
-                TrapType::Breakpoint
-            }
-            Exception::UserEnvCall => TrapType::SysCall,
-            Exception::StorePageFault => TrapType::StorePageFault(stval),
-            Exception::InstructionPageFault => TrapType::InstructionPageFault(stval),
-            Exception::LoadPageFault => TrapType::LoadPageFault(stval),
-            Exception::IllegalInstruction => {
-                TrapType::IllegalInstruction(stval)
+                TrapKind::Breakpoint
             }
             e => {
-                log::warn!("Unknown user exception: {:?}", e);
-                TrapType::Unknown
+                let kind = exception_kind(e, stval);
+                if kind == TrapKind::Unknown {
+                    log::warn!("Unknown user exception: {:?}", e);
+                }
+                kind
             }
         },
 
@@ -65,13 +139,12 @@ pub fn trap_handler(cx: &mut TrapFrame) -> TrapType {
                     // NOTE: User may trap into kernel frequently. As a consequence, this timer are
                     // likely not triggered in user mode but rather be triggered in supervisor mode,
                     // which will cause user program running on the cpu for a quite long time.
-                    TrapType::Timer
+                    TrapKind::Timer
                 }
-                supervisor::Interrupt::SupervisorExternal => TrapType::SupervisorExternal,
-                _ => {
-                    panic!(
-                        "[trap_handler] Unsupported trap {cause:?}, stval = {stval:#x}!, sepc = {sepc:#x}"
-                    );
+                supervisor::Interrupt::SupervisorExternal => external_irq(&mut vector),
+                supervisor::Interrupt::SupervisorSoft => {
+                    Irq::clear_ipi();
+                    TrapKind::Ipi
                 }
             }
         }
@@ -82,11 +155,52 @@ pub fn trap_handler(cx: &mut TrapFrame) -> TrapType {
             );
         }
     };
-    handler::specific_handler(cx, trap_type, 0);
-    trap_type
+    record_trap(&trap_kind);
+    with_nesting(&trap_kind, || handle_trap(cx, &trap_kind, |_| false));
+    if let Some(vector) = vector {
+        vector.irq_ack();
+    }
+    if trap_kind.is_interrupt() {
+        run_on_interrupt_exit();
+    }
+    trap_kind
+}
+
+/// Claim the pending external interrupt into `vector`, to be completed once
+/// the handlers ran. Spurious claims are reported as IRQ 0.
+fn external_irq(vector: &mut Option<IRQVector>) -> TrapKind {
+    *vector = IRQVector::claim();
+    TrapKind::Irq(vector.map_or(0, |vector| vector.irq_num()))
+}
+
+/// Classify the exceptions that are reported the same way from user and kernel mode.
+fn exception_kind(e: Exception, stval: usize) -> TrapKind {
+    let page_fault = |access| {
+        TrapKind::PageFault(PageFaultInfo {
+            addr: stval,
+            access,
+            protection: false,
+        })
+    };
+    let address_error = |access| TrapKind::AddressError { addr: stval, access };
+    match e {
+        Exception::UserEnvCall => TrapKind::SysCall,
+        Exception::Breakpoint => TrapKind::Breakpoint,
+        Exception::IllegalInstruction => TrapKind::IllegalInstruction(stval),
+        Exception::LoadPageFault => page_fault(AccessType::Read),
+        Exception::StorePageFault => page_fault(AccessType::Write),
+        Exception::InstructionPageFault => page_fault(AccessType::Execute),
+        Exception::LoadMisaligned | Exception::LoadFault => address_error(AccessType::Read),
+        Exception::StoreMisaligned | Exception::StoreFault => address_error(AccessType::Write),
+        Exception::InstructionMisaligned | Exception::InstructionFault => {
+            address_error(AccessType::Execute)
+        }
+        _ => TrapKind::Unknown,
+    }
 }
 
 pub fn panic_on_unknown_trap() {
+    crate::backtrace::backtrace();
     panic!(
         "[kernel] sstatus sum {}, {:?}(scause:{}) in application, bad addr = {:#x}, bad instruction = {:#x}, kernel panicked!!",
         sstatus::read().sum(),
@@ -97,40 +211,67 @@ pub fn panic_on_unknown_trap() {
     );
 }
 
-pub fn kernel_trap_handler() -> TrapType {
+/// Resume a faulting user access routine at its exception table fixup.
+fn resume_at_fixup(tf: &mut TrapRegs) -> bool {
+    search_exception_table(tf.sepc)
+        .map(|fixup| tf.sepc = fixup)
+        .is_some()
+}
+
+/// Called by `__trap_from_kernel` with the frame it built on the stack.
+#[unsafe(no_mangle)]
+extern "C" fn kernel_trap_entry(tf: &mut TrapRegs) {
+    kernel_trap_handler(tf);
+}
+
+pub fn kernel_trap_handler(tf: &mut TrapRegs) -> TrapKind {
     let stval = stval::read();
     let scause = scause::read();
-    let sepc = sepc::read();
+    let sepc = tf.sepc;
     let trap = scause.cause();
-    match trap.try_into() {
+    let mut vector = None;
+    let kind = match trap.try_into() {
         Ok(Trap::Interrupt(i)) => match i {
-            supervisor::Interrupt::SupervisorExternal => TrapType::SupervisorExternal,
+            supervisor::Interrupt::SupervisorExternal => external_irq(&mut vector),
+            supervisor::Interrupt::SupervisorSoft => {
+                Irq::clear_ipi();
+                TrapKind::Ipi
+            }
             supervisor::Interrupt::SupervisorTimer => {
-                // log::error!("[kernel_trap] receive timer interrupt");
-                TIMER_MANAGER.check();
+                // Timers run in the handlers of `TrapClass::Timer`.
                 unsafe { set_next_timer_irq() };
-                TrapType::Timer
+                TrapKind::Timer
             }
-            _ => TrapType::Unknown,
         },
-        Ok(Trap::Exception(e)) => match e {
-            Exception::StorePageFault
-            | Exception::InstructionPageFault
-            | Exception::LoadPageFault => {
+        Ok(Trap::Exception(e)) => {
+            let kind = exception_kind(e, stval);
+            if matches!(kind, TrapKind::PageFault(_)) {
                 log::info!(
                     "[trap_handler] encounter page fault, addr {stval:#x}, instruction {sepc:#x} cause {:?}",
                     e,
                 );
-
-                match e {
-                    Exception::StorePageFault => TrapType::StorePageFault(stval),
-                    Exception::InstructionPageFault => TrapType::InstructionPageFault(stval),
-                    Exception::LoadPageFault => TrapType::LoadPageFault(stval),
-                    _ => TrapType::Unknown,
-                }
             }
-            _ => TrapType::Unknown,
-        },
-        Err(_) => TrapType::Unknown,
+            kind
+        }
+        Err(_) => TrapKind::Unknown,
+    };
+    record_trap(&kind);
+    // A user access routine hit a bad address, resume at its fixup.
+    if matches!(kind, TrapKind::AddressError { .. }) && resume_at_fixup(tf) {
+        return kind;
+    }
+    // Page faults go to their handlers first, the fixup only takes the ones
+    // no handler resolved.
+    with_nesting(&kind, || {
+        handle_trap(tf, &kind, |tf| {
+            matches!(kind, TrapKind::PageFault(_)) && resume_at_fixup(tf)
+        })
+    });
+    if let Some(vector) = vector {
+        vector.irq_ack();
+    }
+    if kind.is_interrupt() {
+        run_on_interrupt_exit();
     }
+    kind
 }
\ No newline at end of file
@@ -1,19 +1,40 @@
 
-use core::{arch::asm, ops::{Index, IndexMut}};
+use core::{arch::asm, ops::{Deref, DerefMut, Index, IndexMut}};
+#[cfg(feature = "rvv")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use riscv::register::sstatus::{self, FS, SPP, Sstatus};
 
+#[cfg(feature = "rvv")]
+use super::config::board::MAX_VLENB;
 
+use crate::trap::{TrapFrameArgs, UserContext};
+
+
+/// Registers saved on every trap, the whole frame of kernel mode traps and
+/// what trap handlers see. A [`TrapFrame`] starts with them and dereferences
+/// to them.
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
-pub struct TrapFrame {
-    // NOTE:  User to kernel should save these:
+pub struct TrapRegs {
     /// General regs from x0 to x31.
     pub user_x: [usize; 32],
     /// CSR sstatus
     pub sstatus: Sstatus, // 32
     /// CSR sepc
     pub sepc: usize, // 33
+}
+
+// `trap.asm` saves the kernel registers of a user context right after them.
+const _: () = assert!(size_of::<TrapRegs>() == 34 * 8);
+
+/// A user context. The floating-point and vector state is only saved here,
+/// not by kernel mode traps.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    // NOTE:  User to kernel should save these:
+    pub regs: TrapRegs, // 0 - 33
 
     // NOTE: Kernel to user should save these:
     pub kernel_sp: usize, // 34
@@ -25,10 +46,15 @@ pub struct TrapFrame {
     pub kernel_fp: usize, // 48
     /// kernel hart address
     pub kernel_tp: usize, // 49
+    /// Per-CPU data area base, which user code may clobber
+    pub kernel_gp: usize, // 50
     /// Float regs
     pub user_fx: UserFloatContext,
 
     pub last_a0: usize,
+    /// Vector regs
+    #[cfg(feature = "rvv")]
+    pub user_vx: UserVectorContext,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -48,10 +74,17 @@ impl TrapFrame {
         sstatus.set_spp(SPP::User);
         sstatus.set_sie(false);
         sstatus.set_spie(false);
+        // Enabled on first use, see `UserFloatContext`.
+        #[cfg(feature = "fp")]
+        sstatus.set_fs(FS::Off);
+        #[cfg(feature = "rvv")]
+        set_vs(&mut sstatus, VS_OFF);
         let mut cx = Self {
-            user_x: [0; 32],
-            sstatus,
-            sepc: entry,
+            regs: TrapRegs {
+                user_x: [0; 32],
+                sstatus,
+                sepc: entry,
+            },
             // The following regs will be stored in asm funciton __restore
             // So we don't need to save them here
             kernel_sp: 0,
@@ -60,17 +93,128 @@ impl TrapFrame {
             kernel_fp: 0,
             // We will give the right kernel tp in `__return_to_user`
             kernel_tp: 0,
+            kernel_gp: 0,
             user_fx: UserFloatContext::new(),
             last_a0: 0,
+            #[cfg(feature = "rvv")]
+            user_vx: UserVectorContext::new(),
         };
         *cx.index_mut(TrapFrameArgs::SP)=sp;
         cx
     }
 
+    // The methods below forward to `UserContext`, for callers that do not
+    // import the trait.
+
+    /// See [`UserContext::init_user`].
+    #[inline]
+    pub fn init_user(
+        &mut self,
+        user_sp: usize,
+        sepc: usize,
+        argc: usize,
+        argv: usize,
+        envp: usize,
+    ) {
+        UserContext::init_user(self, user_sp, sepc, argc, argv, envp);
+    }
+
+    /// See [`UserContext::syscall_args`].
+    #[inline]
+    pub fn syscall_args(&self) -> [usize; 6] {
+        UserContext::syscall_args(self)
+    }
+
+    /// See [`UserContext::save_last_user_a0`].
+    #[inline]
+    pub fn save_last_user_a0(&mut self) {
+        UserContext::save_last_user_a0(self);
+    }
+
+    /// See [`UserContext::restore_last_user_a0`].
+    #[inline]
+    pub fn restore_last_user_a0(&mut self) {
+        UserContext::restore_last_user_a0(self);
+    }
+
+    /// See [`UserContext::set_entry_point`].
+    #[inline]
+    pub fn set_entry_point(&mut self, entry: usize) {
+        UserContext::set_entry_point(self, entry);
+    }
+
+    /// See [`UserContext::syscall_ok`].
+    #[inline]
+    pub fn syscall_ok(&mut self) {
+        UserContext::syscall_ok(self);
+    }
+}
+
+impl Deref for TrapFrame {
+    type Target = TrapRegs;
+
+    #[inline]
+    fn deref(&self) -> &TrapRegs {
+        &self.regs
+    }
+}
+
+impl DerefMut for TrapFrame {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut TrapRegs {
+        &mut self.regs
+    }
+}
+
+impl TrapRegs {
+    /// Address of the trapping instruction.
+    #[inline]
+    pub fn pc(&self) -> usize {
+        self.sepc
+    }
+
+    /// Frame pointer (`s0`) at the time of the trap.
+    #[inline]
+    pub fn fp(&self) -> usize {
+        self.user_x[8]
+    }
+
+    /// Resume execution at `pc`.
+    #[inline]
+    pub fn set_pc(&mut self, pc: usize) {
+        self.sepc = pc;
+    }
+
+    /// Whether the trap was taken from user mode.
+    #[inline]
+    pub fn is_user(&self) -> bool {
+        self.sstatus.spp() == SPP::User
+    }
+
+    /// Returns general-purpose register `x<n>`.
+    #[inline]
+    pub fn gpr(&self, n: usize) -> usize {
+        self.user_x[n]
+    }
+
+    /// Set general-purpose register `x<n>`. Writes to `x0` are ignored.
+    #[inline]
+    pub fn set_gpr(&mut self, n: usize, value: usize) {
+        if n != 0 {
+            self.user_x[n] = value;
+        }
+    }
+}
+
+impl UserContext for TrapFrame {
+    fn new_user(entry: usize, sp: usize) -> Self {
+        Self::new(entry, sp)
+    }
+
     // NOTE: this function must not update `Sstatus` field using `sstatus::read()`,
     // otherwise, interrupt will be triggered in `__return_to_user`, which will mess
     // up user registers .
-    pub fn init_user(
+    fn init_user(
         &mut self,
         user_sp: usize,
         sepc: usize,
@@ -83,12 +227,17 @@ impl TrapFrame {
         self.user_x[11] = argv;
         self.user_x[12] = envp;
         self.sepc = sepc;
-        self.user_fx = UserFloatContext::new()
+        self.user_fx = UserFloatContext::new();
+        #[cfg(feature = "fp")]
+        self.sstatus.set_fs(FS::Off);
+        #[cfg(feature = "rvv")]
+        {
+            self.user_vx = UserVectorContext::new();
+            set_vs(&mut self.sstatus, VS_OFF);
+        }
     }
 
-    /// Syscall number
-
-    pub fn syscall_args(&self) -> [usize; 6] {
+    fn syscall_args(&self) -> [usize; 6] {
         [
             self.user_x[10],
             self.user_x[11],
@@ -99,39 +248,16 @@ impl TrapFrame {
         ]
     }
 
-    /// Set stack pointer to x_2 reg (sp)
-    
-    pub fn save_last_user_a0(&mut self) {
+    fn save_last_user_a0(&mut self) {
         self.last_a0 = self.user_x[10];
     }
 
-    pub fn restore_last_user_a0(&mut self) {
+    fn restore_last_user_a0(&mut self) {
         self.user_x[10] = self.last_a0;
     }
-
-    /// Set entry point
-    pub fn set_entry_point(&mut self, entry: usize) {
-        self.sepc = entry;
-    }
-
-    pub fn syscall_ok(&mut self) {
-        self.sepc += 4;
-    }
 }
 
-pub enum TrapFrameArgs {
-    SEPC,
-    RA,
-    SP,
-    RET,
-    ARG0,
-    ARG1,
-    ARG2,
-    TLS,
-    SYSCALL,
-}
-
-impl Index<TrapFrameArgs> for TrapFrame {
+impl Index<TrapFrameArgs> for TrapRegs {
     type Output = usize;
 
     fn index(&self, index: TrapFrameArgs) -> &Self::Output {
@@ -149,7 +275,7 @@ impl Index<TrapFrameArgs> for TrapFrame {
     }
 }
 
-impl IndexMut<TrapFrameArgs> for TrapFrame {
+impl IndexMut<TrapFrameArgs> for TrapRegs {
     fn index_mut(&mut self, index: TrapFrameArgs) -> &mut Self::Output {
         match index {
             TrapFrameArgs::SEPC => &mut self.sepc,
@@ -165,14 +291,45 @@ impl IndexMut<TrapFrameArgs> for TrapFrame {
     }
 }
 
+impl Index<TrapFrameArgs> for TrapFrame {
+    type Output = usize;
+
+    fn index(&self, index: TrapFrameArgs) -> &Self::Output {
+        &self.regs[index]
+    }
+}
+
+impl IndexMut<TrapFrameArgs> for TrapFrame {
+    fn index_mut(&mut self, index: TrapFrameArgs) -> &mut Self::Output {
+        &mut self.regs[index]
+    }
+}
+
 impl UserFloatContext {
     pub fn new() -> Self {
         unsafe { core::mem::zeroed() }
     }
 
-    pub fn mark_save_if_needed(&mut self, need_save: u8) {
-       unimplemented!();
+    /// Called on every trap from user mode. If the user context dirtied the
+    /// registers, as tracked by the `FS` field of its `sstatus`, they are
+    /// saved before another context may use them. `FS` is set back to clean
+    /// so the next change is noticed.
+    pub fn mark_save_if_needed(&mut self, sstatus: &mut Sstatus) {
+        if sstatus.fs() == FS::Dirty {
+            self.need_save = 1;
+            sstatus.set_fs(FS::Clean);
+        }
+    }
+
+    /// Called when a context with `FS` off, which has not used the
+    /// registers yet, traps on its first floating-point instruction. Its
+    /// registers are loaded when returning to user mode, and the instruction
+    /// is retried.
+    pub fn enable(&mut self, sstatus: &mut Sstatus) {
+        sstatus.set_fs(FS::Clean);
+        self.need_restore = 1;
     }
+
     pub fn yield_task(&mut self) {
         self.save();
         self.need_restore = 1;
@@ -237,6 +394,8 @@ impl UserFloatContext {
             return;
         }
         self.need_restore = 0;
+        // The kernel runs with the `FS` of the last user context.
+        unsafe { sstatus::set_fs(FS::Clean) };
         unsafe {
             asm!("
             fld  f0,  0*8({0})
@@ -278,3 +437,208 @@ impl UserFloatContext {
         }
     }
 }
+
+/// Vector regs and CSRs, switched lazily like [`UserFloatContext`] as tracked
+/// by the `VS` field of `sstatus`.
+///
+/// Only the first `32 * vlenb` bytes of `v` are used, see [`vlenb`]. Signal
+/// frames carry it in a vector extended context.
+#[cfg(feature = "rvv")]
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct UserVectorContext {
+    /// `v0` to `v31`, `vlenb` bytes each.
+    pub v: [u8; 32 * MAX_VLENB],
+    pub vstart: usize,
+    pub vl: usize,
+    pub vtype: usize,
+    pub vcsr: usize,
+    pub need_save: u8,
+    pub need_restore: u8,
+}
+
+/// The `VS` field of `sstatus`, which [`Sstatus`] masks out.
+#[cfg(feature = "rvv")]
+const SSTATUS_VS: usize = 0b11 << 9;
+#[cfg(feature = "rvv")]
+const VS_OFF: usize = 0;
+#[cfg(feature = "rvv")]
+const VS_CLEAN: usize = 0b10 << 9;
+#[cfg(feature = "rvv")]
+const VS_DIRTY: usize = 0b11 << 9;
+
+#[cfg(feature = "rvv")]
+fn vs(sstatus: &Sstatus) -> usize {
+    // `Sstatus` is a `repr(C)` wrapper of the raw CSR value.
+    unsafe { *(sstatus as *const Sstatus).cast::<usize>() & SSTATUS_VS }
+}
+
+#[cfg(feature = "rvv")]
+fn set_vs(sstatus: &mut Sstatus, vs: usize) {
+    let bits = unsafe { &mut *(sstatus as *mut Sstatus).cast::<usize>() };
+    *bits = (*bits & !SSTATUS_VS) | vs;
+}
+
+/// Set `VS` of the live `sstatus`, which the kernel otherwise runs with as
+/// the last user context left it.
+#[cfg(feature = "rvv")]
+unsafe fn set_live_vs(vs: usize) {
+    unsafe {
+        asm!(
+            "csrc sstatus, {mask}",
+            "csrs sstatus, {vs}",
+            mask = in(reg) SSTATUS_VS,
+            vs = in(reg) vs,
+        );
+    }
+}
+
+/// Returns `vlenb` of the harts, or 0 if they have no vector unit or one
+/// with longer vectors than [`MAX_VLENB`]. All harts are assumed to match.
+#[cfg(feature = "rvv")]
+pub fn vlenb() -> usize {
+    static VLENB: AtomicUsize = AtomicUsize::new(usize::MAX);
+    let mut vlenb = VLENB.load(Ordering::Relaxed);
+    if vlenb == usize::MAX {
+        vlenb = probe_vlenb();
+        VLENB.store(vlenb, Ordering::Relaxed);
+    }
+    vlenb
+}
+
+#[cfg(feature = "rvv")]
+fn probe_vlenb() -> usize {
+    // `VS` is hardwired to zero without the vector extension, and `vlenb`
+    // is only accessible while it is on.
+    let sstatus: usize;
+    unsafe {
+        set_live_vs(VS_CLEAN);
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    if sstatus & SSTATUS_VS == 0 {
+        return 0;
+    }
+    let vlenb: usize;
+    unsafe { asm!("csrr {}, 0xc22", out(reg) vlenb) };
+    if vlenb > MAX_VLENB {
+        log::warn!("vlenb {vlenb} exceeds MAX_VLENB, vector extension disabled");
+        return 0;
+    }
+    vlenb
+}
+
+#[cfg(feature = "rvv")]
+impl Default for UserVectorContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "rvv")]
+impl UserVectorContext {
+    pub fn new() -> Self {
+        unsafe { core::mem::zeroed() }
+    }
+
+    /// Whether the context has used the vector registers, i.e. its `VS` is
+    /// not off.
+    pub fn is_enabled(&self, sstatus: &Sstatus) -> bool {
+        vs(sstatus) != VS_OFF
+    }
+
+    /// Called on every trap from user mode, see
+    /// [`UserFloatContext::mark_save_if_needed`].
+    pub fn mark_save_if_needed(&mut self, sstatus: &mut Sstatus) {
+        if vs(sstatus) == VS_DIRTY {
+            self.need_save = 1;
+            set_vs(sstatus, VS_CLEAN);
+        }
+    }
+
+    /// Called when a context with `VS` off traps on an illegal instruction,
+    /// which may be its first vector instruction. Its registers are loaded
+    /// when returning to user mode, and the instruction is retried.
+    ///
+    /// Returns `false`, leaving `VS` off, if there is no usable vector unit.
+    pub fn enable(&mut self, sstatus: &mut Sstatus) -> bool {
+        if vlenb() == 0 {
+            return false;
+        }
+        set_vs(sstatus, VS_CLEAN);
+        self.need_restore = 1;
+        true
+    }
+
+    pub fn yield_task(&mut self) {
+        self.save();
+        self.need_restore = 1;
+    }
+
+    /// Save reg -> mem
+    pub fn save(&mut self) {
+        if self.need_save == 0 {
+            return;
+        }
+        self.need_save = 0;
+        unsafe {
+            set_live_vs(VS_CLEAN);
+            // Whole register stores ignore `vl` and `vtype`, but not `vstart`.
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrr {vstart}, vstart",
+                "csrw vstart, zero",
+                "csrr {vl}, vl",
+                "csrr {vtype}, vtype",
+                "csrr {vcsr}, vcsr",
+                "vs8r.v v0, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vs8r.v v8, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vs8r.v v16, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vs8r.v v24, ({regs})",
+                ".option pop",
+                regs = inout(reg) self.v.as_mut_ptr() => _,
+                group = in(reg) vlenb() * 8,
+                vstart = out(reg) self.vstart,
+                vl = out(reg) self.vl,
+                vtype = out(reg) self.vtype,
+                vcsr = out(reg) self.vcsr,
+            );
+        }
+    }
+
+    /// Restore mem -> reg
+    pub fn restore(&mut self) {
+        if self.need_restore == 0 {
+            return;
+        }
+        self.need_restore = 0;
+        unsafe {
+            set_live_vs(VS_CLEAN);
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrw vstart, zero",
+                "vl8re8.v v0, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vl8re8.v v8, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vl8re8.v v16, ({regs})",
+                "add {regs}, {regs}, {group}",
+                "vl8re8.v v24, ({regs})",
+                "vsetvl zero, {vl}, {vtype}",
+                "csrw vstart, {vstart}",
+                "csrw vcsr, {vcsr}",
+                ".option pop",
+                regs = inout(reg) self.v.as_ptr() => _,
+                group = in(reg) vlenb() * 8,
+                vstart = in(reg) self.vstart,
+                vl = in(reg) self.vl,
+                vtype = in(reg) self.vtype,
+                vcsr = in(reg) self.vcsr,
+            );
+        }
+    }
+}
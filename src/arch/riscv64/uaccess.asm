@@ -0,0 +1,75 @@
+# Record that the instruction at \insn may fault on user memory, and that
+# execution continues at \fixup if it does. "R" keeps the section alive
+# under --gc-sections.
+.macro EXTABLE insn, fixup
+    .pushsection ex_table, "aR"
+    .balign 8
+    .dword \insn, \fixup
+    .popsection
+.endm
+
+# sstatus.SUM, lets supervisor mode access user pages
+.equ SSTATUS_SUM, 1 << 18
+.equ EFAULT, 14
+
+    .section .text
+    .globl __probe_user_read
+    .globl __probe_user_write
+    .globl __copy_user
+    .align 2
+
+# arg: (user_ptr)
+# return: 0, or -EFAULT if the byte is not readable
+__probe_user_read:
+    li   t0, SSTATUS_SUM
+    csrs sstatus, t0
+.Lprobe_read:
+    lb   t1, 0(a0)
+    csrc sstatus, t0
+    li   a0, 0
+    ret
+    EXTABLE .Lprobe_read, __uaccess_fault
+
+# arg: (user_ptr)
+# return: 0, or -EFAULT if the byte is not writable
+__probe_user_write:
+    li   t0, SSTATUS_SUM
+    csrs sstatus, t0
+.Lprobe_write_load:
+    lb   t1, 0(a0)
+.Lprobe_write_store:
+    sb   t1, 0(a0)
+    csrc sstatus, t0
+    li   a0, 0
+    ret
+    EXTABLE .Lprobe_write_load, __uaccess_fault
+    EXTABLE .Lprobe_write_store, __uaccess_fault
+
+# args: (dst, src, len), either of dst and src may be a user pointer
+# return: 0, or -EFAULT if a byte could not be copied
+__copy_user:
+    li   t0, SSTATUS_SUM
+    csrs sstatus, t0
+    beqz a2, 2f
+1:
+.Lcopy_load:
+    lb   t1, 0(a1)
+.Lcopy_store:
+    sb   t1, 0(a0)
+    addi a0, a0, 1
+    addi a1, a1, 1
+    addi a2, a2, -1
+    bnez a2, 1b
+2:
+    csrc sstatus, t0
+    li   a0, 0
+    ret
+    EXTABLE .Lcopy_load, __uaccess_fault
+    EXTABLE .Lcopy_store, __uaccess_fault
+
+# Shared fixup of the leaf routines above. t0 still holds SSTATUS_SUM, as the
+# kernel trap path restores caller-saved registers.
+__uaccess_fault:
+    csrc sstatus, t0
+    li   a0, -EFAULT
+    ret
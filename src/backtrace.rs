@@ -0,0 +1,101 @@
+//! Frame-pointer stack backtraces.
+//!
+//! Both architectures keep the same frame record when built with
+//! `-C force-frame-pointers=yes`: the return address at `fp - 8` and the
+//! caller's frame pointer at `fp - 16`. The walk stops at the first frame
+//! pointer that is not a plausible kernel stack address.
+//!
+//! Return addresses are symbolized through [`kallsyms`](crate::kallsyms).
+use crate::addr::VirtAddr;
+use crate::arch::config::mm::KERNEL_STACK_SIZE;
+use crate::arch::trapframe::TrapRegs;
+use crate::kallsyms;
+use crate::println;
+
+/// Maximum number of frames printed.
+const MAX_DEPTH: usize = 64;
+
+/// Returns the current frame pointer.
+#[inline(always)]
+fn current_fp() -> usize {
+    let fp: usize;
+    unsafe {
+        #[cfg(target_arch = "riscv64")]
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+        #[cfg(target_arch = "loongarch64")]
+        core::arch::asm!("move {}, $fp", out(reg) fp);
+    }
+    fp
+}
+
+/// Whether `fp` may point just above a frame record of a kernel stack.
+fn is_valid_fp(fp: usize) -> bool {
+    fp >= 16 && fp % size_of::<usize>() == 0 && VirtAddr(fp - 16).is_kernel()
+}
+
+/// Print one frame as `#depth pc <symbol+offset/symbol address>`.
+fn print_frame(depth: usize, pc: usize) {
+    match kallsyms::lookup(pc) {
+        Some(sym) => println!(
+            "  #{:<2} {:#018x} <{}+{:#x}/{:#x}>",
+            depth, pc, sym.name, sym.offset, sym.addr
+        ),
+        None => println!("  #{:<2} {:#018x}", depth, pc),
+    }
+}
+
+/// Print the return addresses of the frame chain starting at `fp`, numbering
+/// them from `depth`.
+fn walk(mut fp: usize, mut depth: usize) {
+    while depth < MAX_DEPTH && is_valid_fp(fp) {
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra == 0 {
+            break;
+        }
+        print_frame(depth, ra);
+        depth += 1;
+        // Stacks grow down, so every caller frame lies above its callee, and
+        // no farther than one stack away.
+        if prev_fp <= fp || prev_fp - fp > KERNEL_STACK_SIZE {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// Print a backtrace of the current call stack to the debug console.
+#[inline(never)]
+pub fn backtrace() {
+    println!("backtrace:");
+    walk(current_fp(), 0);
+}
+
+/// Print a backtrace of the context saved in `tf` to the debug console,
+/// starting at the trapping instruction.
+///
+/// The frame chain ends right away if the trap was taken from user mode.
+pub fn backtrace_from_trap(tf: &TrapRegs) {
+    println!("backtrace:");
+    print_frame(0, tf.pc());
+    walk(tf.fp(), 1);
+}
+
+/// Panic handler printing the location, message and backtrace of the panic,
+/// enabled by the `panic-handler` feature for kernels without their own.
+#[cfg(feature = "panic-handler")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    match info.location() {
+        Some(location) => println!(
+            "[kernel] panicked at {}:{}: {}",
+            location.file(),
+            location.line(),
+            info.message()
+        ),
+        None => println!("[kernel] panicked: {}", info.message()),
+    }
+    backtrace();
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1 +1,11 @@
 pub const KERNEL_HEAP_SIZE: usize = 128 * 1024;
+/// Minimum number of frames pulled from the frame allocator when the heap is exhausted.
+pub const KERNEL_HEAP_GROW_PAGES: usize = 16;
+/// Bytes reserved for the symbol table filled in by `scripts/kallsyms.py`.
+pub const KALLSYMS_SIZE: usize = 512 * 1024;
+/// Bytes of the static per-CPU areas, which the per-CPU data of the crate and
+/// the kernel must fit in.
+pub const PERCPU_AREA_SIZE: usize = 4096;
+/// Frames the default `FrameAlloc::alloc_constrained` draws before giving up
+/// on finding one that satisfies the constraint.
+pub const CONSTRAINED_ALLOC_ATTEMPTS: usize = 64;
@@ -0,0 +1,25 @@
+//! Sets of CPUs.
+mod mask;
+
+pub use mask::CpuMask;
+
+use crate::CPU_COUNT;
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+
+impl CpuMask {
+    /// Every CPU, `MAX_HARTS` of them until the CPU count is known.
+    pub fn all() -> Self {
+        let count = CPU_COUNT
+            .try_get()
+            .map_or(MAX_HARTS, |&count| count.min(MAX_HARTS));
+        Self::from_bits((1 << count) - 1)
+    }
+
+    /// Every CPU but the current one.
+    pub fn all_but_self() -> Self {
+        let mut mask = Self::all();
+        mask.remove(hart_id());
+        mask
+    }
+}
@@ -0,0 +1,96 @@
+//! The CPU set type.
+//!
+//! It only depends on `core`, so its tests run on the host, see
+//! `host-tests`.
+
+/// A set of CPUs, one bit per hart ID.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(usize);
+
+impl CpuMask {
+    /// The empty set.
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: usize) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> usize {
+        self.0
+    }
+
+    /// The set of `cpu` alone, empty if `cpu` is beyond the mask.
+    pub const fn one(cpu: usize) -> Self {
+        if cpu < usize::BITS as usize {
+            Self(1 << cpu)
+        } else {
+            Self(0)
+        }
+    }
+
+    /// Add `cpu`, ignored if beyond the mask.
+    pub fn insert(&mut self, cpu: usize) {
+        self.0 |= Self::one(cpu).0;
+    }
+
+    pub fn remove(&mut self, cpu: usize) {
+        self.0 &= !Self::one(cpu).0;
+    }
+
+    pub const fn contains(&self, cpu: usize) -> bool {
+        cpu < usize::BITS as usize && self.0 & (1 << cpu) != 0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns the CPUs of the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + use<> {
+        let bits = self.0;
+        (0..usize::BITS as usize).filter(move |&cpu| bits & (1 << cpu) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut mask = CpuMask::new();
+        assert!(mask.is_empty());
+        mask.insert(3);
+        mask.insert(0);
+        mask.insert(3);
+        assert_eq!(mask.len(), 2);
+        assert!(mask.contains(0) && mask.contains(3) && !mask.contains(1));
+        mask.remove(3);
+        mask.remove(5);
+        assert_eq!(mask, CpuMask::one(0));
+    }
+
+    #[test]
+    fn beyond_the_mask() {
+        let bits = usize::BITS as usize;
+        assert!(CpuMask::one(bits).is_empty());
+        let mut mask = CpuMask::one(bits - 1);
+        mask.insert(bits);
+        assert_eq!(mask.len(), 1);
+        assert!(mask.contains(bits - 1) && !mask.contains(bits));
+    }
+
+    #[test]
+    fn iter_ascending() {
+        let mask = CpuMask::from_bits(0b1010_0101);
+        assert_eq!(mask.bits(), 0b1010_0101);
+        assert!(mask.iter().eq([0, 2, 5, 7]));
+        assert_eq!(CpuMask::new().iter().count(), 0);
+    }
+}
@@ -0,0 +1,119 @@
+//! Exception fixup tables for kernel accesses to user memory.
+//!
+//! Every instruction that may fault on a user pointer is recorded in the
+//! `ex_table` link section together with a fixup address. When such an
+//! instruction faults in kernel mode, the trap handler resumes execution at
+//! the fixup instead of treating the fault as a kernel bug. The fixups of the
+//! routines below return `-EFAULT`.
+//!
+//! The table is located through the `__start_ex_table` and `__stop_ex_table`
+//! symbols the linker provides.
+use crate::addr::{VirtAddr, VirtAddrRange};
+
+/// Error number returned, negated, for inaccessible user memory.
+pub const EFAULT: isize = 14;
+
+/// One `ex_table` entry, emitted by the `EXTABLE` assembler macro.
+#[repr(C)]
+struct ExceptionTableEntry {
+    /// Address of the instruction that may fault.
+    insn: usize,
+    /// Address to resume at if it does.
+    fixup: usize,
+}
+
+unsafe extern "C" {
+    fn __probe_user_read(addr: usize) -> isize;
+    fn __probe_user_write(addr: usize) -> isize;
+    fn __copy_user(dst: usize, src: usize, len: usize) -> isize;
+}
+
+fn exception_table() -> &'static [ExceptionTableEntry] {
+    unsafe extern "Rust" {
+        static __start_ex_table: ExceptionTableEntry;
+        static __stop_ex_table: ExceptionTableEntry;
+    }
+    let start = &raw const __start_ex_table;
+    let end = &raw const __stop_ex_table;
+    let len = (end as usize - start as usize) / size_of::<ExceptionTableEntry>();
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
+
+/// Returns the fixup address for a fault at `pc`, if the faulting instruction
+/// is allowed to fault.
+pub(crate) fn search_exception_table(pc: usize) -> Option<usize> {
+    exception_table()
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
+
+/// Whether `len` bytes at `addr` lie completely in the user half.
+fn is_user_range(addr: usize, len: usize) -> bool {
+    match VirtAddrRange::from_size(VirtAddr(addr), len) {
+        Some(range) if range.is_empty() => true,
+        Some(range) => range.start.is_user() && (range.end - 1).is_user(),
+        None => false,
+    }
+}
+
+/// Checks that the user byte at `addr` is readable.
+///
+/// Returns `0` on success and `-EFAULT` if reading it faults.
+pub fn probe_user_read(addr: VirtAddr) -> isize {
+    if !addr.is_user() {
+        return -EFAULT;
+    }
+    unsafe { __probe_user_read(addr.0) }
+}
+
+/// Checks that the user byte at `addr` is writable, by writing back its
+/// current value.
+///
+/// Returns `0` on success and `-EFAULT` if reading or writing it faults.
+pub fn probe_user_write(addr: VirtAddr) -> isize {
+    if !addr.is_user() {
+        return -EFAULT;
+    }
+    unsafe { __probe_user_write(addr.0) }
+}
+
+/// Copy `len` bytes from the user buffer `src` to the kernel buffer `dst`.
+///
+/// Returns `0` on success and `-EFAULT` if `src` is not a readable user
+/// buffer, in which case `dst` may have been partially written.
+///
+/// # Safety
+/// `dst` must be valid for writes of `len` bytes.
+pub unsafe fn copy_from_user(dst: *mut u8, src: *const u8, len: usize) -> isize {
+    if !is_user_range(src as usize, len) {
+        return -EFAULT;
+    }
+    unsafe { __copy_user(dst as usize, src as usize, len) }
+}
+
+/// Copy `len` bytes from the kernel buffer `src` to the user buffer `dst`.
+///
+/// Returns `0` on success and `-EFAULT` if `dst` is not a writable user
+/// buffer, in which case it may have been partially written.
+///
+/// # Safety
+/// `src` must be valid for reads of `len` bytes.
+pub unsafe fn copy_to_user(dst: *mut u8, src: *const u8, len: usize) -> isize {
+    if !is_user_range(dst as usize, len) {
+        return -EFAULT;
+    }
+    unsafe { __copy_user(dst as usize, src as usize, len) }
+}
+
+/// Copy `len` bytes from `src` to `dst`, either of which may be any kernel or
+/// user address, for debuggers inspecting arbitrary memory.
+///
+/// Returns `0` on success and `-EFAULT` if a byte could not be copied, in
+/// which case `dst` may have been partially written.
+///
+/// # Safety
+/// Writing to arbitrary memory may corrupt any kernel state.
+pub(crate) unsafe fn copy_nofault(dst: *mut u8, src: *const u8, len: usize) -> isize {
+    unsafe { __copy_user(dst as usize, src as usize, len) }
+}
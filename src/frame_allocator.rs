@@ -1,4 +1,6 @@
-use crate::addr::PhysPageNum;
+use crate::addr::{PhysAddr, PhysPageNum, PhysPageRange};
+use crate::config::CONSTRAINED_ALLOC_ATTEMPTS;
+use crate::memory::MemoryZone;
 use crate::utils::OnceCell;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter};
@@ -26,6 +28,42 @@ impl Debug for FrameTracker {
     }
 }
 
+/// Where frames should come from.
+///
+/// The zone is a hard requirement, the node only a preference: allocators fall
+/// back to other nodes when the preferred one is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameConstraint {
+    /// Preferred NUMA node, see [`crate::numa`].
+    pub node: Option<usize>,
+    /// Only hand out frames of this zone.
+    pub zone: Option<MemoryZone>,
+}
+
+impl FrameConstraint {
+    /// Frames preferably local to NUMA node `node`.
+    pub const fn node(node: usize) -> Self {
+        Self {
+            node: Some(node),
+            zone: None,
+        }
+    }
+
+    /// Frames reachable by 32-bit DMA.
+    pub const fn dma32() -> Self {
+        Self {
+            node: None,
+            zone: Some(MemoryZone::Dma32),
+        }
+    }
+
+    /// Whether frame `ppn` satisfies the zone requirement.
+    pub fn allows(&self, ppn: PhysPageNum) -> bool {
+        self.zone
+            .is_none_or(|zone| MemoryZone::of(PhysAddr::from(ppn)) == zone)
+    }
+}
+
 /// Trait for physical frame allocation.
 /// Implementations MUST handle interior mutability (e.g., using Mutex)
 /// because methods take &self but need to modify state.
@@ -38,9 +76,74 @@ pub trait FrameAlloc: Send + Sync {
     /// Takes &self, requires internal synchronization if state is modified.
     fn allocate_physical_pages(&self, pages: usize) -> Option<Vec<PhysPageNum>>;
 
+    /// Allocate `pages` consecutive frames.
+    /// Must not allocate from the kernel heap, as the heap itself grows through it.
+    /// The default only handles single frames.
+    fn alloc_contiguous(&self, pages: usize) -> Option<PhysPageRange> {
+        if pages == 1 {
+            self.alloc().map(|ppn| PhysPageRange::new(ppn, ppn + 1))
+        } else {
+            None
+        }
+    }
+
     /// Deallocate a frame.
     /// Takes &self, requires internal synchronization if state is modified.
     fn dealloc(&self, ppn: PhysPageNum);
+
+    /// Deallocate every frame in `ppns`.
+    /// The default frees them one by one.
+    fn dealloc_range(&self, ppns: PhysPageRange) {
+        ppns.into_iter().for_each(|ppn| self.dealloc(ppn));
+    }
+
+    /// Allocate a frame satisfying `constraint`.
+    /// NUMA-aware allocators should override this. The default ignores the
+    /// node preference and calls [`FrameAlloc::alloc`] until it returns a frame
+    /// of the requested zone, runs out or has drawn
+    /// `CONSTRAINED_ALLOC_ATTEMPTS` frames, then frees the frames it
+    /// rejected. These are chained through their first word, so the search
+    /// never touches the kernel heap.
+    fn alloc_constrained(&self, constraint: FrameConstraint) -> Option<PhysPageNum> {
+        let mut rejected: Option<PhysPageNum> = None;
+        let mut found = None;
+        for _ in 0..CONSTRAINED_ALLOC_ATTEMPTS {
+            match self.alloc() {
+                Some(ppn) if constraint.allows(ppn) => {
+                    found = Some(ppn);
+                    break;
+                }
+                Some(ppn) => {
+                    *PhysAddr::from(ppn).get_mut::<Option<PhysPageNum>>() = rejected;
+                    rejected = Some(ppn);
+                }
+                None => break,
+            }
+        }
+        while let Some(ppn) = rejected {
+            rejected = *PhysAddr::from(ppn).get_ref::<Option<PhysPageNum>>();
+            self.dealloc(ppn);
+        }
+        found
+    }
+
+    /// Allocate multiple consecutive frames satisfying `constraint`.
+    /// The default ignores the node preference and gives up if
+    /// [`FrameAlloc::allocate_physical_pages`] returns frames outside the
+    /// requested zone.
+    fn allocate_physical_pages_constrained(
+        &self,
+        pages: usize,
+        constraint: FrameConstraint,
+    ) -> Option<Vec<PhysPageNum>> {
+        let ppns = self.allocate_physical_pages(pages)?;
+        if ppns.iter().all(|ppn| constraint.allows(*ppn)) {
+            Some(ppns)
+        } else {
+            ppns.into_iter().for_each(|ppn| self.dealloc(ppn));
+            None
+        }
+    }
 }
 
 // --- Global Static Allocator Reference ---
@@ -83,6 +186,56 @@ pub fn frame_alloc_physical_pages(num: usize) -> Option<Vec<FrameTracker>> {
         .map(|ppns| ppns.into_iter().map(FrameTracker::new).collect())
 }
 
+/// Allocate a frame satisfying `constraint` using the global allocator.
+///
+/// Returns `None` if no such frame is available.
+/// # Panics
+/// Panics if the allocator is not initialized.
+pub fn frame_alloc_constrained(constraint: FrameConstraint) -> Option<FrameTracker> {
+    FRAME_ALLOCATOR
+        .get()
+        .alloc_constrained(constraint)
+        .map(FrameTracker::new)
+}
+
+/// Allocate a frame, preferably local to NUMA node `node`.
+pub fn frame_alloc_on_node(node: usize) -> Option<FrameTracker> {
+    frame_alloc_constrained(FrameConstraint::node(node))
+}
+
+/// Allocate a frame below 4 GiB, for devices with 32-bit DMA.
+pub fn frame_alloc_dma32() -> Option<FrameTracker> {
+    frame_alloc_constrained(FrameConstraint::dma32())
+}
+
+/// Allocate multiple consecutive frames satisfying `constraint` using the
+/// global allocator.
+///
+/// Returns `None` if not enough such frames are available.
+/// # Panics
+/// Panics if the allocator is not initialized.
+pub fn frame_alloc_physical_pages_constrained(
+    num: usize,
+    constraint: FrameConstraint,
+) -> Option<Vec<FrameTracker>> {
+    if num == 0 {
+        return Some(Vec::new());
+    }
+    FRAME_ALLOCATOR
+        .get()
+        .allocate_physical_pages_constrained(num, constraint)
+        .map(|ppns| ppns.into_iter().map(FrameTracker::new).collect())
+}
+
+/// Allocate `pages` consecutive frames without wrapping them in `FrameTracker`s.
+///
+/// Unlike [`frame_alloc_physical_pages`], this never touches the kernel heap, so it
+/// may be called from inside the global allocator.
+/// Returns `None` if no allocator is registered yet or not enough frames are available.
+pub(crate) fn frame_alloc_contiguous_raw(pages: usize) -> Option<PhysPageRange> {
+    FRAME_ALLOCATOR.try_get()?.alloc_contiguous(pages)
+}
+
 /// Deallocate a frame using the global allocator.
 ///
 /// This is usually called automatically when a `FrameTracker` is dropped.
@@ -92,3 +245,11 @@ pub fn frame_alloc_physical_pages(num: usize) -> Option<Vec<FrameTracker>> {
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.get().dealloc(ppn);
 }
+
+/// Deallocate every frame in `ppns` using the global allocator.
+///
+/// # Panics
+/// Panics if the allocator is not initialized.
+pub fn frame_dealloc_range(ppns: PhysPageRange) {
+    FRAME_ALLOCATOR.get().dealloc_range(ppns);
+}
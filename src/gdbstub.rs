@@ -0,0 +1,457 @@
+//! GDB remote serial protocol stub over the debug console.
+//!
+//! With the `gdbstub` feature, a built-in [`TrapClass::Breakpoint`] handler
+//! takes over breakpoint traps raised in kernel mode and talks to GDB on the
+//! debug console, e.g. with `target remote /dev/ttyS0` or QEMU's serial
+//! socket. [`gdb_break`] stops in the debugger explicitly, typically once at
+//! boot so GDB can attach.
+//!
+//! The stub supports reading and writing the saved [`TrapRegs`] and memory,
+//! the latter through the page table of the current address space, software
+//! breakpoints (`Z0`), continue and single-step. The hardware has no
+//! single-step mode, so a step places temporary breakpoints at every
+//! possible successor of the instruction at the PC. Continuing from a
+//! breakpoint first steps over it the same way, to put it back afterwards.
+//!
+//! Breakpoints are only present in memory while the kernel runs. While GDB
+//! is in control, the other harts are held in a cross-CPU call, so GDB sees
+//! the hart that trapped as the only thread. A hart with interrupts disabled
+//! only stops once it enables them, and one trapping too waits in the
+//! handler for its turn.
+mod packet;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use packet::{PACKET_SIZE, Reply, decode_hex, parse_hex, read_packet, split};
+
+use crate::arch::config::mm::PAGE_SIZE;
+use crate::arch::console::DebugConsole;
+use crate::arch::gdbstub::{
+    NUM_REGS, STEP_BREAK_LEN, break_insn, break_len, breakpoint, breakpoint_pc, flush_icache,
+    read_reg, step_targets, translate, write_reg,
+};
+use crate::arch::trapframe::TrapRegs;
+use crate::cpumask::CpuMask;
+use crate::extable::copy_nofault;
+use crate::ipi::smp_call_on_cpus;
+use crate::trap::{TrapClass, TrapHandlerEntry};
+use crate::utils::MutexNoIrq;
+
+/// Maximum number of software breakpoints.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Signal reported on every stop, `SIGTRAP`.
+const STOP_REPLY: &[u8] = b"S05";
+
+/// Error replies, with the errno of the failure.
+const EFAULT_REPLY: &[u8] = b"E0e";
+const EINVAL_REPLY: &[u8] = b"E16";
+const ENOSPC_REPLY: &[u8] = b"E1c";
+
+/// A breakpoint instruction, with the bytes it replaced while inserted.
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    len: usize,
+    saved: [u8; 4],
+}
+
+impl Breakpoint {
+    const fn new(addr: usize, len: usize) -> Self {
+        Self {
+            addr,
+            len,
+            saved: [0; 4],
+        }
+    }
+
+    fn insert(&mut self) -> bool {
+        let insn = break_insn(self.len).unwrap();
+        read_mem(self.addr, &mut self.saved[..self.len]) && write_mem(self.addr, insn)
+    }
+
+    fn remove(&self) {
+        write_mem(self.addr, &self.saved[..self.len]);
+    }
+}
+
+/// How to resume after the command loop.
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+struct GdbStub {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// Whether `breakpoints` are in memory.
+    inserted: bool,
+    /// Temporary breakpoints of a step, in memory if present.
+    steps: [Option<Breakpoint>; 2],
+    /// The step is stepping over a breakpoint, continue after it.
+    continue_after_step: bool,
+    /// GDB has sent a packet since the last detach.
+    attached: bool,
+}
+
+impl GdbStub {
+    const fn new() -> Self {
+        Self {
+            breakpoints: [None; MAX_BREAKPOINTS],
+            inserted: false,
+            steps: [None; 2],
+            continue_after_step: false,
+            attached: false,
+        }
+    }
+
+    fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    fn has_step(&self, addr: usize) -> bool {
+        self.steps.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    /// Put the recorded breakpoints and steps into memory.
+    fn insert_all(&mut self) {
+        if self.inserted {
+            for bp in self.breakpoints.iter_mut().flatten() {
+                if !bp.insert() {
+                    log::warn!("gdbstub: cannot insert breakpoint at {:#x}", bp.addr);
+                }
+            }
+        }
+        for bp in self.steps.iter_mut().flatten() {
+            bp.insert();
+        }
+        flush_icache();
+    }
+
+    /// Restore the instructions of every breakpoint in memory, in reverse
+    /// order in case of overlaps.
+    fn remove_all(&mut self) {
+        for bp in self.steps.iter().rev().flatten() {
+            bp.remove();
+        }
+        if self.inserted {
+            for bp in self.breakpoints.iter().rev().flatten() {
+                bp.remove();
+            }
+        }
+        flush_icache();
+    }
+
+    /// Record temporary breakpoints at the successors of the instruction at
+    /// the PC of `tf`.
+    fn prepare_step(&mut self, tf: &TrapRegs) -> bool {
+        let Some(targets) = step_targets(tf) else {
+            return false;
+        };
+        for (slot, target) in self.steps.iter_mut().zip(targets) {
+            *slot = target.map(|addr| Breakpoint::new(addr, STEP_BREAK_LEN));
+        }
+        if targets[0] == targets[1] {
+            self.steps[1] = None;
+        }
+        true
+    }
+}
+
+static GDB_STUB: MutexNoIrq<GdbStub> = MutexNoIrq::new(GdbStub::new());
+
+/// Packet buffers, only used with `GDB_STUB` held.
+static BUFFERS: MutexNoIrq<([u8; PACKET_SIZE], Reply)> =
+    MutexNoIrq::new(([0; PACKET_SIZE], Reply::new()));
+
+/// The other harts are held while set.
+static HOLD: AtomicBool = AtomicBool::new(false);
+
+/// Spin until GDB resumes the kernel. Run on the other harts by a cross-CPU
+/// call, with interrupts disabled.
+fn hold(_: usize) {
+    while HOLD.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Trap into the debugger, and wait for GDB if it is not attached yet.
+#[inline(never)]
+pub fn gdb_break() {
+    breakpoint();
+}
+
+/// Copy `buf.len()` bytes at `addr` into `buf`, through the page table of the
+/// current address space.
+pub(crate) fn read_mem(addr: usize, buf: &mut [u8]) -> bool {
+    for_each_page(addr, buf.len(), |kaddr, offset, len| unsafe {
+        copy_nofault(buf[offset..].as_mut_ptr(), kaddr as *const u8, len) == 0
+    })
+}
+
+/// Copy `buf` to `addr`, through the page table of the current address space.
+/// Read-only mappings are written as well.
+pub(crate) fn write_mem(addr: usize, buf: &[u8]) -> bool {
+    for_each_page(addr, buf.len(), |kaddr, offset, len| unsafe {
+        copy_nofault(kaddr as *mut u8, buf[offset..].as_ptr(), len) == 0
+    })
+}
+
+/// Call `f` with the translated address, buffer offset and length of each
+/// part of `len` bytes at `addr` within a page.
+fn for_each_page(addr: usize, len: usize, mut f: impl FnMut(usize, usize, usize) -> bool) -> bool {
+    let mut offset = 0;
+    while offset < len {
+        let va = addr.wrapping_add(offset);
+        let chunk = (PAGE_SIZE - va % PAGE_SIZE).min(len - offset);
+        match translate(va) {
+            Some(kaddr) if f(kaddr, offset, chunk) => offset += chunk,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn getchar() -> u8 {
+    loop {
+        if let Some(c) = DebugConsole::getchar() {
+            return c;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Receive the next packet into `buf`, acknowledging it. Packets with a bad
+/// checksum are requested again.
+fn recv_packet(buf: &mut [u8; PACKET_SIZE]) -> &[u8] {
+    read_packet(buf, getchar, DebugConsole::putchar)
+}
+
+impl Reply {
+    /// Send the packet until GDB acknowledges it, and clear it.
+    fn send(&mut self) {
+        self.write(getchar, DebugConsole::putchar);
+    }
+}
+
+/// Handle a breakpoint trap in kernel mode.
+///
+/// Returns `false` for traps from user mode, which belong to the kernel.
+fn handle_breakpoint(tf: &mut TrapRegs) -> bool {
+    if tf.is_user() {
+        return false;
+    }
+    let mut stub = GDB_STUB.lock();
+    let pc = breakpoint_pc(tf);
+    tf.set_pc(pc);
+    let hit_step = stub.has_step(pc);
+    let hit_breakpoint = stub.inserted && stub.has_breakpoint(pc);
+    stub.remove_all();
+
+    if hit_step {
+        stub.steps = [None; 2];
+        if stub.continue_after_step && !stub.has_breakpoint(pc) {
+            stub.continue_after_step = false;
+            stub.inserted = true;
+            stub.insert_all();
+            return true;
+        }
+    } else if !hit_breakpoint {
+        match break_len(pc) {
+            // A compiled-in breakpoint, resume after it.
+            Some(len) => tf.set_pc(pc + len),
+            // Another hart hit a breakpoint removed in the meantime.
+            None => {
+                stub.insert_all();
+                return true;
+            }
+        }
+    }
+    stub.inserted = false;
+    stub.steps = [None; 2];
+    stub.continue_after_step = false;
+
+    let mut buffers = BUFFERS.lock();
+    let (rx, tx) = &mut *buffers;
+    HOLD.store(true, Ordering::Release);
+    smp_call_on_cpus(CpuMask::all_but_self(), hold, 0, false);
+    if stub.attached {
+        tx.push(STOP_REPLY);
+        tx.send();
+    }
+    let resume = command_loop(&mut stub, tf, rx, tx);
+    HOLD.store(false, Ordering::Release);
+    match resume {
+        Resume::Continue if stub.has_breakpoint(tf.pc()) => {
+            stub.continue_after_step = stub.prepare_step(tf);
+            stub.inserted = !stub.continue_after_step;
+        }
+        Resume::Continue => stub.inserted = true,
+        Resume::Step => {
+            stub.prepare_step(tf);
+        }
+        Resume::Detach => {
+            stub.breakpoints = [None; MAX_BREAKPOINTS];
+            stub.attached = false;
+        }
+    }
+    stub.insert_all();
+    true
+}
+
+/// Serve GDB until it resumes the kernel.
+fn command_loop(
+    stub: &mut GdbStub,
+    tf: &mut TrapRegs,
+    rx: &mut [u8; PACKET_SIZE],
+    tx: &mut Reply,
+) -> Resume {
+    loop {
+        let packet = recv_packet(rx);
+        stub.attached = true;
+        let (&command, args) = match packet.split_first() {
+            Some(split) => split,
+            None => {
+                tx.send();
+                continue;
+            }
+        };
+        match command {
+            b'?' => tx.push(STOP_REPLY),
+            b'g' => {
+                for n in 0..NUM_REGS {
+                    tx.push_hex(&read_reg(tf, n).unwrap_or(0).to_le_bytes());
+                }
+            }
+            b'G' => {
+                for (n, value) in args.chunks(16).enumerate().take(NUM_REGS) {
+                    let mut bytes = [0; 8];
+                    if decode_hex(value, &mut bytes) {
+                        write_reg(tf, n, usize::from_le_bytes(bytes));
+                    }
+                }
+                tx.push(b"OK");
+            }
+            b'p' => match parse_hex(args).and_then(|n| read_reg(tf, n)) {
+                Some(value) => tx.push_hex(&value.to_le_bytes()),
+                // Unavailable, e.g. the floating-point registers.
+                None => tx.push(b"xxxxxxxxxxxxxxxx"),
+            },
+            b'P' => {
+                let mut bytes = [0; 8];
+                let written = split(args, b'=').is_some_and(|(n, value)| {
+                    decode_hex(value, &mut bytes)
+                        && parse_hex(n)
+                            .is_some_and(|n| write_reg(tf, n, usize::from_le_bytes(bytes)))
+                });
+                tx.push(if written { b"OK" } else { EINVAL_REPLY });
+            }
+            b'm' => match split(args, b',')
+                .and_then(|(addr, len)| Some((parse_hex(addr)?, parse_hex(len)?)))
+            {
+                Some((addr, len)) => {
+                    let len = len.min(PACKET_SIZE / 2);
+                    let mut bytes = [0; PACKET_SIZE / 2];
+                    if read_mem(addr, &mut bytes[..len]) {
+                        tx.push_hex(&bytes[..len]);
+                    } else {
+                        tx.push(EFAULT_REPLY);
+                    }
+                }
+                None => tx.push(EINVAL_REPLY),
+            },
+            b'M' => {
+                let mut bytes = [0; PACKET_SIZE / 2];
+                let reply = match split(args, b',').and_then(|(addr, rest)| {
+                    let (len, data) = split(rest, b':')?;
+                    Some((parse_hex(addr)?, parse_hex(len)?, data))
+                }) {
+                    Some((addr, len, data))
+                        if len <= bytes.len() && decode_hex(data, &mut bytes[..len]) =>
+                    {
+                        if write_mem(addr, &bytes[..len]) {
+                            flush_icache();
+                            b"OK".as_slice()
+                        } else {
+                            EFAULT_REPLY
+                        }
+                    }
+                    _ => EINVAL_REPLY,
+                };
+                tx.push(reply);
+            }
+            b'Z' | b'z' if args.first() == Some(&b'0') => {
+                let reply = match split(args, b',').and_then(|(_, rest)| split(rest, b',')) {
+                    Some((addr, kind)) => match (parse_hex(addr), parse_hex(kind)) {
+                        (Some(addr), Some(kind)) if command == b'Z' => {
+                            set_breakpoint(stub, addr, kind)
+                        }
+                        (Some(addr), Some(_)) => {
+                            stub.breakpoints
+                                .iter_mut()
+                                .filter(|bp| bp.is_some_and(|bp| bp.addr == addr))
+                                .for_each(|bp| *bp = None);
+                            b"OK"
+                        }
+                        _ => EINVAL_REPLY,
+                    },
+                    None => EINVAL_REPLY,
+                };
+                tx.push(reply);
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    tf.set_pc(addr);
+                }
+                return if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+            }
+            b'D' => {
+                tx.push(b"OK");
+                tx.send();
+                return Resume::Detach;
+            }
+            b'k' => return Resume::Detach,
+            b'H' => tx.push(b"OK"),
+            b'q' if args.starts_with(b"Supported") => tx.push(b"PacketSize=1000"),
+            b'q' if args.starts_with(b"Attached") => tx.push(b"1"),
+            // Unsupported, answered with an empty packet.
+            _ => {}
+        }
+        tx.send();
+    }
+}
+
+/// Handle `Z0`, checking that the breakpoint can be written.
+fn set_breakpoint(stub: &mut GdbStub, addr: usize, kind: usize) -> &'static [u8] {
+    if break_insn(kind).is_none() {
+        return EINVAL_REPLY;
+    }
+    if stub.has_breakpoint(addr) {
+        return b"OK";
+    }
+    let mut bytes = [0; 4];
+    if !read_mem(addr, &mut bytes[..kind]) || !write_mem(addr, &bytes[..kind]) {
+        return EFAULT_REPLY;
+    }
+    match stub.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+        Some(slot) => {
+            *slot = Some(Breakpoint::new(addr, kind));
+            b"OK"
+        }
+        None => ENOSPC_REPLY,
+    }
+}
+
+/// Built-in handler passing kernel breakpoints to GDB. It runs before every
+/// other breakpoint handler.
+#[used(linker)]
+#[unsafe(link_section = "trap_handlers")]
+static GDB_BREAKPOINT_HANDLER: TrapHandlerEntry = TrapHandlerEntry {
+    class: TrapClass::Breakpoint,
+    priority: i32::MAX,
+    handler: |tf, _| handle_breakpoint(tf),
+};
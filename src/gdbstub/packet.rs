@@ -0,0 +1,201 @@
+//! Packet framing and hex encoding of the GDB remote serial protocol.
+//!
+//! It only depends on `core`, so its tests run on the host, see
+//! `host-tests`.
+
+/// Size of the packet buffers, advertised to GDB in hex as `PacketSize`.
+pub(super) const PACKET_SIZE: usize = 0x1000;
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Parse a whole field of hex digits.
+pub(super) fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0, |acc, &c| Some((acc << 4) | hex_digit(c)? as usize))
+}
+
+/// Decode the hex string `s` into `buf`, which it must fill exactly.
+pub(super) fn decode_hex(s: &[u8], buf: &mut [u8]) -> bool {
+    if s.len() != buf.len() * 2 {
+        return false;
+    }
+    for (byte, pair) in buf.iter_mut().zip(s.chunks(2)) {
+        match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(hi), Some(lo)) => *byte = (hi << 4) | lo,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Split `s` at the first `sep`.
+pub(super) fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let at = s.iter().position(|&c| c == sep)?;
+    Some((&s[..at], &s[at + 1..]))
+}
+
+/// Read the next packet into `buf` from the bytes `getchar` returns, sending
+/// the acknowledgements through `putchar`.
+pub(super) fn read_packet(
+    buf: &mut [u8; PACKET_SIZE],
+    mut getchar: impl FnMut() -> u8,
+    mut putchar: impl FnMut(u8),
+) -> &[u8] {
+    // The last byte read, which starts the next packet if it is a `$`.
+    let mut c = getchar();
+    'packet: loop {
+        while c != b'$' {
+            c = getchar();
+        }
+        let mut len = 0;
+        let mut sum = 0u8;
+        loop {
+            c = getchar();
+            match c {
+                b'#' => break,
+                b'$' => continue 'packet,
+                _ if len < PACKET_SIZE => {
+                    buf[len] = c;
+                    len += 1;
+                    sum = sum.wrapping_add(c);
+                }
+                _ => continue 'packet,
+            }
+        }
+        let expected = hex_digit(getchar()).zip(hex_digit(getchar()));
+        if expected == Some((sum >> 4, sum & 0xf)) {
+            putchar(b'+');
+            return &buf[..len];
+        }
+        putchar(b'-');
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// A reply packet being built.
+pub(super) struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub(super) const fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub(super) fn push(&mut self, data: &[u8]) {
+        let len = data.len().min(PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&data[..len]);
+        self.len += len;
+    }
+
+    pub(super) fn push_hex(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.push(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]]);
+        }
+    }
+
+    /// Write the packet through `putchar` until `getchar` returns GDB's
+    /// acknowledgement, and clear it.
+    pub(super) fn write(&mut self, mut getchar: impl FnMut() -> u8, mut putchar: impl FnMut(u8)) {
+        let data = &self.buf[..self.len];
+        let sum = data.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+        loop {
+            putchar(b'$');
+            data.iter().for_each(|&c| putchar(c));
+            putchar(b'#');
+            putchar(HEX[(sum >> 4) as usize]);
+            putchar(HEX[(sum & 0xf) as usize]);
+            match getchar() {
+                b'+' => break,
+                b'-' => continue,
+                // GDB is not listening, e.g. on a stop before it attached.
+                _ => break,
+            }
+        }
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Read a packet from `input`, returning it with the acknowledgements.
+    fn read(input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut buf = [0; PACKET_SIZE];
+        let mut input = input.iter().copied();
+        let mut acks = Vec::new();
+        let packet = read_packet(
+            &mut buf,
+            || input.next().expect("input exhausted"),
+            |c| acks.push(c),
+        );
+        (packet.to_vec(), acks)
+    }
+
+    #[test]
+    fn packet_framing() {
+        assert_eq!(read(b"+$g#67"), (b"g".to_vec(), b"+".to_vec()));
+        assert_eq!(read(b"$#00"), (Vec::new(), b"+".to_vec()));
+        // A bad checksum is requested again.
+        assert_eq!(read(b"$g#00$g#67"), (b"g".to_vec(), b"-+".to_vec()));
+        // A `$` restarts the packet.
+        assert_eq!(read(b"$m0$c#63"), (b"c".to_vec(), b"+".to_vec()));
+    }
+
+    #[test]
+    fn overlong_packet() {
+        let mut input = Vec::from(*b"$");
+        input.resize(PACKET_SIZE + 2, b'0');
+        input.extend_from_slice(b"$s#73");
+        assert_eq!(read(&input), (b"s".to_vec(), b"+".to_vec()));
+    }
+
+    #[test]
+    fn hex_fields() {
+        assert_eq!(parse_hex(b"ffffffc080200000"), Some(0xffff_ffc0_8020_0000));
+        assert_eq!(parse_hex(b"1A"), Some(0x1a));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12x"), None);
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+
+        let mut buf = [0; 3];
+        assert!(decode_hex(b"00ff7e", &mut buf));
+        assert_eq!(buf, [0x00, 0xff, 0x7e]);
+        assert!(!decode_hex(b"00ff7", &mut buf));
+        assert!(!decode_hex(b"00fg7e", &mut buf));
+
+        assert_eq!(split(b"1000,4", b','), Some((&b"1000"[..], &b"4"[..])));
+        assert_eq!(split(b"1000", b','), None);
+    }
+
+    #[test]
+    fn reply_hex() {
+        let mut reply = Reply::new();
+        reply.push(b"S");
+        reply.push_hex(&[0x05, 0xab]);
+        assert_eq!(&reply.buf[..reply.len], b"S05ab");
+    }
+
+    #[test]
+    fn reply_sent_until_acknowledged() {
+        let mut reply = Reply::new();
+        reply.push(b"OK");
+        let mut acks = b"-+".iter().copied();
+        let mut output = Vec::new();
+        reply.write(|| acks.next().unwrap(), |c| output.push(c));
+        assert_eq!(output, b"$OK#9a$OK#9a");
+        assert_eq!(reply.len, 0);
+    }
+}
@@ -0,0 +1,107 @@
+//! Kernel heap backing `alloc`.
+//!
+//! The heap starts out on a static arena in `.bss`, so `Vec`s work during early
+//! boot before any frame allocator is registered. Once a [`FrameAlloc`] has been
+//! registered, an exhausted heap grows by pulling frames from it. Frames handed
+//! to the heap are never given back.
+//!
+//! [`FrameAlloc`]: crate::frame_allocator::FrameAlloc
+use crate::addr::{PhysAddrRange, PhysPageRange, phys_to_virt};
+use crate::arch::config::mm::PAGE_SIZE;
+use crate::config::{KERNEL_HEAP_GROW_PAGES, KERNEL_HEAP_SIZE};
+use crate::frame_allocator::frame_alloc_contiguous_raw;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const HEAP_ORDER: usize = 32;
+
+#[global_allocator]
+static HEAP_ALLOCATOR: LockedHeapWithRescue<HEAP_ORDER> = LockedHeapWithRescue::new(grow_heap);
+
+#[unsafe(link_section = ".bss.heap")]
+static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
+
+static HEAP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Number of frames pulled from the frame allocator since boot.
+static GROWN_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Snapshot of the kernel heap usage.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Bytes owned by the heap: the static arena plus every grown frame.
+    pub total_bytes: usize,
+    /// Bytes requested by the callers of live allocations.
+    pub user_bytes: usize,
+    /// Bytes reserved for live allocations, including buddy rounding.
+    pub allocated_bytes: usize,
+    /// Frames pulled from the frame allocator since boot.
+    pub grown_pages: usize,
+}
+
+/// Hand the static early-boot arena to the heap allocator.
+///
+/// This is called by `arch_init`, before anything is allocated.
+///
+/// # Panics
+/// Panics if called more than once.
+pub fn init_heap() {
+    if HEAP_INITIALIZED.swap(true, Ordering::AcqRel) {
+        panic!("kernel heap already initialized");
+    }
+    unsafe {
+        HEAP_ALLOCATOR
+            .lock()
+            .init(&raw mut HEAP_SPACE as usize, KERNEL_HEAP_SIZE);
+    }
+}
+
+/// Returns the current usage of the kernel heap.
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats {
+        total_bytes: heap.stats_total_bytes(),
+        user_bytes: heap.stats_alloc_user(),
+        allocated_bytes: heap.stats_alloc_actual(),
+        grown_pages: GROWN_PAGES.load(Ordering::Relaxed),
+    }
+}
+
+/// Rescue hook run by the allocator when `layout` cannot be satisfied.
+///
+/// It runs with the heap lock held, so it must not allocate itself.
+fn grow_heap(heap: &mut Heap<HEAP_ORDER>, layout: &Layout) {
+    // Buddy blocks are naturally aligned, so a page-aligned region is only
+    // guaranteed to contain one if it spans twice the block size.
+    let block = layout.size().max(layout.align()).next_power_of_two();
+    let pages = KERNEL_HEAP_GROW_PAGES.max((2 * block).div_ceil(PAGE_SIZE));
+    if let Some(ppns) = frame_alloc_contiguous_raw(pages) {
+        add_frames(heap, ppns);
+        return;
+    }
+
+    // Not enough consecutive frames, single frames still serve small requests.
+    if block <= PAGE_SIZE {
+        for _ in 0..KERNEL_HEAP_GROW_PAGES {
+            match frame_alloc_contiguous_raw(1) {
+                Some(ppns) => add_frames(heap, ppns),
+                None => break,
+            }
+        }
+    }
+}
+
+fn add_frames(heap: &mut Heap<HEAP_ORDER>, ppns: PhysPageRange) {
+    let range = PhysAddrRange::from(ppns);
+    let start = phys_to_virt(range.start).0;
+    unsafe {
+        heap.add_to_heap(start, start + range.size());
+    }
+    GROWN_PAGES.fetch_add(ppns.size(), Ordering::Relaxed);
+    log::trace!(
+        "kernel heap grew by {} page(s) at {:#x}",
+        ppns.size(),
+        start
+    );
+}
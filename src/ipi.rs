@@ -0,0 +1,168 @@
+//! Inter-processor interrupts.
+//!
+//! Every CPU owns a bounded, lock-free queue of messages. [`send_ipi`] posts
+//! a message to the queue of each target and raises an IPI on them, which
+//! drain their queue in interrupt context. Dispatch is a built-in handler of
+//! [`TrapClass::Ipi`] with the highest priority, so the handler chain only
+//! sees IPIs without messages.
+//!
+//! A CPU takes IPIs once it called [`init_ipi`]. Only CPUs with an ID below
+//! `MAX_HARTS` have a queue, the others are never sent messages.
+mod queue;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use queue::ArrayQueue;
+
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+use crate::arch::irq::Irq;
+use crate::cpumask::CpuMask;
+use crate::irq::{in_interrupt, without_interrupts};
+use crate::preempt::{PreemptGuard, set_need_resched};
+use crate::trap::{TrapClass, TrapHandlerEntry};
+
+/// Messages a CPU queues before senders wait for it to drain them.
+pub const IPI_QUEUE_SIZE: usize = 32;
+
+/// A message carried by an IPI.
+#[derive(Debug, Clone, Copy)]
+pub enum IpiMessage {
+    /// Request a reschedule of the target, as [`set_need_resched`] there.
+    Reschedule,
+    /// Run `func(arg)` on the target, in interrupt context.
+    Call { func: fn(usize), arg: usize },
+    /// Halt the target with interrupts disabled, e.g. on panic or shutdown.
+    Stop,
+}
+
+#[derive(Clone, Copy)]
+struct Message {
+    msg: IpiMessage,
+    /// Calls left to return for a synchronous caller, or null.
+    pending: *const AtomicUsize,
+}
+
+// The sender of a message with a pending counter waits for it to reach 0,
+// so the counter outlives the message.
+unsafe impl Send for Message {}
+
+static IPI_QUEUES: [ArrayQueue<Message, IPI_QUEUE_SIZE>; MAX_HARTS] =
+    [const { ArrayQueue::new() }; MAX_HARTS];
+
+/// Built-in handler draining the message queue of the CPU.
+#[used(linker)]
+#[unsafe(link_section = "trap_handlers")]
+static IPI_DISPATCHER: TrapHandlerEntry = TrapHandlerEntry {
+    class: TrapClass::Ipi,
+    priority: i32::MAX,
+    handler: |_, _| handle_ipi(),
+};
+
+/// Enable IPIs on the current CPU. Every CPU calls it once.
+pub fn init_ipi() {
+    Irq::enable_ipi();
+}
+
+/// Queue `message` for `cpu`, waiting while its queue is full.
+fn post(cpu: usize, message: Message) {
+    while !IPI_QUEUES[cpu].push(message) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Send `msg` to every CPU of `target`, the current one included.
+///
+/// While the queue of a target is full, this waits for it to drain, so a
+/// target must not be stuck with interrupts disabled.
+pub fn send_ipi(target: CpuMask, msg: IpiMessage) {
+    let target = CpuMask::from_bits(target.bits() & CpuMask::all().bits());
+    if target.is_empty() {
+        return;
+    }
+    let message = Message {
+        msg,
+        pending: core::ptr::null(),
+    };
+    for cpu in target.iter() {
+        post(cpu, message);
+    }
+    Irq::send_ipi(target.bits());
+}
+
+/// Run `func(arg)` on `cpu`, in interrupt context. With `wait`, returns once
+/// it returned, otherwise right after queueing it. On the current CPU, it
+/// runs right away with interrupts disabled.
+///
+/// Waiting callers must have interrupts enabled: two CPUs waiting on each
+/// other with interrupts disabled deadlock.
+///
+/// Returns `false` if `cpu` does not exist.
+pub fn smp_call_on_cpu(cpu: usize, func: fn(usize), arg: usize, wait: bool) -> bool {
+    if !CpuMask::all().contains(cpu) {
+        return false;
+    }
+    smp_call_on_cpus(CpuMask::one(cpu), func, arg, wait);
+    true
+}
+
+/// Run `func(arg)` on every CPU of `cpus`, like [`smp_call_on_cpu`].
+pub fn smp_call_on_cpus(cpus: CpuMask, func: fn(usize), arg: usize, wait: bool) {
+    let _guard = PreemptGuard::new();
+    let mut remote = CpuMask::from_bits(cpus.bits() & CpuMask::all().bits());
+    let local = remote.contains(hart_id());
+    remote.remove(hart_id());
+    let pending = AtomicUsize::new(remote.len());
+    if !remote.is_empty() {
+        debug_assert!(
+            !wait || (Irq::interrupt_enabled() && !in_interrupt()),
+            "synchronous cross-CPU call with interrupts disabled"
+        );
+        let message = Message {
+            msg: IpiMessage::Call { func, arg },
+            pending: if wait { &pending } else { core::ptr::null() },
+        };
+        for cpu in remote.iter() {
+            post(cpu, message);
+        }
+        Irq::send_ipi(remote.bits());
+    }
+    if local {
+        without_interrupts(|| func(arg));
+    }
+    if wait {
+        while pending.load(Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Handle the messages queued for the current CPU. Returns `false` if there
+/// were none, or the CPU has no queue.
+fn handle_ipi() -> bool {
+    let Some(queue) = IPI_QUEUES.get(hart_id()) else {
+        return false;
+    };
+    let mut handled = false;
+    while let Some(message) = queue.pop() {
+        handled = true;
+        match message.msg {
+            IpiMessage::Reschedule => set_need_resched(),
+            IpiMessage::Call { func, arg } => {
+                func(arg);
+                if !message.pending.is_null() {
+                    unsafe { (*message.pending).fetch_sub(1, Ordering::Release) };
+                }
+            }
+            IpiMessage::Stop => {
+                log::info!("CPU {} stopped", hart_id());
+                without_interrupts(|| {
+                    loop {
+                        Irq::wait_for_interrupt();
+                    }
+                })
+            }
+        }
+    }
+    handled
+}
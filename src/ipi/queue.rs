@@ -0,0 +1,175 @@
+//! The bounded lock-free queue behind the per-CPU IPI message queues.
+//!
+//! It only depends on `core`, so its tests run on the host, see
+//! `host-tests`.
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+struct Slot<T> {
+    /// The sequence number of the slot, minus its index so that every slot
+    /// starts at 0: slot `i` is free for the push at position `seq + i` and
+    /// full for the pop at position `seq + i - 1`.
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded multi-producer queue of `N` values, with the slot sequence
+/// numbers of Vyukov's algorithm.
+pub(crate) struct ArrayQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for ArrayQueue<T, N> {}
+
+impl<T: Copy, const N: usize> ArrayQueue<T, N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            slots: [const {
+                Slot {
+                    seq: AtomicUsize::new(0),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                }
+            }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the slot of position `pos` with its sequence number.
+    fn slot(&self, pos: usize) -> (&Slot<T>, usize) {
+        let index = pos % N;
+        let slot = &self.slots[index];
+        (slot, slot.seq.load(Ordering::Acquire).wrapping_add(index))
+    }
+
+    fn set_seq(&self, pos: usize, seq: usize) {
+        let index = pos % N;
+        self.slots[index]
+            .seq
+            .store(seq.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// Returns `false` if the queue is full.
+    pub(crate) fn push(&self, value: T) -> bool {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let (slot, seq) = self.slot(pos);
+            match seq.wrapping_sub(pos) as isize {
+                0 => match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        self.set_seq(pos, pos.wrapping_add(1));
+                        return true;
+                    }
+                    Err(head) => pos = head,
+                },
+                diff if diff < 0 => return false,
+                _ => pos = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let (slot, seq) = self.slot(pos);
+            match seq.wrapping_sub(pos.wrapping_add(1)) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init() };
+                        self.set_seq(pos, pos.wrapping_add(N));
+                        return Some(value);
+                    }
+                    Err(tail) => pos = tail,
+                },
+                diff if diff < 0 => return None,
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 32;
+
+    #[test]
+    fn full_and_empty() {
+        let queue = ArrayQueue::<usize, SIZE>::new();
+        assert!(queue.pop().is_none());
+        for i in 0..SIZE {
+            assert!(queue.push(i));
+        }
+        assert!(!queue.push(SIZE));
+        for i in 0..SIZE {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn wrap_around() {
+        let queue = ArrayQueue::<usize, SIZE>::new();
+        let lag = SIZE / 2;
+        for i in 0..lag {
+            assert!(queue.push(i));
+        }
+        // Go around the ring several times with the queue half full.
+        for i in lag..4 * SIZE + 3 {
+            assert!(queue.push(i));
+            assert_eq!(queue.pop(), Some(i - lag));
+        }
+        let next = 4 * SIZE + 3;
+        for i in next..next + SIZE - lag {
+            assert!(queue.push(i));
+        }
+        assert!(!queue.push(0));
+        for i in next - lag..next + SIZE - lag {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn concurrent_producers() {
+        const PRODUCERS: usize = 4;
+        const COUNT: usize = 1_000;
+        let queue = ArrayQueue::<(usize, usize), SIZE>::new();
+        std::thread::scope(|s| {
+            for producer in 0..PRODUCERS {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..COUNT {
+                        while !queue.push((producer, i)) {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            // The values of each producer come out in the order it pushed them.
+            let mut next = [0; PRODUCERS];
+            while next.iter().any(|&n| n < COUNT) {
+                if let Some((producer, i)) = queue.pop() {
+                    assert_eq!(i, next[producer]);
+                    next[producer] += 1;
+                }
+            }
+        });
+        assert!(queue.pop().is_none());
+    }
+}
@@ -0,0 +1,256 @@
+//! External interrupt handler registry.
+//!
+//! Drivers attach handlers to the IRQ numbers reported by [`TrapKind::Irq`]
+//! with [`request_irq`]. A line requested with [`IrqFlags::SHARED`] by all of
+//! its handlers takes several, which all run when it fires, as more than one
+//! device may have raised it. The first handler of a line enables it in the
+//! interrupt controller, and freeing the last one disables it.
+//!
+//! Dispatch is a built-in handler of [`TrapClass::Irq`] with the highest
+//! priority, so the trap handlers of both architectures run it ahead of the
+//! handler chain, which only sees IRQs no handler claimed.
+//!
+//! Interrupt traps are handled in interrupt context, which [`in_interrupt`]
+//! reports and which is not preemptible.
+use crate::arch::irq::{IRQVector, Irq};
+use crate::bit;
+use crate::preempt::{preempt_disable, preempt_enable};
+use crate::trap::{TrapClass, TrapHandlerEntry, TrapKind};
+use crate::utils::MutexNoIrq;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// An IRQ handler. Returns `true` if its device raised the interrupt.
+pub type IrqHandler = fn(irq: usize) -> bool;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    /// Options of [`request_irq`]
+    pub struct IrqFlags: usize {
+        const SHARED = bit!(0);     // Line may be shared with other SHARED handlers
+        const NO_AUTOEN = bit!(1);  // Leave the line disabled, see enable_irq
+    }
+}
+
+/// Maximum number of handlers sharing a line.
+pub const MAX_SHARED_IRQ_HANDLERS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct IrqAction {
+    handler: IrqHandler,
+    flags: IrqFlags,
+    name: &'static str,
+}
+
+#[derive(Clone, Copy)]
+struct IrqLine {
+    actions: [Option<IrqAction>; MAX_SHARED_IRQ_HANDLERS],
+    len: usize,
+}
+
+static IRQ_LINES: MutexNoIrq<BTreeMap<usize, IrqLine>> = MutexNoIrq::new(BTreeMap::new());
+
+/// Interrupt traps being handled on this CPU.
+#[unsafe(mantahal_macro::def_percpu)]
+static IRQ_NESTING: usize = 0;
+
+/// Built-in handler running the handlers requested for the IRQ.
+#[used(linker)]
+#[unsafe(link_section = "trap_handlers")]
+static IRQ_DISPATCHER: TrapHandlerEntry = TrapHandlerEntry {
+    class: TrapClass::Irq,
+    priority: i32::MAX,
+    handler: |_, kind| match kind {
+        TrapKind::Irq(irq) => dispatch_irq(*irq),
+        _ => false,
+    },
+};
+
+/// Attach `handler` to `irq`, enabling the line if it is the first one and
+/// `flags` does not contain [`IrqFlags::NO_AUTOEN`]. `name` identifies the
+/// device in logs.
+///
+/// Returns `false` if `irq` is 0, the line is full, or either this or an
+/// attached handler does not share it.
+pub fn request_irq(irq: usize, handler: IrqHandler, flags: IrqFlags, name: &'static str) -> bool {
+    if irq == 0 {
+        return false;
+    }
+    let mut lines = IRQ_LINES.lock();
+    let line = lines.entry(irq).or_insert(IrqLine {
+        actions: [None; MAX_SHARED_IRQ_HANDLERS],
+        len: 0,
+    });
+    let shared = line.actions[..line.len]
+        .iter()
+        .flatten()
+        .all(|action| action.flags.contains(IrqFlags::SHARED));
+    if line.len == MAX_SHARED_IRQ_HANDLERS
+        || (line.len > 0 && !(shared && flags.contains(IrqFlags::SHARED)))
+    {
+        log::warn!("IRQ {irq} is busy, cannot attach {name}");
+        return false;
+    }
+    line.actions[line.len] = Some(IrqAction {
+        handler,
+        flags,
+        name,
+    });
+    line.len += 1;
+    if line.len == 1 && !flags.contains(IrqFlags::NO_AUTOEN) && !IRQVector::enable(irq) {
+        log::warn!("IRQ {irq} of {name} cannot be enabled in the interrupt controller");
+    }
+    log::debug!("IRQ {irq} attached to {name}");
+    true
+}
+
+/// Detach a handler attached by [`request_irq`], disabling the line if it
+/// was the last one.
+///
+/// Returns `false` if `handler` is not attached to `irq`.
+pub fn free_irq(irq: usize, handler: IrqHandler) -> bool {
+    let mut lines = IRQ_LINES.lock();
+    let Some(line) = lines.get_mut(&irq) else {
+        return false;
+    };
+    let Some(index) = line.actions[..line.len]
+        .iter()
+        .position(|action| action.is_some_and(|a| core::ptr::fn_addr_eq(a.handler, handler)))
+    else {
+        return false;
+    };
+    if let Some(action) = line.actions[index] {
+        log::debug!("IRQ {irq} detached from {}", action.name);
+    }
+    line.actions.copy_within(index + 1..line.len, index);
+    line.len -= 1;
+    line.actions[line.len] = None;
+    if line.len == 0 {
+        lines.remove(&irq);
+        IRQVector::disable(irq);
+    }
+    true
+}
+
+/// Enable `irq` in the interrupt controller, routing it to the current CPU.
+pub fn enable_irq(irq: usize) -> bool {
+    IRQVector::enable(irq)
+}
+
+/// Disable `irq` in the interrupt controller.
+pub fn disable_irq(irq: usize) -> bool {
+    IRQVector::disable(irq)
+}
+
+/// Route `irq` to `cpu` alone.
+///
+/// Returns `false` if the interrupt controller cannot raise `irq` on `cpu`.
+pub fn set_irq_affinity(irq: usize, cpu: usize) -> bool {
+    IRQVector::set_affinity(irq, cpu)
+}
+
+/// Set the priority of `irq` in the interrupt controller. Only the RISC-V
+/// PLIC has priorities, where 0 keeps the line from interrupting.
+pub fn set_irq_priority(irq: usize, priority: u32) -> bool {
+    IRQVector::set_priority(irq, priority)
+}
+
+/// Returns the priority of `irq` in the interrupt controller, if it has
+/// priorities.
+pub fn irq_priority(irq: usize) -> Option<u32> {
+    IRQVector::priority(irq)
+}
+
+/// Set the priority threshold of `cpu`, which then only takes IRQs of a
+/// higher priority. Only the RISC-V PLIC has priorities.
+pub fn set_irq_threshold(cpu: usize, threshold: u32) -> bool {
+    IRQVector::set_threshold(cpu, threshold)
+}
+
+/// Whether `irq` is pending in the interrupt controller. The LoongArch
+/// EIOINTC only reports the IRQs pending on the current CPU.
+pub fn irq_pending(irq: usize) -> bool {
+    IRQVector::is_pending(irq)
+}
+
+/// Set whether `irq` is edge-triggered rather than level-triggered, and
+/// whether it is active low rather than high. Only the LoongArch PCH-PIC
+/// inputs can be configured.
+pub fn set_irq_trigger(irq: usize, edge: bool, active_low: bool) -> bool {
+    IRQVector::set_trigger(irq, edge, active_low)
+}
+
+/// Returns the names of the handlers attached to `irq`.
+pub(crate) fn irq_names(irq: usize) -> Vec<&'static str> {
+    IRQ_LINES.lock().get(&irq).map_or(Vec::new(), |line| {
+        line.actions[..line.len]
+            .iter()
+            .flatten()
+            .map(|action| action.name)
+            .collect()
+    })
+}
+
+/// Run every handler attached to `irq`. Returns whether one of them claimed
+/// the interrupt. The line is copied out of its lock first, so handlers may
+/// request and free IRQs themselves.
+fn dispatch_irq(irq: usize) -> bool {
+    let Some(line) = IRQ_LINES.lock().get(&irq).copied() else {
+        return false;
+    };
+    line.actions[..line.len]
+        .iter()
+        .flatten()
+        .fold(false, |handled, action| (action.handler)(irq) | handled)
+}
+
+/// Marks the handling of an interrupt trap on this CPU until dropped.
+pub(crate) struct IrqContext(());
+
+impl IrqContext {
+    pub(crate) fn enter() -> Self {
+        preempt_disable();
+        unsafe { *IRQ_NESTING.current_ref_mut_raw() += 1 };
+        Self(())
+    }
+}
+
+impl Drop for IrqContext {
+    fn drop(&mut self) {
+        unsafe { *IRQ_NESTING.current_ref_mut_raw() -= 1 };
+        preempt_enable();
+    }
+}
+
+/// Run `f` with interrupts disabled on this CPU, restoring them after.
+// `Irq::disable_interrupt` is only unsafe on RISC-V.
+#[allow(unused_unsafe)]
+pub(crate) fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let irq_enabled = Irq::interrupt_enabled();
+    unsafe { Irq::disable_interrupt() };
+    let ret = f();
+    if irq_enabled {
+        unsafe { Irq::enable_interrupt() };
+    }
+    ret
+}
+
+/// Run `f` with interrupts enabled on this CPU, disabling them after if they
+/// were disabled.
+#[allow(unused_unsafe)]
+pub(crate) fn with_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let irq_enabled = Irq::interrupt_enabled();
+    unsafe { Irq::enable_interrupt() };
+    let ret = f();
+    if !irq_enabled {
+        unsafe { Irq::disable_interrupt() };
+    }
+    ret
+}
+
+/// Whether this CPU is handling an interrupt trap.
+pub fn in_interrupt() -> bool {
+    // Outside of interrupt context the task may migrate, so the counter is
+    // located and read with interrupts disabled.
+    without_interrupts(|| unsafe { IRQ_NESTING.read_current_raw() } > 0)
+}
@@ -0,0 +1,93 @@
+//! Kernel symbol table for backtraces.
+//!
+//! With the `kallsyms` feature, the `kallsyms` link section reserves
+//! [`KALLSYMS_SIZE`] bytes that `scripts/kallsyms.py` fills in after linking,
+//! from the `nm` output of the final kernel image. The blob layout, all
+//! integers little-endian:
+//!
+//! ```text
+//! magic       b"KSYMTAB\0"
+//! count       u64
+//! strings     u64, offset of the string table from the start of the blob
+//! entries     count * { addr: u64, name_off: u32, name_len: u32 }, sorted by addr
+//! string table
+//! ```
+//!
+//! Until the script has run, the section is all zeros and no symbol resolves.
+#[cfg(feature = "kallsyms")]
+use crate::config::KALLSYMS_SIZE;
+
+/// A symbol containing some address.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// Start address of the symbol.
+    pub addr: usize,
+    /// Offset of the looked-up address from `addr`.
+    pub offset: usize,
+}
+
+#[cfg(feature = "kallsyms")]
+#[used(linker)]
+#[unsafe(link_section = "kallsyms")]
+static KALLSYMS: [u8; KALLSYMS_SIZE] = [0; KALLSYMS_SIZE];
+
+#[cfg(feature = "kallsyms")]
+const MAGIC: &[u8; 8] = b"KSYMTAB\0";
+#[cfg(feature = "kallsyms")]
+const HEADER_SIZE: usize = 24;
+#[cfg(feature = "kallsyms")]
+const ENTRY_SIZE: usize = 16;
+
+#[cfg(feature = "kallsyms")]
+fn read_u64(blob: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(blob.get(off..off + 8)?.try_into().ok()?))
+}
+
+#[cfg(feature = "kallsyms")]
+fn read_u32(blob: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(blob.get(off..off + 4)?.try_into().ok()?))
+}
+
+/// Returns the symbol containing `addr`, i.e. the closest one at or below it.
+#[cfg(feature = "kallsyms")]
+pub fn lookup(addr: usize) -> Option<Symbol> {
+    // The contents are patched after compilation, so they must not be
+    // constant-folded from the zero initializer.
+    let blob: &[u8] = unsafe { &*core::hint::black_box(&raw const KALLSYMS) };
+    if blob.get(..MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let count = read_u64(blob, 8)? as usize;
+    let strings = read_u64(blob, 16)? as usize;
+    let entry_addr = |i: usize| read_u64(blob, HEADER_SIZE + i * ENTRY_SIZE).map(|a| a as usize);
+
+    // Index of the first entry above `addr`.
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if entry_addr(mid)? <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let index = lo.checked_sub(1)?;
+    let entry = HEADER_SIZE + index * ENTRY_SIZE;
+    let sym_addr = entry_addr(index)?;
+    let name_off = strings + read_u32(blob, entry + 8)? as usize;
+    let name_len = read_u32(blob, entry + 12)? as usize;
+    let name = core::str::from_utf8(blob.get(name_off..name_off + name_len)?).ok()?;
+    Some(Symbol {
+        name,
+        addr: sym_addr,
+        offset: addr - sym_addr,
+    })
+}
+
+/// Returns the symbol containing `addr`. Always `None` without the
+/// `kallsyms` feature.
+#[cfg(not(feature = "kallsyms"))]
+pub fn lookup(_addr: usize) -> Option<Symbol> {
+    None
+}
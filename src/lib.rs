@@ -10,45 +10,123 @@ extern crate alloc;
 
 mod addr;
 mod arch;
+mod backtrace;
 mod config;
 mod console;
+mod cpumask;
 mod device;
+mod extable;
 mod frame_allocator;
+#[cfg(feature = "gdbstub")]
+mod gdbstub;
+#[cfg(feature = "heap")]
+mod heap;
+mod ipi;
+mod irq;
+mod kallsyms;
+mod memory;
+#[cfg(feature = "misaligned")]
+mod misaligned;
+mod numa;
 mod pagetable;
+mod percpu;
+mod preempt;
+mod signal;
+mod slab;
+mod softirq;
+mod stats;
 mod tlb;
+mod trap;
 mod utils;
 
+#[cfg(target_arch = "riscv64")]
+pub use crate::arch::trap::set_nested_interrupts;
+pub use crate::arch::trapframe::{TrapFrame, TrapRegs};
+pub use crate::backtrace::{backtrace, backtrace_from_trap};
+pub use crate::cpumask::CpuMask;
+pub use crate::extable::{EFAULT, copy_from_user, copy_to_user, probe_user_read, probe_user_write};
+pub use crate::frame_allocator::{
+    FrameAlloc, FrameConstraint, FrameTracker, frame_alloc, frame_alloc_constrained,
+    frame_alloc_dma32, frame_alloc_on_node, frame_alloc_physical_pages,
+    frame_alloc_physical_pages_constrained, frame_dealloc, frame_dealloc_range,
+    init_frame_allocator,
+};
+#[cfg(feature = "gdbstub")]
+pub use crate::gdbstub::gdb_break;
+#[cfg(feature = "heap")]
+pub use crate::heap::{HeapStats, heap_stats};
+pub use crate::ipi::{
+    IPI_QUEUE_SIZE, IpiMessage, init_ipi, send_ipi, smp_call_on_cpu, smp_call_on_cpus,
+};
+pub use crate::irq::{
+    IrqFlags, IrqHandler, MAX_SHARED_IRQ_HANDLERS, disable_irq, enable_irq, free_irq, in_interrupt,
+    irq_pending, irq_priority, request_irq, set_irq_affinity, set_irq_priority, set_irq_threshold,
+    set_irq_trigger,
+};
+pub use crate::memory::{
+    DMA32_LIMIT, MemoryKind, MemoryRegion, MemoryZone, find_memory_region, free_memory_regions,
+    free_memory_regions_in, memory_regions_of,
+};
+pub use crate::numa::{NumaNode, current_node, hart_node, numa_nodes};
+pub use crate::percpu::init_percpu;
+pub use crate::preempt::{
+    PreemptGuard, preempt_count, preempt_disable, preempt_enable, preemptible, set_need_resched,
+    set_preempt_hook, take_need_resched,
+};
+pub use crate::signal::{SS_DISABLE, SS_ONSTACK, SigInfo, SignalDelivery, SignalStack};
+pub use crate::slab::{SlabBox, SlabCache, SlabStats};
+pub use crate::softirq::{
+    DEFERRED_EXIT_BUDGET_US, DEFERRED_QUEUE_SIZE, defer_work, has_deferred_work,
+    run_deferred_work, set_deferred_work_hook,
+};
+pub use crate::stats::{NR_IRQ_STATS, TrapStats, dump_trap_stats, trap_stats};
+pub use crate::tlb::{
+    active_cpus, clear_active_asid, flush_tlb_range, flush_tlb_range_on, set_active_asid,
+};
+pub use crate::trap::{
+    AccessType, DefaultTrapHandler, PageFaultInfo, TrapClass, TrapFrameArgs, TrapHandler,
+    TrapHandlerEntry, TrapKind, UserContext, register_trap_handler, set_default_trap_handler,
+    unregister_trap_handler,
+};
+
+use crate::addr::{PhysAddr, phys_to_virt};
 use crate::utils::OnceCell;
 use alloc::vec::Vec;
 use fdt::Fdt;
 
 
 //TODO：解决此处报错
+/// Hart ID of the current CPU, set by [`init_percpu`].
 #[unsafe(mantahal_macro::def_percpu)] 
 pub(crate) static CPU_ID: usize = 0;
 
 
 pub static CPU_COUNT: OnceCell<usize> = OnceCell::new();
 
-pub static MEMORY_AREAS: OnceCell<Vec<(usize, usize)>> = OnceCell::new();
+pub static MEMORY_REGIONS: OnceCell<Vec<MemoryRegion>> = OnceCell::new();
 
 pub static DEVICE_TREE_BLOB: OnceCell<Vec<u8>> = OnceCell::new();
 
+/// Physical address of the device tree blob handed over by the bootloader.
+/// Read through the linear map, never as a virtual address.
 #[allow(dead_code)]
 pub(crate) static DTB_PTR: OnceCell<usize> = OnceCell::new();
 
-/// Returns a reference to the vector describing physical memory regions.
+/// Returns the typed map of physical memory, sorted by start address.
 ///
-/// Each tuple in the vector represents `(start_address, end_address)` of a contiguous
-/// physical memory area available for use.
+/// RAM regions never overlap other regions: reserved memory, the kernel image,
+/// the DTB and the initrd are carved out of them, so every `MemoryKind::Ram`
+/// region is free for allocators. The kernel image is located through the
+/// `_skernel` and `_ekernel` symbols, which the kernel linker script must
+/// define around it; linking fails without them.
 ///
 /// # Panics
 ///
-/// Panics if the memory areas have not been initialized (e.g., via `MEMORY_AREAS.init(...)`)
+/// Panics if the memory regions have not been initialized (by `arch_init`)
 /// before this function is called. Ensure initialization occurs during early boot.
 #[inline]
-pub fn memory_areas() -> &'static Vec<(usize, usize)> {
-    MEMORY_AREAS.get()
+pub fn memory_regions() -> &'static [MemoryRegion] {
+    MEMORY_REGIONS.get()
 }
 
 /// Returns a reference to the raw Device Tree Blob (DTB) binary data.
@@ -79,5 +157,6 @@ pub fn cpu_count() -> usize {
 /// Get the fdt
 pub fn get_fdt() -> Option<Fdt<'static>> {
     // Fdt::new(&DTB_BIN).ok()
-    unsafe { Fdt::from_ptr(*DTB_PTR.get_unchecked() as *const u8).ok() }
+    let dtb_ptr = phys_to_virt(PhysAddr(*DTB_PTR.try_get()?)).0 as *const u8;
+    unsafe { Fdt::from_ptr(dtb_ptr).ok() }
 }
\ No newline at end of file
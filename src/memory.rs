@@ -0,0 +1,308 @@
+//! Typed physical memory map.
+//!
+//! The map is built once by `arch_init` from the device tree. Everything that
+//! must not be handed out (the FDT `/memreserve/` block, `/reserved-memory`
+//! children, the kernel image, the DTB and the initrd) is carved out of RAM,
+//! so the remaining [`MemoryKind::Ram`] regions are free for allocators.
+//!
+//! Every region is tagged with the NUMA node of the `/memory` node it came
+//! from, and RAM is split at [`DMA32_LIMIT`] so that each free region lies in
+//! exactly one [`MemoryZone`].
+//!
+//! The kernel image is located through the `_skernel` and `_ekernel` symbols,
+//! which the kernel linker script must provide.
+use crate::addr::{PhysAddr, PhysAddrRange, VirtAddr, virt_to_phys};
+use crate::arch::config::mm::PAGE_SIZE;
+use crate::{DTB_PTR, MEMORY_REGIONS, memory_regions};
+use alloc::vec::Vec;
+use core::ops::Range;
+use fdt::Fdt;
+use fdt::node::FdtNode;
+
+/// What a physical memory region is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free RAM, usable by allocators.
+    Ram,
+    /// Reserved by firmware through `/memreserve/` or `/reserved-memory`.
+    Reserved,
+    KernelImage,
+    /// The device tree blob passed by the bootloader.
+    Dtb,
+    Initrd,
+    /// Device registers described by the device tree.
+    Mmio,
+}
+
+/// Physical addresses below this limit are reachable by 32-bit DMA.
+pub const DMA32_LIMIT: usize = 1 << 32;
+
+/// Memory zones, distinguished by device addressing limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryZone {
+    /// Below [`DMA32_LIMIT`], for devices with 32-bit DMA.
+    Dma32,
+    Normal,
+}
+
+impl MemoryZone {
+    /// Returns the zone `pa` belongs to.
+    #[inline]
+    pub fn of(pa: PhysAddr) -> Self {
+        if pa.0 < DMA32_LIMIT {
+            MemoryZone::Dma32
+        } else {
+            MemoryZone::Normal
+        }
+    }
+}
+
+/// A contiguous range of physical memory.
+#[derive(Clone)]
+#[cfg_attr(feature = "debug", derive(Debug))]
+pub struct MemoryRegion {
+    pub range: PhysAddrRange,
+    pub kind: MemoryKind,
+    /// NUMA node the region belongs to, `0` without NUMA information.
+    pub node: usize,
+}
+
+impl MemoryRegion {
+    pub fn new(start: usize, end: usize, kind: MemoryKind) -> Self {
+        Self {
+            range: PhysAddrRange::new(PhysAddr(start), PhysAddr(end)),
+            kind,
+            node: 0,
+        }
+    }
+
+    /// Returns the zone of the region. Free RAM never straddles zones, other
+    /// regions are classified by their start address.
+    #[inline]
+    pub fn zone(&self) -> MemoryZone {
+        MemoryZone::of(self.range.start)
+    }
+
+    #[inline]
+    pub fn start(&self) -> PhysAddr {
+        self.range.start
+    }
+
+    #[inline]
+    pub fn end(&self) -> PhysAddr {
+        self.range.end
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.range.size()
+    }
+
+    #[inline]
+    pub fn contains(&self, pa: PhysAddr) -> bool {
+        self.range.contains(pa)
+    }
+}
+
+/// Returns the free RAM regions, i.e. what remains for the frame allocator.
+pub fn free_memory_regions() -> impl Iterator<Item = &'static MemoryRegion> {
+    memory_regions_of(MemoryKind::Ram)
+}
+
+/// Returns all regions of the given kind.
+pub fn memory_regions_of(kind: MemoryKind) -> impl Iterator<Item = &'static MemoryRegion> {
+    memory_regions()
+        .iter()
+        .filter(move |region| region.kind == kind)
+}
+
+/// Returns the free RAM regions of NUMA node `node` in `zone`.
+pub fn free_memory_regions_in(
+    node: usize,
+    zone: MemoryZone,
+) -> impl Iterator<Item = &'static MemoryRegion> {
+    free_memory_regions().filter(move |region| region.node == node && region.zone() == zone)
+}
+
+/// Returns the region containing `pa`, if any.
+pub fn find_memory_region(pa: PhysAddr) -> Option<&'static MemoryRegion> {
+    memory_regions().iter().find(|region| region.contains(pa))
+}
+
+/// Builder keeping RAM disjoint from every other region.
+struct MemoryMap {
+    regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMap {
+    fn add_ram(&mut self, start: usize, end: usize, node: usize) {
+        let mut region = MemoryRegion::new(start, end, MemoryKind::Ram);
+        region.node = node;
+        self.regions.push(region);
+    }
+
+    /// Record `start..end` as `kind` and carve it out of RAM.
+    fn reserve(&mut self, start: usize, end: usize, kind: MemoryKind) {
+        if start >= end {
+            return;
+        }
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        let mut node = None;
+        for region in self.regions.drain(..) {
+            let (ram_start, ram_end) = (region.start().0, region.end().0);
+            if region.kind != MemoryKind::Ram || ram_end <= start || ram_start >= end {
+                regions.push(region);
+                continue;
+            }
+            node.get_or_insert(region.node);
+            if ram_start < start {
+                regions.push(MemoryRegion {
+                    range: PhysAddrRange::new(region.start(), PhysAddr(start)),
+                    ..region.clone()
+                });
+            }
+            if end < ram_end {
+                regions.push(MemoryRegion {
+                    range: PhysAddrRange::new(PhysAddr(end), region.end()),
+                    ..region
+                });
+            }
+        }
+        // Regions carved out of RAM belong to the node of that RAM.
+        let mut region = MemoryRegion::new(start, end, kind);
+        region.node = node.unwrap_or(0);
+        regions.push(region);
+        self.regions = regions;
+    }
+
+    /// Shrink RAM to whole pages, split it at [`DMA32_LIMIT`] and sort the
+    /// map by address.
+    fn finish(mut self) -> Vec<MemoryRegion> {
+        let straddling = self.regions.iter().position(|region| {
+            region.kind == MemoryKind::Ram
+                && region.start().0 < DMA32_LIMIT
+                && region.end().0 > DMA32_LIMIT
+        });
+        if let Some(index) = straddling {
+            let high = MemoryRegion {
+                range: PhysAddrRange::new(PhysAddr(DMA32_LIMIT), self.regions[index].end()),
+                ..self.regions[index].clone()
+            };
+            self.regions[index].range.end = PhysAddr(DMA32_LIMIT);
+            self.regions.push(high);
+        }
+        for region in self.regions.iter_mut() {
+            if region.kind == MemoryKind::Ram {
+                let start = region.start().align_up(PAGE_SIZE);
+                let end = region.end().align_down(PAGE_SIZE);
+                region.range = PhysAddrRange::new(start, end.max(start));
+            }
+        }
+        self.regions.retain(|region| region.size() != 0);
+        self.regions.sort_by_key(|region| region.start());
+        self.regions
+    }
+}
+
+/// Build the memory map from `fdt` and publish it in [`MEMORY_REGIONS`].
+///
+/// `default_ram` is used when there is no device tree or it describes no memory.
+/// The kernel image is reserved from the `_skernel` and `_ekernel` linker
+/// symbols.
+pub(crate) fn init_memory_regions(fdt: Option<&Fdt>, default_ram: Range<usize>) {
+    unsafe extern "C" {
+        fn _skernel();
+        fn _ekernel();
+    }
+
+    let mut map = MemoryMap {
+        regions: Vec::new(),
+    };
+    if let Some(fdt) = fdt {
+        fdt.find_all_nodes("/memory").for_each(|node| {
+            let numa_node = node
+                .property("numa-node-id")
+                .and_then(|p| p.as_usize())
+                .unwrap_or(0);
+            if let Some(reg) = node.reg() {
+                reg.for_each(|x| {
+                    let start = x.starting_address as usize;
+                    map.add_ram(start, start + x.size.unwrap_or(0), numa_node);
+                });
+            }
+        });
+    }
+    if map.regions.is_empty() {
+        map.add_ram(default_ram.start, default_ram.end, 0);
+    }
+
+    map.reserve(
+        virt_to_phys(VirtAddr(_skernel as usize)).0,
+        virt_to_phys(VirtAddr(_ekernel as usize)).0,
+        MemoryKind::KernelImage,
+    );
+
+    if let Some(fdt) = fdt {
+        fdt.memory_reservations().for_each(|x| {
+            let start = x.address() as usize;
+            map.reserve(start, start + x.size(), MemoryKind::Reserved);
+        });
+        if let Some(node) = fdt.find_node("/reserved-memory") {
+            node.children().for_each(|child| {
+                reserve_reg(&mut map, child, MemoryKind::Reserved);
+            });
+        }
+        if let Some(&dtb_ptr) = DTB_PTR.try_get() {
+            map.reserve(dtb_ptr, dtb_ptr + fdt.total_size(), MemoryKind::Dtb);
+        }
+        if let Some(chosen) = fdt.find_node("/chosen") {
+            let initrd_start = chosen
+                .property("linux,initrd-start")
+                .and_then(|p| p.as_usize());
+            let initrd_end = chosen
+                .property("linux,initrd-end")
+                .and_then(|p| p.as_usize());
+            if let (Some(start), Some(end)) = (initrd_start, initrd_end) {
+                map.reserve(start, end, MemoryKind::Initrd);
+            }
+        }
+        if let Some(root) = fdt.find_node("/") {
+            root.children()
+                .for_each(|child| reserve_mmio(&mut map, child));
+        }
+    }
+
+    let regions = map.finish();
+    regions.iter().for_each(|region| {
+        log::info!(
+            "memory region {:#X} - {:#X} {:?} node {}",
+            region.start().0,
+            region.end().0,
+            region.kind,
+            region.node
+        );
+    });
+    MEMORY_REGIONS.init(regions);
+}
+
+fn reserve_reg(map: &mut MemoryMap, node: FdtNode, kind: MemoryKind) {
+    if let Some(reg) = node.reg() {
+        reg.for_each(|x| {
+            let start = x.starting_address as usize;
+            if let Some(size) = x.size {
+                map.reserve(start, start + size, kind);
+            }
+        });
+    }
+}
+
+/// Record the `reg` of every device node below `node` as MMIO.
+fn reserve_mmio(map: &mut MemoryMap, node: FdtNode) {
+    const NOT_DEVICES: [&str; 5] = ["memory", "reserved-memory", "cpus", "chosen", "aliases"];
+    let name = node.name.split('@').next().unwrap_or(node.name);
+    if NOT_DEVICES.contains(&name) {
+        return;
+    }
+    reserve_reg(map, node, MemoryKind::Mmio);
+    node.children().for_each(|child| reserve_mmio(map, child));
+}
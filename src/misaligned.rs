@@ -0,0 +1,135 @@
+//! Emulation of misaligned loads and stores.
+//!
+//! With the `misaligned` feature, the instruction at the PC of a misaligned
+//! access trap is decoded. If it is a load or store whose address is
+//! misaligned for its width, the access is performed bytewise on the saved
+//! [`TrapRegs`], and the PC is advanced past the instruction. `run_user_task`
+//! does so for user traps and resumes the task right away, a built-in
+//! [`TrapClass::AddressError`] handler for kernel traps. Floating-point loads
+//! and stores are emulated as well when the `fp` feature is enabled, on the
+//! live floating-point registers.
+//!
+//! User memory is accessed through the fault-tolerant user access routines, so
+//! a misaligned access to unmapped user memory is left to the next handler.
+pub(crate) mod insn;
+
+pub(crate) use insn::{Access, Reg};
+
+use crate::arch::misaligned::decode;
+use crate::arch::trapframe::TrapRegs;
+use crate::extable::{copy_from_user, copy_to_user};
+use crate::trap::{TrapClass, TrapHandlerEntry, TrapKind};
+
+/// Generate `read_fpr` and `write_fpr`, accessing the floating-point register
+/// of a runtime index through the given register names.
+#[cfg(feature = "fp")]
+macro_rules! fpr_access {
+    ($($n:literal => $reg:tt),* $(,)?) => {
+        /// Returns the raw bits of floating-point register `n`.
+        pub(crate) fn read_fpr(n: usize) -> u64 {
+            let value: f64;
+            match n {
+                $($n => unsafe { core::arch::asm!("", out($reg) value) },)*
+                _ => unreachable!(),
+            }
+            value.to_bits()
+        }
+
+        /// Write the raw `bits` to floating-point register `n`.
+        pub(crate) fn write_fpr(n: usize, bits: u64) {
+            match n {
+                $($n => unsafe { core::arch::asm!("", in($reg) f64::from_bits(bits)) },)*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+#[cfg(feature = "fp")]
+pub(crate) use fpr_access;
+
+/// Copy `buf.len()` bytes at `addr` into `buf`, from user memory if the trap
+/// was taken from user mode.
+pub(crate) fn read_mem(tf: &TrapRegs, addr: usize, buf: &mut [u8]) -> bool {
+    if tf.is_user() {
+        unsafe { copy_from_user(buf.as_mut_ptr(), addr as *const u8, buf.len()) == 0 }
+    } else {
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        true
+    }
+}
+
+fn write_mem(tf: &TrapRegs, addr: usize, buf: &[u8]) -> bool {
+    if tf.is_user() {
+        unsafe { copy_to_user(addr as *mut u8, buf.as_ptr(), buf.len()) == 0 }
+    } else {
+        unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), addr as *mut u8, buf.len()) };
+        true
+    }
+}
+
+/// Returns the register operand of `access`, truncated to the access width.
+fn read_reg(tf: &TrapRegs, access: &Access) -> u64 {
+    let value = match access.reg {
+        Reg::Gpr(n) => tf.gpr(n) as u64,
+        #[cfg(feature = "fp")]
+        Reg::Fpr(n) => crate::arch::misaligned::read_fpr(n),
+    };
+    value & (u64::MAX >> (64 - access.width * 8))
+}
+
+fn write_reg(tf: &mut TrapRegs, access: &Access, value: u64) {
+    match access.reg {
+        Reg::Gpr(n) => tf.set_gpr(n, value as usize),
+        #[cfg(feature = "fp")]
+        Reg::Fpr(n) => crate::arch::misaligned::set_fpr(tf, n, access.width, value),
+    }
+}
+
+/// Emulate the misaligned access that trapped at the PC of `tf`.
+///
+/// Returns `false` if the instruction is not a misaligned load or store, or
+/// if the memory is inaccessible.
+pub(crate) fn emulate(tf: &mut TrapRegs) -> bool {
+    let Some(access) = decode(tf) else {
+        return false;
+    };
+    if access.addr % access.width == 0 {
+        return false;
+    }
+    let mut bytes = [0u8; 8];
+    let bytes = &mut bytes[..access.width];
+    if access.store {
+        let value = read_reg(tf, &access);
+        bytes.copy_from_slice(&value.to_le_bytes()[..access.width]);
+        if !write_mem(tf, access.addr, bytes) {
+            return false;
+        }
+    } else {
+        if !read_mem(tf, access.addr, bytes) {
+            return false;
+        }
+        let mut raw = [0u8; 8];
+        raw[..access.width].copy_from_slice(bytes);
+        let mut value = u64::from_le_bytes(raw);
+        if access.signed {
+            let shift = 64 - access.width * 8;
+            value = (((value << shift) as i64) >> shift) as u64;
+        }
+        write_reg(tf, &access, value);
+    }
+    tf.set_pc(tf.pc() + access.insn_len);
+    true
+}
+
+/// Built-in handler emulating misaligned accesses in kernel mode. It runs
+/// before every other address error handler.
+#[used(linker)]
+#[unsafe(link_section = "trap_handlers")]
+static MISALIGNED_EMULATOR: TrapHandlerEntry = TrapHandlerEntry {
+    class: TrapClass::AddressError,
+    priority: i32::MAX,
+    handler: |tf, kind| match kind {
+        TrapKind::AddressError { .. } if !tf.is_user() => emulate(tf),
+        _ => false,
+    },
+};
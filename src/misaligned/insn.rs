@@ -0,0 +1,392 @@
+//! Decoding of the loads and stores of both architectures.
+//!
+//! It only depends on `core`, so its tests run on the host, see
+//! `host-tests`.
+
+/// Register operand of a load or store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reg {
+    /// General-purpose register.
+    Gpr(usize),
+    /// Floating-point register.
+    #[cfg(feature = "fp")]
+    Fpr(usize),
+}
+
+/// A decoded load or store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Access {
+    pub store: bool,
+    /// Sign-extend the loaded value to the register width.
+    pub signed: bool,
+    /// Access width in bytes.
+    pub width: usize,
+    pub reg: Reg,
+    pub addr: usize,
+    /// Length of the instruction in bytes.
+    pub insn_len: usize,
+}
+
+/// Extract `len` bits of `insn` starting at bit `lo`.
+#[inline]
+const fn bits(insn: u32, lo: u32, len: u32) -> usize {
+    ((insn >> lo) & ((1 << len) - 1)) as usize
+}
+
+/// Sign-extend the low `len` bits of `value`.
+#[inline]
+const fn sext(value: usize, len: u32) -> usize {
+    (((value << (64 - len)) as isize) >> (64 - len)) as usize
+}
+
+/// RISC-V loads and stores, standard and compressed.
+#[cfg(any(test, target_arch = "riscv64"))]
+pub(crate) mod riscv64 {
+    use super::{Reg, bits, sext};
+
+    const OP_LOAD: u32 = 0x03;
+    #[cfg(feature = "fp")]
+    const OP_LOAD_FP: u32 = 0x07;
+    const OP_STORE: u32 = 0x23;
+    #[cfg(feature = "fp")]
+    const OP_STORE_FP: u32 = 0x27;
+
+    /// Store flag, signedness, width, register, base register and offset of an
+    /// access.
+    pub(crate) type Decoded = (bool, bool, usize, Reg, usize, usize);
+
+    pub(crate) fn decode_32(insn: u32) -> Option<Decoded> {
+        let funct3 = bits(insn, 12, 3);
+        let rd = bits(insn, 7, 5);
+        let rs1 = bits(insn, 15, 5);
+        let rs2 = bits(insn, 20, 5);
+        let load_imm = sext(bits(insn, 20, 12), 12);
+        let store_imm = sext((bits(insn, 25, 7) << 5) | bits(insn, 7, 5), 12);
+        match bits(insn, 0, 7) as u32 {
+            OP_LOAD => {
+                // lb, lh, lw, ld, lbu, lhu, lwu
+                let width = 1 << (funct3 & 0b11);
+                let signed = funct3 < 4;
+                (funct3 != 7).then_some((false, signed, width, Reg::Gpr(rd), rs1, load_imm))
+            }
+            OP_STORE if funct3 < 4 => {
+                Some((true, false, 1 << funct3, Reg::Gpr(rs2), rs1, store_imm))
+            }
+            #[cfg(feature = "fp")]
+            OP_LOAD_FP if funct3 == 2 || funct3 == 3 => {
+                Some((false, false, 1 << funct3, Reg::Fpr(rd), rs1, load_imm))
+            }
+            #[cfg(feature = "fp")]
+            OP_STORE_FP if funct3 == 2 || funct3 == 3 => {
+                Some((true, false, 1 << funct3, Reg::Fpr(rs2), rs1, store_imm))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn decode_16(insn: u32) -> Option<Decoded> {
+        let funct3 = bits(insn, 13, 3);
+        // Registers x8 to x15 of the CL and CS formats.
+        let rd_short = bits(insn, 2, 3) + 8;
+        let rs1_short = bits(insn, 7, 3) + 8;
+        // c.lw / c.sw offset
+        let word_off = (bits(insn, 10, 3) << 3) | (bits(insn, 6, 1) << 2) | (bits(insn, 5, 1) << 6);
+        // c.ld / c.sd / c.fld / c.fsd offset
+        let double_off = (bits(insn, 10, 3) << 3) | (bits(insn, 5, 2) << 6);
+        // Stack-relative forms of quadrant 2.
+        let rd = bits(insn, 7, 5);
+        let rs2 = bits(insn, 2, 5);
+        let lwsp_off = (bits(insn, 12, 1) << 5) | (bits(insn, 4, 3) << 2) | (bits(insn, 2, 2) << 6);
+        let ldsp_off = (bits(insn, 12, 1) << 5) | (bits(insn, 5, 2) << 3) | (bits(insn, 2, 3) << 6);
+        let swsp_off = (bits(insn, 9, 4) << 2) | (bits(insn, 7, 2) << 6);
+        let sdsp_off = (bits(insn, 10, 3) << 3) | (bits(insn, 7, 3) << 6);
+        const SP: usize = 2;
+        match (bits(insn, 0, 2), funct3) {
+            // c.lw, c.ld, c.sw, c.sd
+            (0b00, 0b010) => Some((false, true, 4, Reg::Gpr(rd_short), rs1_short, word_off)),
+            (0b00, 0b011) => Some((false, false, 8, Reg::Gpr(rd_short), rs1_short, double_off)),
+            (0b00, 0b110) => Some((true, false, 4, Reg::Gpr(rd_short), rs1_short, word_off)),
+            (0b00, 0b111) => Some((true, false, 8, Reg::Gpr(rd_short), rs1_short, double_off)),
+            // c.fld, c.fsd
+            #[cfg(feature = "fp")]
+            (0b00, 0b001) => Some((false, false, 8, Reg::Fpr(rd_short), rs1_short, double_off)),
+            #[cfg(feature = "fp")]
+            (0b00, 0b101) => Some((true, false, 8, Reg::Fpr(rd_short), rs1_short, double_off)),
+            // c.lwsp, c.ldsp, c.swsp, c.sdsp
+            (0b10, 0b010) if rd != 0 => Some((false, true, 4, Reg::Gpr(rd), SP, lwsp_off)),
+            (0b10, 0b011) if rd != 0 => Some((false, false, 8, Reg::Gpr(rd), SP, ldsp_off)),
+            (0b10, 0b110) => Some((true, false, 4, Reg::Gpr(rs2), SP, swsp_off)),
+            (0b10, 0b111) => Some((true, false, 8, Reg::Gpr(rs2), SP, sdsp_off)),
+            // c.fldsp, c.fsdsp
+            #[cfg(feature = "fp")]
+            (0b10, 0b001) => Some((false, false, 8, Reg::Fpr(rd), SP, ldsp_off)),
+            #[cfg(feature = "fp")]
+            (0b10, 0b101) => Some((true, false, 8, Reg::Fpr(rs2), SP, sdsp_off)),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const fn neg(offset: usize) -> usize {
+            offset.wrapping_neg()
+        }
+
+        #[test]
+        fn standard_forms() {
+            // lw a0, 4(a1)
+            assert_eq!(
+                decode_32(0x0045a503),
+                Some((false, true, 4, Reg::Gpr(10), 11, 4))
+            );
+            // lhu t0, -2(sp)
+            assert_eq!(
+                decode_32(0xffe15283),
+                Some((false, false, 2, Reg::Gpr(5), 2, neg(2)))
+            );
+            // lwu a0, 0(a1)
+            assert_eq!(
+                decode_32(0x0005e503),
+                Some((false, false, 4, Reg::Gpr(10), 11, 0))
+            );
+            // sd a0, -8(s0)
+            assert_eq!(
+                decode_32(0xfea43c23),
+                Some((true, false, 8, Reg::Gpr(10), 8, neg(8)))
+            );
+            // addi a0, a0, 1
+            assert_eq!(decode_32(0x00150513), None);
+            // A load with funct3 7, which is reserved.
+            assert_eq!(decode_32(0x0005f503), None);
+        }
+
+        #[test]
+        fn compressed_forms() {
+            // c.lw a0, 4(a1)
+            assert_eq!(
+                decode_16(0x41c8),
+                Some((false, true, 4, Reg::Gpr(10), 11, 4))
+            );
+            // c.sd s1, 24(a5)
+            assert_eq!(
+                decode_16(0xef84),
+                Some((true, false, 8, Reg::Gpr(9), 15, 24))
+            );
+            // c.lwsp a0, 12(sp)
+            assert_eq!(
+                decode_16(0x4532),
+                Some((false, true, 4, Reg::Gpr(10), 2, 12))
+            );
+            // c.sdsp ra, 8(sp)
+            assert_eq!(decode_16(0xe406), Some((true, false, 8, Reg::Gpr(1), 2, 8)));
+            // c.ldsp with rd x0, which is reserved.
+            assert_eq!(decode_16(0x6002), None);
+            // c.addi a0, 1
+            assert_eq!(decode_16(0x0505), None);
+        }
+
+        #[cfg(feature = "fp")]
+        #[test]
+        fn floating_point() {
+            // fld fa0, 16(a1)
+            assert_eq!(
+                decode_32(0x0105b507),
+                Some((false, false, 8, Reg::Fpr(10), 11, 16))
+            );
+            // fsw ft1, 12(a2)
+            assert_eq!(
+                decode_32(0x00162627),
+                Some((true, false, 4, Reg::Fpr(1), 12, 12))
+            );
+            // c.fld fa0, 8(a0)
+            assert_eq!(
+                decode_16(0x2508),
+                Some((false, false, 8, Reg::Fpr(10), 10, 8))
+            );
+        }
+    }
+}
+
+/// LoongArch loads and stores.
+#[cfg(any(test, target_arch = "loongarch64"))]
+pub(crate) mod loongarch64 {
+    use super::{Access, Reg, bits, sext};
+
+    /// Store flag, signedness, width and register of a load or store opcode.
+    type Operation = (bool, bool, usize, Reg);
+
+    /// `ld.*` / `st.*` / `fld.*` / `fst.*` with a 12-bit immediate, by bits
+    /// `[31:22]`. Also the `ldx.*` / `stx.*` / `fldx.*` / `fstx.*` indexed forms,
+    /// by bits `[31:15]` with the same low bits.
+    fn operation(op: usize, rd: usize) -> Option<Operation> {
+        let gpr = Reg::Gpr(rd);
+        Some(match op {
+            0x0 => (false, true, 1, gpr),
+            0x1 => (false, true, 2, gpr),
+            0x2 => (false, true, 4, gpr),
+            0x3 => (false, false, 8, gpr),
+            0x4 => (true, false, 1, gpr),
+            0x5 => (true, false, 2, gpr),
+            0x6 => (true, false, 4, gpr),
+            0x7 => (true, false, 8, gpr),
+            0x8 => (false, false, 1, gpr),
+            0x9 => (false, false, 2, gpr),
+            0xa => (false, false, 4, gpr),
+            #[cfg(feature = "fp")]
+            0xc => (false, false, 4, Reg::Fpr(rd)),
+            #[cfg(feature = "fp")]
+            0xd => (true, false, 4, Reg::Fpr(rd)),
+            #[cfg(feature = "fp")]
+            0xe => (false, false, 8, Reg::Fpr(rd)),
+            #[cfg(feature = "fp")]
+            0xf => (true, false, 8, Reg::Fpr(rd)),
+            _ => return None,
+        })
+    }
+
+    /// Decode the load or store `insn`, with `gpr` returning the value of a
+    /// general-purpose register.
+    pub(crate) fn decode_insn(insn: u32, gpr: impl Fn(usize) -> usize) -> Option<Access> {
+        let rd = bits(insn, 0, 5);
+        let rj = gpr(bits(insn, 5, 5));
+        let (op, addr) = match insn >> 22 {
+            // 2RI12: ld.b ... fst.d
+            op @ 0x0a0..=0x0af => (
+                op as usize - 0x0a0,
+                rj.wrapping_add(sext(bits(insn, 10, 12), 12)),
+            ),
+            _ => match insn >> 24 {
+                // 2RI14: ldptr.w, stptr.w, ldptr.d, stptr.d
+                op @ 0x24..=0x27 => {
+                    let offset = sext(bits(insn, 10, 14), 14) << 2;
+                    let op = [0x2, 0x6, 0x3, 0x7][op as usize - 0x24];
+                    (op, rj.wrapping_add(offset))
+                }
+                // 3R: ldx.b ... fstx.d
+                _ => match insn >> 15 {
+                    op @ 0x7000..=0x7078 if op % 8 == 0 => {
+                        // fldx.d and fstx.s are ordered the other way round than
+                        // fld.d and fst.s.
+                        let op = match (op as usize - 0x7000) / 8 {
+                            0xd => 0xe,
+                            0xe => 0xd,
+                            op => op,
+                        };
+                        (op, rj.wrapping_add(gpr(bits(insn, 10, 5))))
+                    }
+                    _ => return None,
+                },
+            },
+        };
+        let (store, signed, width, reg) = operation(op, rd)?;
+        Some(Access {
+            store,
+            signed,
+            width,
+            reg,
+            addr,
+            insn_len: 4,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn access(store: bool, signed: bool, width: usize, reg: Reg, addr: usize) -> Access {
+            Access {
+                store,
+                signed,
+                width,
+                reg,
+                addr,
+                insn_len: 4,
+            }
+        }
+
+        fn decode(insn: u32) -> Option<Access> {
+            decode_insn(insn, |n| match n {
+                3 => 0x2000, // $sp
+                5 => 0x1000, // $a1
+                6 => 0x3000, // $a2
+                _ => 0,
+            })
+        }
+
+        #[test]
+        fn immediate_forms() {
+            // ld.w $a0, $a1, -4
+            assert_eq!(
+                decode(0x28bff0a4),
+                Some(access(false, true, 4, Reg::Gpr(4), 0xffc))
+            );
+            // ld.hu $t0, $sp, 6
+            assert_eq!(
+                decode(0x2a40186c),
+                Some(access(false, false, 2, Reg::Gpr(12), 0x2006))
+            );
+            // st.d $a0, $a1, 16
+            assert_eq!(
+                decode(0x29c040a4),
+                Some(access(true, false, 8, Reg::Gpr(4), 0x1010))
+            );
+            // ldptr.d $a0, $a1, 8
+            assert_eq!(
+                decode(0x260008a4),
+                Some(access(false, false, 8, Reg::Gpr(4), 0x1008))
+            );
+            // stptr.w $a0, $a1, -4
+            assert_eq!(
+                decode(0x25fffca4),
+                Some(access(true, false, 4, Reg::Gpr(4), 0xffc))
+            );
+        }
+
+        #[test]
+        fn indexed_forms() {
+            // ldx.w $a0, $a1, $a2
+            assert_eq!(
+                decode(0x380818a4),
+                Some(access(false, true, 4, Reg::Gpr(4), 0x4000))
+            );
+            // stx.b $a0, $a1, $a2
+            assert_eq!(
+                decode(0x381018a4),
+                Some(access(true, false, 1, Reg::Gpr(4), 0x4000))
+            );
+        }
+
+        #[cfg(feature = "fp")]
+        #[test]
+        fn floating_point() {
+            // fld.d $fa0, $a1, 8
+            assert_eq!(
+                decode(0x2b8020a0),
+                Some(access(false, false, 8, Reg::Fpr(0), 0x1008))
+            );
+            // fst.s $fa1, $a2, 4
+            assert_eq!(
+                decode(0x2b4010c1),
+                Some(access(true, false, 4, Reg::Fpr(1), 0x3004))
+            );
+            // fldx.d $fa0, $a1, $a2
+            assert_eq!(
+                decode(0x383418a0),
+                Some(access(false, false, 8, Reg::Fpr(0), 0x4000))
+            );
+            // fstx.s $fa0, $a1, $a2
+            assert_eq!(
+                decode(0x383818a0),
+                Some(access(true, false, 4, Reg::Fpr(0), 0x4000))
+            );
+        }
+
+        #[test]
+        fn not_an_access() {
+            // addi.d $a0, $a0, 1
+            assert_eq!(decode(0x02c00484), None);
+        }
+    }
+}
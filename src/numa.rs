@@ -0,0 +1,90 @@
+//! NUMA topology.
+//!
+//! Nodes are discovered from the `numa-node-id` property of the device tree
+//! `/memory` and `/cpus/cpu` nodes. Without it, every hart and all memory
+//! belong to node `0`.
+use crate::arch::arch::hart_id;
+use crate::arch::config::mm::PAGE_SIZE;
+use crate::memory::free_memory_regions;
+use crate::utils::OnceCell;
+use alloc::vec::Vec;
+use fdt::Fdt;
+
+/// A NUMA node.
+#[derive(Debug, Clone)]
+pub struct NumaNode {
+    pub id: usize,
+    /// Hart IDs of the CPUs local to this node.
+    pub harts: Vec<usize>,
+    /// Number of free RAM frames local to this node.
+    pub free_pages: usize,
+}
+
+static NUMA_NODES: OnceCell<Vec<NumaNode>> = OnceCell::new();
+
+/// Build the node topology from `fdt` and the tagged memory regions.
+///
+/// Must be called after the memory regions have been initialized.
+pub(crate) fn init_numa(fdt: Option<&Fdt>) {
+    fn node_mut(nodes: &mut Vec<NumaNode>, id: usize) -> &mut NumaNode {
+        match nodes.iter().position(|node| node.id == id) {
+            Some(index) => &mut nodes[index],
+            None => {
+                nodes.push(NumaNode {
+                    id,
+                    harts: Vec::new(),
+                    free_pages: 0,
+                });
+                nodes.last_mut().unwrap()
+            }
+        }
+    }
+
+    let mut nodes = Vec::new();
+    free_memory_regions().for_each(|region| {
+        node_mut(&mut nodes, region.node).free_pages += region.size() / PAGE_SIZE;
+    });
+    if let Some(fdt) = fdt.filter(|fdt| fdt.find_node("/cpus").is_some()) {
+        fdt.cpus().for_each(|cpu| {
+            let id = cpu
+                .property("numa-node-id")
+                .and_then(|p| p.as_usize())
+                .unwrap_or(0);
+            node_mut(&mut nodes, id).harts.push(cpu.ids().first());
+        });
+    }
+    if nodes.is_empty() {
+        node_mut(&mut nodes, 0);
+    }
+    nodes.sort_by_key(|node| node.id);
+    nodes.iter().for_each(|node| {
+        log::info!(
+            "NUMA node {}: harts {:?}, {} free pages",
+            node.id,
+            node.harts,
+            node.free_pages
+        );
+    });
+    NUMA_NODES.init(nodes);
+}
+
+/// Returns all NUMA nodes, sorted by ID.
+///
+/// # Panics
+/// Panics if the topology has not been initialized by `arch_init`.
+pub fn numa_nodes() -> &'static [NumaNode] {
+    NUMA_NODES.get()
+}
+
+/// Returns the node of hart `hart`, `0` if unknown.
+pub fn hart_node(hart: usize) -> usize {
+    numa_nodes()
+        .iter()
+        .find(|node| node.harts.contains(&hart))
+        .map_or(0, |node| node.id)
+}
+
+/// Returns the node of the current hart.
+pub fn current_node() -> usize {
+    hart_node(hart_id())
+}
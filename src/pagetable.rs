@@ -1,4 +1,6 @@
-use super::addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use super::addr::{
+    PhysAddr, PhysPageNum, VirtAddr, VirtAddrRange, VirtPageNum, VirtPageRange, phys_to_virt,
+};
 use super::frame_allocator::{FrameTracker, frame_alloc};
 use crate::bit;
 use alloc::string::String;
@@ -230,6 +232,20 @@ impl<T: PTOps> PageTable<T> {
         // Do we need this?
     }
 
+    /// Map the pages in `vpns` to consecutive frames starting at `ppn`.
+    pub fn map_range(&mut self, vpns: VirtPageRange, ppn: PhysPageNum, flags: PTEFlags) {
+        for (i, vpn) in vpns.into_iter().enumerate() {
+            self.map(vpn, ppn + i, flags);
+        }
+    }
+
+    /// Unmap every page in `vpns`. See [`Self::unmap`].
+    pub fn unmap_range(&mut self, vpns: VirtPageRange) {
+        for vpn in vpns {
+            self.unmap(vpn);
+        }
+    }
+
     /// Translate a virtual page number to its corresponding PageTableEntry (if validly mapped).
     pub fn translate_vpn(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte) // Copy the entry
@@ -253,41 +269,35 @@ pub unsafe fn translate_byte_buffer<T: PTOps>(
     len: usize,
 ) -> Option<Vec<&'static mut [u8]>> {
     // Should likely be &'static [u8] if only checking R
-    let mut start = ptr as usize;
-    let end = start.checked_add(len)?;
+    if len == 0 {
+        return Some(Vec::new());
+    }
+    let buffer = VirtAddrRange::from_size(VirtAddr(ptr as usize), len)?;
     let mut result_slices = Vec::new();
 
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let vpn = T::va_to_vpn(start_va);
-
+    for vpn in buffer.pages() {
         let pte = pt.find_pte(vpn)?; // Checks validity implicitly
         let flags = T::pte_to_generic_flags(pte);
+        // The part of the buffer that lies in this page.
+        let chunk = VirtAddrRange::from(VirtPageRange::new(vpn, vpn + 1)).intersection(&buffer)?;
         if !flags.contains(PTEFlags::R) {
             log::warn!(
                 "Attempt to read from non-readable page: VA {:?}, Flags {:?}",
-                start_va,
+                chunk.start,
                 flags
             );
             return None;
         }
 
         let page_start_pa = T::ppn_to_pa(T::pte_to_ppn(pte));
-        let page_offset = start_va.page_offset();
-        let current_phys_addr = usize::from(page_start_pa) + page_offset;
+        let current_phys_addr = page_start_pa + chunk.start.page_offset();
 
-        let page_remaining = T::PAGE_SIZE - page_offset;
-        let requested_remaining = end - start;
-        let current_chunk_len = usize::min(page_remaining, requested_remaining);
-
-        let phys_ptr = current_phys_addr as *mut u8; // Need mutable? Or const?
+        let phys_ptr = phys_to_virt(current_phys_addr).0 as *mut u8; // Need mutable? Or const?
         // Let's assume read-only for byte buffer view:
-        // let slice = slice::from_raw_parts(phys_ptr as *const u8, current_chunk_len);
+        // let slice = slice::from_raw_parts(phys_ptr as *const u8, chunk.size());
         // If mutable access is needed, check PTEFlags::W as well. Assuming read-only here.
-        let slice = unsafe { core::slice::from_raw_parts_mut(phys_ptr, current_chunk_len) }; // Original was mut
+        let slice = unsafe { core::slice::from_raw_parts_mut(phys_ptr, chunk.size()) }; // Original was mut
         result_slices.push(slice);
-
-        start += current_chunk_len;
     }
     Some(result_slices)
 }
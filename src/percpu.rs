@@ -0,0 +1,84 @@
+//! Per-CPU data areas.
+//!
+//! Statics declared with `#[def_percpu]` live in the `percpu` link section,
+//! located through the `__start_percpu` and `__stop_percpu` symbols the
+//! linker provides. The section is only a template: every CPU works on its
+//! own copy of it, whose address is held in a base register, `gp` on RISC-V
+//! and `$r21` on LoongArch. Kernels leave that register alone, so RISC-V
+//! kernels must not be linked with global pointer relaxation.
+//!
+//! Every CPU calls [`init_percpu`] before it takes a lock or touches per-CPU
+//! data, preemption control included. The first CPU gets a static area, the
+//! others an area from the global allocator, so the heap must be up by the
+//! time they start.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::alloc::{Layout, alloc};
+use spin::Mutex;
+
+use crate::CPU_ID;
+use crate::arch::arch::set_percpu_base;
+use crate::config::PERCPU_AREA_SIZE;
+
+/// Alignment of the areas, at least that of any per-CPU data.
+const PERCPU_ALIGN: usize = 64;
+
+#[repr(C, align(64))]
+struct PercpuArea([u8; PERCPU_AREA_SIZE]);
+
+static mut BOOT_AREA: PercpuArea = PercpuArea([0; PERCPU_AREA_SIZE]);
+static BOOT_AREA_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The area a CPU runs on while allocating its own, as the allocator takes
+/// locks which update per-CPU data.
+static mut SCRATCH_AREA: PercpuArea = PercpuArea([0; PERCPU_AREA_SIZE]);
+static SCRATCH_LOCK: Mutex<()> = Mutex::new(());
+
+fn template() -> &'static [u8] {
+    unsafe extern "Rust" {
+        fn __start_percpu();
+        fn __stop_percpu();
+    }
+    let start = __start_percpu as usize;
+    let len = __stop_percpu as usize - start;
+    unsafe { core::slice::from_raw_parts(start as *const u8, len) }
+}
+
+/// Fill `area` from the template and make it the area of the current CPU.
+unsafe fn switch_to(area: *mut u8) {
+    let template = template();
+    unsafe {
+        core::ptr::copy_nonoverlapping(template.as_ptr(), area, template.len());
+        set_percpu_base(area as usize);
+    }
+}
+
+/// Set up the per-CPU data area of the current CPU, whose hart ID `cpu` is
+/// then returned by `hart_id`. Every CPU calls it once, first thing after
+/// boot, with interrupts disabled.
+///
+/// # Panics
+///
+/// Panics if the per-CPU data exceeds [`PERCPU_AREA_SIZE`] or the area of a
+/// secondary CPU cannot be allocated.
+pub fn init_percpu(cpu: usize) {
+    let len = template().len();
+    assert!(
+        len <= PERCPU_AREA_SIZE,
+        "{len} bytes of per-CPU data exceed PERCPU_AREA_SIZE"
+    );
+    if BOOT_AREA_TAKEN.swap(true, Ordering::AcqRel) {
+        let _scratch = SCRATCH_LOCK.lock();
+        unsafe { switch_to((&raw mut SCRATCH_AREA).cast()) };
+        let layout = Layout::from_size_align(len.max(1), PERCPU_ALIGN).unwrap();
+        let area = unsafe { alloc(layout) };
+        assert!(
+            !area.is_null(),
+            "cannot allocate the per-CPU area of CPU {cpu}"
+        );
+        unsafe { switch_to(area) };
+    } else {
+        unsafe { switch_to((&raw mut BOOT_AREA).cast()) };
+    }
+    unsafe { CPU_ID.write_current_raw(cpu) };
+}
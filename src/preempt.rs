@@ -0,0 +1,129 @@
+//! Kernel preemption control.
+//!
+//! Every CPU counts the sections that must not be preempted, such as per-CPU
+//! data accesses and held [`MutexNoIrq`](crate::utils::MutexNoIrq) locks. A
+//! scheduler checks [`preemptible`] before switching tasks on an interrupt,
+//! and otherwise defers the switch with [`set_need_resched`]: the hook set
+//! with [`set_preempt_hook`] runs once the last section ends.
+//!
+//! The counters are per-CPU data, so a CPU takes no lock before it called
+//! [`init_percpu`](crate::percpu::init_percpu).
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::irq::Irq;
+
+#[unsafe(mantahal_macro::def_percpu)]
+static PREEMPT_COUNT: usize = 0;
+
+#[unsafe(mantahal_macro::def_percpu)]
+static NEED_RESCHED: bool = false;
+
+/// The hook run when a deferred reschedule becomes possible, or 0.
+static PREEMPT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Disables preemption until the matching [`preempt_enable`]. Calls nest.
+pub fn preempt_disable() {
+    // The counter is updated with interrupts disabled, so the task cannot be
+    // migrated between locating and updating it.
+    let irq_enabled = Irq::interrupt_enabled();
+    unsafe {
+        Irq::disable_interrupt();
+        *PREEMPT_COUNT.current_ref_mut_raw() += 1;
+        if irq_enabled {
+            Irq::enable_interrupt();
+        }
+    }
+}
+
+/// Re-enables preemption disabled by [`preempt_disable`].
+///
+/// If this ends the last section and a reschedule was deferred, the preempt
+/// hook runs, unless interrupts are disabled. The reschedule then stays
+/// pending for the scheduler to pick up on its next interrupt.
+pub fn preempt_enable() {
+    let irq_enabled = Irq::interrupt_enabled();
+    let resched = unsafe {
+        Irq::disable_interrupt();
+        let count = PREEMPT_COUNT.current_ref_mut_raw();
+        debug_assert!(*count > 0, "unbalanced preempt_enable");
+        *count -= 1;
+        let resched = *count == 0 && irq_enabled && NEED_RESCHED.read_current_raw();
+        if resched {
+            NEED_RESCHED.write_current_raw(false);
+        }
+        if irq_enabled {
+            Irq::enable_interrupt();
+        }
+        resched
+    };
+    if resched {
+        let hook = PREEMPT_HOOK.load(Ordering::Acquire);
+        if hook != 0 {
+            let hook: fn() = unsafe { core::mem::transmute(hook) };
+            hook();
+        }
+    }
+}
+
+/// Returns the number of non-preemptible sections active on this CPU.
+pub fn preempt_count() -> usize {
+    unsafe { PREEMPT_COUNT.read_current_raw() }
+}
+
+/// Whether the current task may be switched out, i.e. no non-preemptible
+/// section is active on this CPU.
+pub fn preemptible() -> bool {
+    preempt_count() == 0
+}
+
+/// Request a reschedule of this CPU once it becomes preemptible, typically
+/// from its timer interrupt.
+pub fn set_need_resched() {
+    unsafe { NEED_RESCHED.write_current_raw(true) };
+}
+
+/// Whether a reschedule of this CPU is pending, clearing the request. Called
+/// with interrupts disabled, e.g. on the way back from an interrupt.
+pub fn take_need_resched() -> bool {
+    unsafe { core::mem::replace(NEED_RESCHED.current_ref_mut_raw(), false) }
+}
+
+/// Set the hook run by [`preempt_enable`] when a reschedule deferred with
+/// [`set_need_resched`] becomes possible. It typically yields the current
+/// task.
+pub fn set_preempt_hook(hook: fn()) {
+    PREEMPT_HOOK.store(hook as usize, Ordering::Release);
+}
+
+/// Disables preemption until dropped.
+pub struct PreemptGuard(());
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        preempt_disable();
+        Self(())
+    }
+}
+
+impl Default for PreemptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+// Called by the per-CPU data accessors `def_percpu` generates.
+#[unsafe(no_mangle)]
+fn __percpu_preempt_disable() {
+    preempt_disable();
+}
+
+#[unsafe(no_mangle)]
+fn __percpu_preempt_enable() {
+    preempt_enable();
+}
@@ -0,0 +1,172 @@
+//! Signal frames of the Linux user ABI.
+//!
+//! `TrapFrame::setup_sigframe` pushes an `rt_sigframe`, i.e. a `siginfo`
+//! followed by a `ucontext` holding the interrupted registers, the
+//! floating-point and vector state and the signal mask, onto the user stack, and enters
+//! the handler as `handler(signo, &info, &uc)` returning to the restorer.
+//! `TrapFrame::restore_from_sigframe` undoes it on `rt_sigreturn`.
+//!
+//! The frame layouts match the Linux `ucontext` of each architecture, so
+//! unmodified C libraries can inspect and modify them.
+use crate::extable::{copy_from_user, copy_to_user};
+
+/// `SignalStack::flags`: the thread is running on the alternate stack.
+pub const SS_ONSTACK: i32 = 1;
+/// `SignalStack::flags`: the alternate stack is disabled.
+pub const SS_DISABLE: i32 = 2;
+
+/// Signal information passed to `SA_SIGINFO` handlers, `siginfo_t`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    /// The union of the signal specific fields, e.g. the faulting address of
+    /// `SIGSEGV` in `fields[0]`.
+    pub fields: [u64; 14],
+}
+
+impl SigInfo {
+    pub const fn new(signo: i32, code: i32) -> Self {
+        Self {
+            signo,
+            errno: 0,
+            code,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+}
+
+/// An alternate signal stack, `stack_t`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub flags: i32,
+    pub size: usize,
+}
+
+impl SignalStack {
+    /// The `uc_stack` recorded when no alternate stack is set up.
+    pub const DISABLED: Self = Self {
+        sp: 0,
+        flags: SS_DISABLE,
+        size: 0,
+    };
+
+    /// Whether `sp` lies on this stack.
+    pub fn contains(&self, sp: usize) -> bool {
+        sp > self.sp && sp <= self.sp + self.size
+    }
+}
+
+/// How to deliver a signal.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalDelivery {
+    pub info: SigInfo,
+    /// Address of the handler.
+    pub handler: usize,
+    /// Address the handler returns to, which issues `rt_sigreturn`.
+    pub restorer: usize,
+    /// Signal mask to restore on `rt_sigreturn`.
+    pub saved_mask: u64,
+    /// Alternate stack to run the handler on, for `SA_ONSTACK` handlers.
+    pub alt_stack: Option<SignalStack>,
+}
+
+/// The head of `ucontext`, common to all architectures.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(crate) struct UContext<M> {
+    pub flags: u64,
+    pub link: u64,
+    pub stack: SignalStack,
+    pub sigmask: u64,
+    /// Room for a larger `sigset_t`.
+    pub _unused: [u8; 120],
+    pub mcontext: M,
+}
+
+/// `rt_sigframe`, as found at the stack pointer of the handler.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(crate) struct RtSigFrame<M> {
+    pub info: SigInfo,
+    pub uc: UContext<M>,
+}
+
+impl<M> RtSigFrame<M> {
+    pub fn new(delivery: &SignalDelivery, sp: usize, mcontext: M) -> Self {
+        let stack = match delivery.alt_stack {
+            Some(alt) if alt.contains(sp) => SignalStack {
+                flags: SS_ONSTACK,
+                ..alt
+            },
+            Some(alt) => alt,
+            None => SignalStack::DISABLED,
+        };
+        Self {
+            info: delivery.info,
+            uc: UContext {
+                flags: 0,
+                link: 0,
+                stack,
+                sigmask: delivery.saved_mask,
+                _unused: [0; 120],
+                mcontext,
+            },
+        }
+    }
+}
+
+/// Returns the stack pointer to push the frame of `delivery` below, switching
+/// to the alternate stack unless already on it.
+fn frame_top(delivery: &SignalDelivery, sp: usize) -> usize {
+    match delivery.alt_stack {
+        Some(alt) if alt.flags & SS_DISABLE == 0 && alt.size != 0 && !alt.contains(sp) => {
+            alt.sp + alt.size
+        }
+        _ => sp,
+    }
+}
+
+/// Push `frame` below the user stack pointer `sp`, 16-byte aligned, leaving
+/// `ext_len` bytes after it for extended contexts of a variable size.
+///
+/// Returns the address of the frame, or `None` if the stack is not writable.
+pub(crate) fn push_frame<M>(
+    delivery: &SignalDelivery,
+    sp: usize,
+    frame: &RtSigFrame<M>,
+    ext_len: usize,
+) -> Option<usize> {
+    let len = size_of::<RtSigFrame<M>>() + ext_len;
+    let addr = frame_top(delivery, sp).checked_sub(len)? & !0xf;
+    write_user(addr, frame).then_some(addr)
+}
+
+/// Read the frame at the user stack pointer `sp` of `rt_sigreturn`.
+pub(crate) fn pop_frame<M: Copy>(sp: usize) -> Option<RtSigFrame<M>> {
+    if sp % 16 != 0 {
+        return None;
+    }
+    read_user(sp)
+}
+
+/// Copy `value` to user memory at `addr`. Returns `false` if it is not
+/// writable.
+pub(crate) fn write_user<T>(addr: usize, value: &T) -> bool {
+    let src = (value as *const T).cast();
+    unsafe { copy_to_user(addr as *mut u8, src, size_of::<T>()) == 0 }
+}
+
+/// Read a `T` from user memory at `addr`, or `None` if it is not readable.
+pub(crate) fn read_user<T: Copy>(addr: usize) -> Option<T> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let len = size_of::<T>();
+    let ret = unsafe { copy_from_user(value.as_mut_ptr().cast(), addr as *const u8, len) };
+    (ret == 0).then(|| unsafe { value.assume_init() })
+}
@@ -0,0 +1,340 @@
+//! Object caches for fixed-size kernel objects.
+//!
+//! A [`SlabCache<T>`] carves frames from the global frame allocator into
+//! equally sized slots for `T`. Free slots are threaded into an intrusive list
+//! per slab. In front of the slabs each CPU keeps a small magazine of free
+//! objects, so the common alloc/free path only touches CPU-local state.
+//!
+//! ```text
+//!   alloc/free --> [ CPU magazine ] --refill/flush--> [ depot: slab, slab, ... ]
+//!                                                               |
+//!                                                  frame_alloc / FrameTracker drop
+//! ```
+use crate::addr::{PhysAddr, phys_to_virt};
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+use crate::arch::config::mm::PAGE_SIZE;
+use crate::frame_allocator::{FrameTracker, frame_alloc_physical_pages};
+use crate::utils::MutexNoIrq;
+use alloc::vec::Vec;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// Number of free objects each CPU may hold in its magazine.
+const MAGAZINE_SIZE: usize = 16;
+
+/// Minimum number of objects per slab; slabs span several pages for big objects.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Usage statistics of a [`SlabCache`].
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    /// Size of one slot in bytes, including padding.
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    /// Number of slabs currently owned by the cache.
+    pub slabs: usize,
+    /// Frames backing those slabs.
+    pub pages: usize,
+    /// Objects handed out to callers.
+    pub active_objects: usize,
+    /// Free objects parked in per-CPU magazines.
+    pub cached_objects: usize,
+}
+
+/// One or more consecutive frames split into object slots.
+struct Slab {
+    /// Dropping the trackers returns the frames to the frame allocator.
+    frames: Vec<FrameTracker>,
+    base: usize,
+    /// Head of the intrusive free list, `0` if the slab is full.
+    free: usize,
+    /// Slots not on the free list, including those cached in magazines.
+    in_use: usize,
+}
+
+impl Slab {
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr < self.base + self.frames.len() * PAGE_SIZE
+    }
+}
+
+/// Slabs shared by all CPUs.
+struct Depot {
+    slabs: Vec<Slab>,
+}
+
+/// Per-CPU stack of free objects.
+struct Magazine {
+    objs: [usize; MAGAZINE_SIZE],
+    len: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objs: [0; MAGAZINE_SIZE],
+            len: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.objs[self.len])
+    }
+
+    fn push(&mut self, obj: usize) -> bool {
+        if self.len == MAGAZINE_SIZE {
+            return false;
+        }
+        self.objs[self.len] = obj;
+        self.len += 1;
+        true
+    }
+}
+
+/// A cache of equally sized objects of type `T`.
+///
+/// Caches are meant to live in statics:
+///
+/// ```rust
+/// static TRAP_FRAMES: SlabCache<TrapFrame> = SlabCache::with_ctor("trap_frame", TrapFrame::new);
+///
+/// let tf = TRAP_FRAMES.alloc().expect("out of memory");
+/// ```
+pub struct SlabCache<T> {
+    name: &'static str,
+    ctor: Option<fn() -> T>,
+    depot: MutexNoIrq<Depot>,
+    magazines: [MutexNoIrq<Magazine>; MAX_HARTS],
+}
+
+impl<T> SlabCache<T> {
+    /// Size of one slot. Free slots store the free list link in place.
+    const OBJECT_SIZE: usize = {
+        let size = if size_of::<T>() > size_of::<usize>() {
+            size_of::<T>()
+        } else {
+            size_of::<usize>()
+        };
+        size.next_multiple_of(Self::OBJECT_ALIGN)
+    };
+    const OBJECT_ALIGN: usize = {
+        let align = if align_of::<T>() > align_of::<usize>() {
+            align_of::<T>()
+        } else {
+            align_of::<usize>()
+        };
+        assert!(
+            align <= PAGE_SIZE,
+            "slab objects cannot be page-overaligned"
+        );
+        align
+    };
+    const PAGES_PER_SLAB: usize = (Self::OBJECT_SIZE * MIN_OBJECTS_PER_SLAB).div_ceil(PAGE_SIZE);
+    const OBJECTS_PER_SLAB: usize = Self::PAGES_PER_SLAB * PAGE_SIZE / Self::OBJECT_SIZE;
+
+    /// Create an empty cache. Objects must be provided to [`Self::alloc_with`].
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ctor: None,
+            depot: MutexNoIrq::new(Depot { slabs: Vec::new() }),
+            magazines: [const { MutexNoIrq::new(Magazine::new()) }; MAX_HARTS],
+        }
+    }
+
+    /// Create an empty cache whose objects are built by `ctor` in [`Self::alloc`].
+    pub const fn with_ctor(name: &'static str, ctor: fn() -> T) -> Self {
+        let mut cache = Self::new(name);
+        cache.ctor = Some(ctor);
+        cache
+    }
+
+    /// Allocate an object built by the cache's constructor.
+    ///
+    /// Returns `None` if no frames are available.
+    /// # Panics
+    /// Panics if the cache was created without a constructor.
+    pub fn alloc(&self) -> Option<SlabBox<'_, T>> {
+        let ctor = self
+            .ctor
+            .unwrap_or_else(|| panic!("slab cache {} has no constructor", self.name));
+        self.alloc_with(ctor())
+    }
+
+    /// Allocate an object and move `value` into it.
+    ///
+    /// Returns `None` if no frames are available.
+    pub fn alloc_with(&self, value: T) -> Option<SlabBox<'_, T>> {
+        let ptr = self.alloc_raw()?.cast::<T>();
+        unsafe { ptr.write(value) };
+        Some(SlabBox { ptr, cache: self })
+    }
+
+    /// Allocate an uninitialized slot.
+    fn alloc_raw(&self) -> Option<NonNull<u8>> {
+        let Some(magazine) = self.magazines.get(hart_id()) else {
+            // CPUs beyond `MAX_HARTS` have no magazine and go to the depot.
+            let obj = self.depot_take(&mut self.depot.lock())?;
+            return NonNull::new(obj as *mut u8);
+        };
+        let mut magazine = magazine.lock();
+        if let Some(obj) = magazine.pop() {
+            return NonNull::new(obj as *mut u8);
+        }
+        // Refill half of the magazine so that alloc/free ping-pong stays local.
+        let mut depot = self.depot.lock();
+        for _ in 0..MAGAZINE_SIZE / 2 {
+            match self.depot_take(&mut depot) {
+                Some(obj) => magazine.push(obj),
+                None => break,
+            };
+        }
+        magazine.pop().and_then(|obj| NonNull::new(obj as *mut u8))
+    }
+
+    /// Give back a slot whose object has already been dropped.
+    fn free_raw(&self, ptr: NonNull<u8>) {
+        let Some(magazine) = self.magazines.get(hart_id()) else {
+            Self::depot_put(&mut self.depot.lock(), ptr.as_ptr() as usize);
+            return;
+        };
+        let mut magazine = magazine.lock();
+        if magazine.push(ptr.as_ptr() as usize) {
+            return;
+        }
+        let mut depot = self.depot.lock();
+        for _ in 0..MAGAZINE_SIZE / 2 {
+            let obj = magazine.pop().unwrap();
+            Self::depot_put(&mut depot, obj);
+        }
+        magazine.push(ptr.as_ptr() as usize);
+    }
+
+    fn depot_take(&self, depot: &mut Depot) -> Option<usize> {
+        let slab = match depot.slabs.iter_mut().position(|slab| slab.free != 0) {
+            Some(index) => &mut depot.slabs[index],
+            None => {
+                depot.slabs.push(self.new_slab()?);
+                depot.slabs.last_mut().unwrap()
+            }
+        };
+        let obj = slab.free;
+        slab.free = unsafe { *(obj as *const usize) };
+        slab.in_use += 1;
+        Some(obj)
+    }
+
+    fn depot_put(depot: &mut Depot, obj: usize) {
+        let slab = depot
+            .slabs
+            .iter_mut()
+            .find(|slab| slab.contains(obj))
+            .expect("object does not belong to this slab cache");
+        unsafe { *(obj as *mut usize) = slab.free };
+        slab.free = obj;
+        slab.in_use -= 1;
+    }
+
+    fn new_slab(&self) -> Option<Slab> {
+        let frames = frame_alloc_physical_pages(Self::PAGES_PER_SLAB)?;
+        let base = phys_to_virt(PhysAddr::from(frames[0].ppn)).0;
+        // Thread every slot into the free list, lowest address first.
+        let mut free = 0;
+        for i in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let obj = base + i * Self::OBJECT_SIZE;
+            unsafe { *(obj as *mut usize) = free };
+            free = obj;
+        }
+        log::trace!("slab cache {}: new slab at {:#x}", self.name, base);
+        Some(Slab {
+            frames,
+            base,
+            free,
+            in_use: 0,
+        })
+    }
+
+    /// Flush all per-CPU magazines and return completely free slabs to the
+    /// frame allocator.
+    ///
+    /// Returns the number of frames released.
+    pub fn shrink(&self) -> usize {
+        for magazine in self.magazines.iter() {
+            let mut magazine = magazine.lock();
+            let mut depot = self.depot.lock();
+            while let Some(obj) = magazine.pop() {
+                Self::depot_put(&mut depot, obj);
+            }
+        }
+        let mut depot = self.depot.lock();
+        let before = depot.slabs.len();
+        depot.slabs.retain(|slab| slab.in_use != 0);
+        (before - depot.slabs.len()) * Self::PAGES_PER_SLAB
+    }
+
+    /// Returns the usage statistics of this cache.
+    pub fn stats(&self) -> SlabStats {
+        let cached_objects = self
+            .magazines
+            .iter()
+            .map(|magazine| magazine.lock().len)
+            .sum::<usize>();
+        let depot = self.depot.lock();
+        let in_use = depot.slabs.iter().map(|slab| slab.in_use).sum::<usize>();
+        SlabStats {
+            name: self.name,
+            object_size: Self::OBJECT_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: depot.slabs.len(),
+            pages: depot.slabs.len() * Self::PAGES_PER_SLAB,
+            active_objects: in_use.saturating_sub(cached_objects),
+            cached_objects,
+        }
+    }
+}
+
+/// An owned object allocated from a [`SlabCache`].
+///
+/// The object is dropped and its slot returned to the cache when the box is dropped.
+pub struct SlabBox<'a, T> {
+    ptr: NonNull<T>,
+    cache: &'a SlabCache<T>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+impl<T> SlabBox<'_, T> {
+    /// Returns the raw pointer to the object, e.g. to hand it to assembly.
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.ptr.as_ptr()) };
+        self.cache.free_raw(self.ptr.cast());
+    }
+}
@@ -0,0 +1,154 @@
+//! Deferred work.
+//!
+//! Interrupt handlers run with interrupts disabled, so they queue the heavy
+//! part of their work with [`defer_work`] on the current CPU. The queue is
+//! run on the way out of the interrupt trap, after the handlers and before
+//! returning to the interrupted context, with interrupts enabled but not
+//! preemptible.
+//!
+//! Work left after [`DEFERRED_EXIT_BUDGET_US`] stays queued for the next
+//! interrupt exit, and the hook set with [`set_deferred_work_hook`] runs, to
+//! wake a kernel thread that calls [`run_deferred_work`].
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+use crate::arch::time::get_time_us;
+use crate::irq::{in_interrupt, with_interrupts};
+use crate::preempt::PreemptGuard;
+use crate::utils::MutexNoIrq;
+
+/// Work items a CPU queues before [`defer_work`] fails.
+pub const DEFERRED_QUEUE_SIZE: usize = 64;
+
+/// Time spent on deferred work on the way out of an interrupt before handing
+/// it to the kernel thread.
+pub const DEFERRED_EXIT_BUDGET_US: usize = 2000;
+
+/// A work item, run as `func(arg)`.
+#[derive(Clone, Copy)]
+struct Work {
+    func: fn(usize),
+    arg: usize,
+}
+
+struct WorkQueue {
+    items: [Option<Work>; DEFERRED_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self {
+            items: [None; DEFERRED_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, work: Work) -> bool {
+        if self.len == DEFERRED_QUEUE_SIZE {
+            return false;
+        }
+        self.items[(self.head + self.len) % DEFERRED_QUEUE_SIZE] = Some(work);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % DEFERRED_QUEUE_SIZE;
+        self.len -= 1;
+        work
+    }
+}
+
+static WORK_QUEUES: [MutexNoIrq<WorkQueue>; MAX_HARTS] =
+    [const { MutexNoIrq::new(WorkQueue::new()) }; MAX_HARTS];
+
+/// Whether this CPU is running deferred work on an interrupt exit.
+#[unsafe(mantahal_macro::def_percpu)]
+static DEFERRED_RUNNING: bool = false;
+
+/// The hook run when deferred work exceeds its budget, or 0.
+static DEFERRED_WORK_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the queue of the current CPU, which CPUs with an ID not below
+/// `MAX_HARTS` lack.
+fn work_queue() -> Option<&'static MutexNoIrq<WorkQueue>> {
+    WORK_QUEUES.get(hart_id())
+}
+
+/// Queue `func(arg)` to run on the current CPU once its interrupts are
+/// handled. Returns `false` if the queue of the CPU is full, or the CPU has
+/// none.
+pub fn defer_work(func: fn(usize), arg: usize) -> bool {
+    work_queue().is_some_and(|queue| queue.lock().push(Work { func, arg }))
+}
+
+/// Whether work is queued on the current CPU.
+pub fn has_deferred_work() -> bool {
+    work_queue().is_some_and(|queue| queue.lock().len != 0)
+}
+
+/// Set the hook run when deferred work exceeds its budget on an interrupt
+/// exit. It typically wakes a kernel thread calling [`run_deferred_work`].
+pub fn set_deferred_work_hook(hook: fn()) {
+    DEFERRED_WORK_HOOK.store(hook as usize, Ordering::Release);
+}
+
+/// Run one work item of the current CPU. Returns `false` if there was none.
+fn run_one() -> bool {
+    let _guard = PreemptGuard::new();
+    let work = work_queue().and_then(|queue| queue.lock().pop());
+    work.map(|work| (work.func)(work.arg)).is_some()
+}
+
+/// Run the work queued on the current CPU until none is left, from a kernel
+/// thread. It is preemptible between work items, and interrupts taken while
+/// one runs leave the rest to it.
+pub fn run_deferred_work() {
+    loop {
+        let _guard = PreemptGuard::new();
+        unsafe { DEFERRED_RUNNING.write_current_raw(true) };
+        let ran = run_one();
+        unsafe { DEFERRED_RUNNING.write_current_raw(false) };
+        if !ran {
+            break;
+        }
+    }
+}
+
+/// Run the deferred work of this CPU at the end of an interrupt trap, called
+/// with interrupts disabled.
+///
+/// Nothing runs within another interrupt or deferred work, which the
+/// outermost exit takes care of.
+pub(crate) fn run_on_interrupt_exit() {
+    if in_interrupt() || unsafe { DEFERRED_RUNNING.read_current_raw() } || !has_deferred_work() {
+        return;
+    }
+    let _guard = PreemptGuard::new();
+    unsafe { DEFERRED_RUNNING.write_current_raw(true) };
+    let start = get_time_us();
+    let finished = with_interrupts(|| {
+        while run_one() {
+            if get_time_us() - start >= DEFERRED_EXIT_BUDGET_US {
+                return !has_deferred_work();
+            }
+        }
+        true
+    });
+    unsafe { DEFERRED_RUNNING.write_current_raw(false) };
+    if !finished {
+        let hook = DEFERRED_WORK_HOOK.load(Ordering::Acquire);
+        if hook != 0 {
+            let hook: fn() = unsafe { core::mem::transmute(hook) };
+            hook();
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Interrupt and trap statistics.
+//!
+//! The trap handlers of both architectures count every trap they take on the
+//! CPU taking it, right after classifying it and before any handler runs, so
+//! faults resolved by an exception table fixup count too. Counters only grow
+//! and are read without stopping the CPUs, so a snapshot of a busy CPU is
+//! only consistent counter by counter.
+//!
+//! CPUs with an ID not below `MAX_HARTS` are not counted.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+use crate::cpumask::CpuMask;
+use crate::irq::irq_names;
+use crate::println;
+use crate::trap::{AccessType, TrapKind};
+
+/// IRQ numbers counted one by one, covering every number the interrupt
+/// controllers of both architectures report.
+pub const NR_IRQ_STATS: usize = 1024;
+
+/// Counted traps, other than the external interrupts of each IRQ.
+#[derive(Clone, Copy)]
+enum Counter {
+    Timer,
+    Ipi,
+    SysCall,
+    PageFaultRead,
+    PageFaultWrite,
+    PageFaultExecute,
+    PageFaultProtection,
+    AddressError,
+    IllegalInstruction,
+    Breakpoint,
+    SpuriousIrq,
+    Unknown,
+}
+
+const NR_COUNTERS: usize = Counter::Unknown as usize + 1;
+
+/// The rows of [`dump_trap_stats`] after the IRQs, with their label.
+const COUNTER_ROWS: [(Counter, &str, &str); NR_COUNTERS] = [
+    (Counter::Timer, "TIM", "Timer interrupts"),
+    (Counter::Ipi, "IPI", "Inter-processor interrupts"),
+    (Counter::SysCall, "SYS", "System calls"),
+    (Counter::PageFaultRead, "PFR", "Page faults on read"),
+    (Counter::PageFaultWrite, "PFW", "Page faults on write"),
+    (Counter::PageFaultExecute, "PFX", "Page faults on execute"),
+    (Counter::PageFaultProtection, "PFP", "Protection faults"),
+    (Counter::AddressError, "ADE", "Address errors"),
+    (Counter::IllegalInstruction, "ILL", "Illegal instructions"),
+    (Counter::Breakpoint, "BRK", "Breakpoints"),
+    (Counter::SpuriousIrq, "SPU", "Spurious interrupts"),
+    (Counter::Unknown, "UNK", "Unknown traps"),
+];
+
+struct CpuStats {
+    counters: [AtomicUsize; NR_COUNTERS],
+    irqs: [AtomicUsize; NR_IRQ_STATS],
+}
+
+impl CpuStats {
+    const fn new() -> Self {
+        Self {
+            counters: [const { AtomicUsize::new(0) }; NR_COUNTERS],
+            irqs: [const { AtomicUsize::new(0) }; NR_IRQ_STATS],
+        }
+    }
+
+    fn count(&self, counter: Counter) {
+        self.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, counter: Counter) -> usize {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+}
+
+static CPU_STATS: [CpuStats; MAX_HARTS] = [const { CpuStats::new() }; MAX_HARTS];
+
+/// A snapshot of the traps taken by a CPU since boot.
+#[derive(Debug, Clone, Default)]
+pub struct TrapStats {
+    pub timer: usize,
+    pub ipi: usize,
+    pub syscall: usize,
+    pub page_fault_read: usize,
+    pub page_fault_write: usize,
+    pub page_fault_execute: usize,
+    /// Page faults on a mapping forbidding the access, also counted by access
+    /// above. Always 0 on RISC-V, which cannot tell them apart.
+    pub page_fault_protection: usize,
+    pub address_error: usize,
+    pub illegal_instruction: usize,
+    pub breakpoint: usize,
+    /// External interrupts with no IRQ pending in the interrupt controller.
+    pub spurious_irq: usize,
+    pub unknown: usize,
+    /// External interrupts taken, as `(irq, count)` pairs in ascending IRQ
+    /// order, without the IRQs never taken.
+    pub irqs: Vec<(usize, usize)>,
+}
+
+/// Count `kind` on the current CPU. Called by the trap handlers.
+pub(crate) fn record_trap(kind: &TrapKind) {
+    let Some(stats) = CPU_STATS.get(hart_id()) else {
+        return;
+    };
+    match *kind {
+        TrapKind::Timer => stats.count(Counter::Timer),
+        TrapKind::Ipi => stats.count(Counter::Ipi),
+        TrapKind::SysCall => stats.count(Counter::SysCall),
+        TrapKind::PageFault(info) => {
+            stats.count(match info.access {
+                AccessType::Read => Counter::PageFaultRead,
+                AccessType::Write => Counter::PageFaultWrite,
+                AccessType::Execute => Counter::PageFaultExecute,
+            });
+            if info.protection {
+                stats.count(Counter::PageFaultProtection);
+            }
+        }
+        TrapKind::AddressError { .. } => stats.count(Counter::AddressError),
+        TrapKind::IllegalInstruction(_) => stats.count(Counter::IllegalInstruction),
+        TrapKind::Breakpoint => stats.count(Counter::Breakpoint),
+        TrapKind::Irq(0) => stats.count(Counter::SpuriousIrq),
+        TrapKind::Irq(irq) => {
+            if let Some(count) = stats.irqs.get(irq) {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        TrapKind::Unknown => stats.count(Counter::Unknown),
+    }
+}
+
+/// Returns the traps taken by `cpu` since boot, or `None` if it does not
+/// exist.
+pub fn trap_stats(cpu: usize) -> Option<TrapStats> {
+    let stats = CPU_STATS
+        .get(cpu)
+        .filter(|_| CpuMask::all().contains(cpu))?;
+    Some(TrapStats {
+        timer: stats.get(Counter::Timer),
+        ipi: stats.get(Counter::Ipi),
+        syscall: stats.get(Counter::SysCall),
+        page_fault_read: stats.get(Counter::PageFaultRead),
+        page_fault_write: stats.get(Counter::PageFaultWrite),
+        page_fault_execute: stats.get(Counter::PageFaultExecute),
+        page_fault_protection: stats.get(Counter::PageFaultProtection),
+        address_error: stats.get(Counter::AddressError),
+        illegal_instruction: stats.get(Counter::IllegalInstruction),
+        breakpoint: stats.get(Counter::Breakpoint),
+        spurious_irq: stats.get(Counter::SpuriousIrq),
+        unknown: stats.get(Counter::Unknown),
+        irqs: stats
+            .irqs
+            .iter()
+            .enumerate()
+            .map(|(irq, count)| (irq, count.load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count != 0)
+            .collect(),
+    })
+}
+
+/// Print the trap counts of every CPU on the console, one row per IRQ taken
+/// and per other kind of trap, in the manner of Linux `/proc/interrupts`.
+pub fn dump_trap_stats() {
+    let cpus = CpuMask::all();
+    let mut header = String::from("     ");
+    for cpu in cpus.iter() {
+        header += &format!(" {:>10}", format!("CPU{cpu}"));
+    }
+    println!("{header}");
+
+    let row = |label: &str, count: &dyn Fn(&CpuStats) -> usize, name: &str| {
+        let mut line = format!("{label:>4}:");
+        for cpu in cpus.iter() {
+            line += &format!(" {:>10}", count(&CPU_STATS[cpu]));
+        }
+        println!("{line}  {name}");
+    };
+    for irq in 1..NR_IRQ_STATS {
+        let taken = cpus
+            .iter()
+            .any(|cpu| CPU_STATS[cpu].irqs[irq].load(Ordering::Relaxed) != 0);
+        if taken {
+            let label = format!("{irq}");
+            let names = irq_names(irq).join(", ");
+            row(
+                &label,
+                &|stats| stats.irqs[irq].load(Ordering::Relaxed),
+                &names,
+            );
+        }
+    }
+    for (counter, label, name) in COUNTER_ROWS {
+        row(label, &|stats| stats.get(counter), name);
+    }
+}
@@ -1,4 +1,19 @@
-use crate::addr::VirtAddr;
+//! TLB maintenance.
+//!
+//! Translations of user address spaces are flushed on other CPUs with
+//! [`flush_tlb_range_on`], through SBI on RISC-V and IPIs on LoongArch.
+//! Switching page tables flushes the local TLB, so only the CPUs running an
+//! address space hold its translations: kernels record them with
+//! [`set_active_asid`] and flush with [`flush_tlb_range`].
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, fence};
+
+use crate::addr::{VirtAddr, VirtAddrRange};
+use crate::arch::arch::hart_id;
+use crate::arch::config::board::MAX_HARTS;
+use crate::arch::mm::tlb::remote_flush_range;
+use crate::cpumask::CpuMask;
+use crate::ipi::smp_call_on_cpus;
+
 pub struct Tlb;
 
 pub trait TLBOperation {
@@ -6,4 +21,87 @@ pub trait TLBOperation {
     fn flush_vaddr(vaddr: VirtAddr);
     /// flush all tlb entry
     fn flush_all();
+    /// flush the TLB entries of `range` in address space `asid`
+    fn flush_range(asid: usize, range: VirtAddrRange);
+}
+
+/// Ranges of more pages than this flush the whole address space instead.
+pub(crate) const FLUSH_ALL_PAGES: usize = 64;
+
+/// Marks a CPU without an active user address space.
+const NO_ASID: usize = usize::MAX;
+
+/// The address space active on each CPU.
+static ACTIVE_ASIDS: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(NO_ASID) }; MAX_HARTS];
+
+/// Record `asid` as the address space of the current CPU.
+///
+/// CPUs with an ID not below `MAX_HARTS` are not tracked, so they miss the
+/// flushes of other CPUs, which is reported once.
+fn store_active_asid(asid: usize) {
+    static UNTRACKED_REPORTED: AtomicBool = AtomicBool::new(false);
+    let cpu = hart_id();
+    match ACTIVE_ASIDS.get(cpu) {
+        Some(active) => active.store(asid, Ordering::SeqCst),
+        None if !UNTRACKED_REPORTED.swap(true, Ordering::Relaxed) => {
+            log::warn!("CPU {cpu} is beyond MAX_HARTS, its TLB misses remote flushes");
+        }
+        None => {}
+    }
+}
+
+/// Record that the current CPU runs address space `asid`, before switching
+/// to its page table.
+pub fn set_active_asid(asid: usize) {
+    store_active_asid(asid);
+}
+
+/// Record that the current CPU left its user address space, e.g. to run a
+/// kernel thread.
+pub fn clear_active_asid() {
+    store_active_asid(NO_ASID);
+}
+
+/// Returns the CPUs running address space `asid`.
+pub fn active_cpus(asid: usize) -> CpuMask {
+    // Order the page table updates to flush before reading the CPUs: a CPU
+    // not seen yet switches page tables after them.
+    fence(Ordering::SeqCst);
+    let mut cpus = CpuMask::new();
+    for (cpu, active) in ACTIVE_ASIDS.iter().enumerate() {
+        if active.load(Ordering::SeqCst) == asid {
+            cpus.insert(cpu);
+        }
+    }
+    cpus
+}
+
+/// Flush the translations of `range` in address space `asid` on every CPU of
+/// `cpus`, the current one included. Returns once they are flushed.
+///
+/// Without a remote flush from the firmware, the other CPUs are waited for,
+/// so interrupts must be enabled.
+pub fn flush_tlb_range_on(cpus: CpuMask, asid: usize, range: VirtAddrRange) {
+    if cpus.is_empty() || range.is_empty() || remote_flush_range(cpus, asid, range) {
+        return;
+    }
+    let request = FlushRequest { asid, range };
+    smp_call_on_cpus(cpus, flush_requested, &request as *const _ as usize, true);
+}
+
+/// Flush the translations of `range` in address space `asid` on the CPUs
+/// running it, see [`flush_tlb_range_on`].
+pub fn flush_tlb_range(asid: usize, range: VirtAddrRange) {
+    flush_tlb_range_on(active_cpus(asid), asid, range);
+}
+
+struct FlushRequest {
+    asid: usize,
+    range: VirtAddrRange,
+}
+
+/// Flush the [`FlushRequest`] at `request` on the current CPU.
+fn flush_requested(request: usize) {
+    let request = unsafe { &*(request as *const FlushRequest) };
+    Tlb::flush_range(request.asid, request.range);
 }
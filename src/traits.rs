@@ -1,6 +1,7 @@
 use core::time::Duration;
 
-use crate::arch::{context::Context, handler::TrapType, trapframe};
+use crate::arch::{context::Context, trapframe};
+use crate::trap::TrapKind;
 
 use super::addr::VirtAddr;
 
@@ -42,8 +43,8 @@ pub trait TrapOps {
     fn init();
     fn set_kernel_trap();
     fn set_user_trap();
-    fn kernel_trap_handler();
-    fn trap_handler(tf: &mut trapframe::TrapFrame) -> TrapType;
+    fn kernel_trap_handler(tf: &mut trapframe::TrapRegs) -> TrapKind;
+    fn trap_handler(tf: &mut trapframe::TrapFrame) -> TrapKind;
     fn trap_return(tf: &mut trapframe::TrapFrame);
 }
 
@@ -0,0 +1,356 @@
+//! Architecture-independent trap classification and handler registry.
+//!
+//! Both `trap_handler` on RISC-V and `loongarch64_trap_handler` on LoongArch
+//! decode the raw cause registers into a [`TrapKind`], so kernels can handle
+//! traps once for every supported architecture.
+//!
+//! Kernels install handlers per [`TrapClass`], either at runtime through
+//! [`register_trap_handler`] or statically with `#[arch_interrupt(Class)]`.
+//! Handlers of a class form a chain ordered by descending priority. Each one
+//! returns `true` if it handled the trap, otherwise the next one runs. If no
+//! handler claims the trap, the default handler set by
+//! [`set_default_trap_handler`] runs.
+//!
+//! Static handlers live in the `trap_handlers` link section, located through
+//! the `__start_trap_handlers` and `__stop_trap_handlers` symbols the linker
+//! provides, and are sorted into their chains once, on the first trap or
+//! registration. `#[arch_interrupt]` keeps them with `#[used(linker)]`, so
+//! crates using it need `#![feature(used_with_arg)]`.
+//!
+//! Handlers see the [`TrapRegs`] saved on every trap, which are all kernel
+//! mode traps save, and the head of the [`TrapFrame`] of user traps.
+//!
+//! The user context saved in a [`TrapFrame`] is accessed through the
+//! [`UserContext`] trait, so system call dispatch, restart and `exec` setup
+//! are architecture-independent too.
+//!
+//! [`TrapFrame`]: crate::TrapFrame
+use crate::arch::trapframe::TrapRegs;
+use crate::irq::IrqContext;
+use crate::utils::MutexNoIrq;
+use core::ops::IndexMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Once;
+
+/// The kind of memory access that faulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Details of a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFaultInfo {
+    /// The faulting virtual address.
+    pub addr: usize,
+    pub access: AccessType,
+    /// `true` if a valid mapping exists but forbids the access. RISC-V cannot
+    /// tell the two cases apart and always reports `false`.
+    pub protection: bool,
+}
+
+/// Architecture-independent trap cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    PageFault(PageFaultInfo),
+    /// Misaligned, out-of-range or otherwise inaccessible address.
+    AddressError {
+        addr: usize,
+        access: AccessType,
+    },
+    /// Illegal or privileged instruction, with its encoding if the hardware
+    /// reports it, `0` otherwise.
+    IllegalInstruction(usize),
+    SysCall,
+    Breakpoint,
+    Timer,
+    /// External interrupt, with the IRQ number claimed from the interrupt
+    /// controller, `0` if none was pending.
+    Irq(usize),
+    /// Inter-processor interrupt.
+    Ipi,
+    Unknown,
+}
+
+impl TrapKind {
+    /// Whether the trap is an asynchronous interrupt rather than an exception.
+    #[inline]
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self, TrapKind::Timer | TrapKind::Irq(_) | TrapKind::Ipi)
+    }
+}
+
+/// The variants of [`TrapKind`] without their payload, used as registry keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapClass {
+    PageFault,
+    AddressError,
+    IllegalInstruction,
+    SysCall,
+    Breakpoint,
+    Timer,
+    Irq,
+    Ipi,
+    Unknown,
+}
+
+const TRAP_CLASSES: usize = 9;
+
+impl TrapKind {
+    #[inline]
+    pub fn class(&self) -> TrapClass {
+        match self {
+            TrapKind::PageFault(_) => TrapClass::PageFault,
+            TrapKind::AddressError { .. } => TrapClass::AddressError,
+            TrapKind::IllegalInstruction(_) => TrapClass::IllegalInstruction,
+            TrapKind::SysCall => TrapClass::SysCall,
+            TrapKind::Breakpoint => TrapClass::Breakpoint,
+            TrapKind::Timer => TrapClass::Timer,
+            TrapKind::Irq(_) => TrapClass::Irq,
+            TrapKind::Ipi => TrapClass::Ipi,
+            TrapKind::Unknown => TrapClass::Unknown,
+        }
+    }
+}
+
+/// A trap handler. Returns `true` if the trap has been handled, `false` to
+/// pass it on to the next handler of the chain.
+pub type TrapHandler = fn(&mut TrapRegs, &TrapKind) -> bool;
+
+/// Handler run when no registered handler claims a trap.
+pub type DefaultTrapHandler = fn(&mut TrapRegs, &TrapKind);
+
+/// Maximum number of handlers per class, static and runtime ones combined.
+pub const MAX_TRAP_HANDLERS: usize = 8;
+
+/// A statically registered handler, emitted by `#[arch_interrupt]`.
+#[doc(hidden)]
+pub struct TrapHandlerEntry {
+    pub class: TrapClass,
+    pub priority: i32,
+    pub handler: TrapHandler,
+}
+
+#[derive(Clone, Copy)]
+struct Chain {
+    handlers: [Option<(i32, TrapHandler)>; MAX_TRAP_HANDLERS],
+    len: usize,
+}
+
+impl Chain {
+    const fn new() -> Self {
+        Self {
+            handlers: [None; MAX_TRAP_HANDLERS],
+            len: 0,
+        }
+    }
+
+    /// Insert behind every handler of the same or a higher priority.
+    fn insert(&mut self, priority: i32, handler: TrapHandler) -> bool {
+        if self.len == MAX_TRAP_HANDLERS {
+            return false;
+        }
+        let index = self.handlers[..self.len]
+            .iter()
+            .position(|entry| entry.is_some_and(|(p, _)| p < priority))
+            .unwrap_or(self.len);
+        self.handlers.copy_within(index..self.len, index + 1);
+        self.handlers[index] = Some((priority, handler));
+        self.len += 1;
+        true
+    }
+
+    fn remove(&mut self, handler: TrapHandler) -> bool {
+        let Some(index) = self.handlers[..self.len]
+            .iter()
+            .position(|entry| entry.is_some_and(|(_, h)| core::ptr::fn_addr_eq(h, handler)))
+        else {
+            return false;
+        };
+        self.handlers.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        self.handlers[self.len] = None;
+        true
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &(i32, TrapHandler)> {
+        self.handlers[..self.len].iter().flatten()
+    }
+}
+
+static TRAP_HANDLERS: [MutexNoIrq<Chain>; TRAP_CLASSES] =
+    [const { MutexNoIrq::new(Chain::new()) }; TRAP_CLASSES];
+
+/// The static handlers of each class, see [`static_chains`].
+static STATIC_TRAP_HANDLERS: Once<[Chain; TRAP_CLASSES]> = Once::new();
+
+/// The default handler, stored as an address so it can be swapped atomically.
+static DEFAULT_TRAP_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Built-in handler reporting traps the hardware could not classify, with a
+/// backtrace of the trapped context. It has the lowest priority and leaves the
+/// trap to the default handler.
+#[used(linker)]
+#[unsafe(link_section = "trap_handlers")]
+static UNKNOWN_TRAP_LOGGER: TrapHandlerEntry = TrapHandlerEntry {
+    class: TrapClass::Unknown,
+    priority: i32::MIN,
+    handler: |tf, kind| {
+        log::warn!("unknown trap: {:?}", kind);
+        crate::backtrace::backtrace_from_trap(tf);
+        false
+    },
+};
+
+/// Register `handler` for traps of `class`.
+///
+/// Handlers with a higher `priority` run first, handlers of equal priority in
+/// registration order, before static handlers of that priority. Returns
+/// `false` if the chain of `class` is full.
+pub fn register_trap_handler(class: TrapClass, priority: i32, handler: TrapHandler) -> bool {
+    let statics = static_chains()[class as usize].len;
+    let mut chain = TRAP_HANDLERS[class as usize].lock();
+    chain.len + statics < MAX_TRAP_HANDLERS && chain.insert(priority, handler)
+}
+
+/// Remove a handler installed by [`register_trap_handler`].
+///
+/// Returns `false` if `handler` is not registered for `class`.
+pub fn unregister_trap_handler(class: TrapClass, handler: TrapHandler) -> bool {
+    TRAP_HANDLERS[class as usize].lock().remove(handler)
+}
+
+/// Replace the handler run for traps no registered handler claims.
+///
+/// Without one, unclaimed traps are ignored and only returned to the caller
+/// of `run_user_task`.
+pub fn set_default_trap_handler(handler: DefaultTrapHandler) {
+    DEFAULT_TRAP_HANDLER.store(handler as usize, Ordering::Release);
+}
+
+fn static_trap_handlers() -> &'static [TrapHandlerEntry] {
+    unsafe extern "Rust" {
+        static __start_trap_handlers: TrapHandlerEntry;
+        static __stop_trap_handlers: TrapHandlerEntry;
+    }
+    let start = &raw const __start_trap_handlers;
+    let end = &raw const __stop_trap_handlers;
+    let len = (end as usize - start as usize) / size_of::<TrapHandlerEntry>();
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
+
+/// Returns the static handlers sorted into a chain per class, the first call
+/// sorting them.
+fn static_chains() -> &'static [Chain; TRAP_CLASSES] {
+    STATIC_TRAP_HANDLERS.call_once(|| {
+        let mut chains = [Chain::new(); TRAP_CLASSES];
+        for entry in static_trap_handlers() {
+            if !chains[entry.class as usize].insert(entry.priority, entry.handler) {
+                log::warn!("too many {:?} trap handlers, ignoring one", entry.class);
+            }
+        }
+        chains
+    })
+}
+
+/// Returns the handlers of `runtime` and `statics` by descending priority,
+/// those of `runtime` first among equals.
+fn merge_chains<'a>(
+    runtime: &'a Chain,
+    statics: &'a Chain,
+) -> impl Iterator<Item = TrapHandler> + 'a {
+    let mut runtime = runtime.iter().peekable();
+    let mut statics = statics.iter().peekable();
+    core::iter::from_fn(move || {
+        let next = match (runtime.peek(), statics.peek()) {
+            (Some((p, _)), Some((q, _))) if p >= q => &mut runtime,
+            (Some(_), None) => &mut runtime,
+            _ => &mut statics,
+        };
+        next.next().map(|&(_, handler)| handler)
+    })
+}
+
+/// Run the handler chain of `kind`, then `fallback` if no handler claimed the
+/// trap, and finally the default handler. Interrupts are handled in interrupt
+/// context.
+///
+/// Returns `true` if a registered handler or `fallback` claimed the trap. The
+/// chain is copied out of its lock first, so handlers may take nested traps
+/// and register handlers themselves.
+pub(crate) fn handle_trap(
+    tf: &mut TrapRegs,
+    kind: &TrapKind,
+    fallback: impl FnOnce(&mut TrapRegs) -> bool,
+) -> bool {
+    let _context = kind.is_interrupt().then(IrqContext::enter);
+    let class = kind.class();
+    let chain = *TRAP_HANDLERS[class as usize].lock();
+    let handled = merge_chains(&chain, &static_chains()[class as usize])
+        .any(|handler| handler(tf, kind))
+        || fallback(tf);
+    if !handled {
+        let default = DEFAULT_TRAP_HANDLER.load(Ordering::Acquire);
+        if default != 0 {
+            let default: DefaultTrapHandler = unsafe { core::mem::transmute(default) };
+            default(tf, kind);
+        }
+    }
+    handled
+}
+
+/// Registers of a [`TrapFrame`] with a role in the user ABI, for indexing.
+///
+/// [`TrapFrame`]: crate::TrapFrame
+#[allow(clippy::upper_case_acronyms)]
+pub enum TrapFrameArgs {
+    /// The user PC, `sepc` on RISC-V and `era` on LoongArch.
+    SEPC,
+    RA,
+    SP,
+    /// System call return value.
+    RET,
+    ARG0,
+    ARG1,
+    ARG2,
+    /// Thread pointer.
+    TLS,
+    /// System call number.
+    SYSCALL,
+}
+
+/// The user context saved in a [`TrapFrame`].
+///
+/// [`TrapFrame`]: crate::TrapFrame
+pub trait UserContext: IndexMut<TrapFrameArgs, Output = usize> {
+    /// A context entering user mode at `entry` with stack pointer `sp`.
+    fn new_user(entry: usize, sp: usize) -> Self;
+
+    /// Prepare the context for running a new program, entering `entry` with
+    /// the `main(argc, argv, envp)` arguments.
+    fn init_user(&mut self, user_sp: usize, entry: usize, argc: usize, argv: usize, envp: usize);
+
+    /// Returns the six system call arguments.
+    fn syscall_args(&self) -> [usize; 6];
+
+    /// Save the first argument register, which the return value of a system
+    /// call overwrites.
+    fn save_last_user_a0(&mut self);
+
+    /// Restore the first argument register saved by
+    /// [`save_last_user_a0`](Self::save_last_user_a0), to restart the system
+    /// call.
+    fn restore_last_user_a0(&mut self);
+
+    /// Set the user PC.
+    fn set_entry_point(&mut self, entry: usize) {
+        self[TrapFrameArgs::SEPC] = entry;
+    }
+
+    /// Advance the user PC past the system call instruction.
+    fn syscall_ok(&mut self) {
+        self[TrapFrameArgs::SEPC] += 4;
+    }
+}
@@ -3,6 +3,7 @@ use core::ops::{Deref, DerefMut};
 use spin::{Mutex, MutexGuard};
 
 use crate::arch::irq::Irq;
+use crate::preempt::PreemptGuard;
 
 pub struct MutexNoIrq<T: ?Sized> {
     lock: Mutex<T>,
@@ -51,12 +52,13 @@ impl<T> MutexNoIrq<T> {
 }
 
 impl<T: ?Sized> MutexNoIrq<T> {
-    /// Attempts to acquire the lock. If successful, interrupts will have been disabled
-    /// and will be restored when the returned guard is dropped.
+    /// Attempts to acquire the lock. If successful, interrupts and preemption will have
+    /// been disabled and will be restored when the returned guard is dropped.
     /// If the lock cannot be acquired, interrupts are restored to their previous state,
     /// and None is returned.
     #[inline]
     pub fn try_lock(&self) -> Option<MutexNoIrqGuard<T>> {
+        let preempt = PreemptGuard::new();
         let original_irq_status = IrqStatus {
             irq_enabled: Irq::interrupt_enabled(),
         };
@@ -70,14 +72,16 @@ impl<T: ?Sized> MutexNoIrq<T> {
         self.lock.try_lock().map(|guard| MutexNoIrqGuard {
             guard,
             _irq_status: original_irq_status,
+            _preempt: preempt,
         })
     }
 
     /// Acquires the lock, spinning if necessary.
-    /// Interrupts are disabled before attempting to acquire the lock and will
+    /// Interrupts and preemption are disabled before attempting to acquire the lock and will
     /// remain disabled while spinning. They are restored when the returned guard is dropped.
     #[inline]
     pub fn lock(&self) -> MutexNoIrqGuard<T> {
+        let preempt = PreemptGuard::new();
         let original_irq_status_keeper = IrqStatus {
             irq_enabled: Irq::interrupt_enabled(),
         };
@@ -96,6 +100,7 @@ impl<T: ?Sized> MutexNoIrq<T> {
         MutexNoIrqGuard {
             guard: acquired_guard,
             _irq_status: original_irq_status_keeper,
+            _preempt: preempt,
         }
     }
 
@@ -118,9 +123,14 @@ impl<T: ?Sized> MutexNoIrq<T> {
 }
 
 /// The Mutex Guard that also manages Irq state restoration.
+///
+/// Fields drop in order: the lock is released, then interrupts are restored,
+/// so a reschedule deferred while it was held can run when preemption is
+/// re-enabled.
 pub struct MutexNoIrqGuard<'a, T: ?Sized + 'a> {
     guard: MutexGuard<'a, T>,
     _irq_status: IrqStatus,
+    _preempt: PreemptGuard,
 }
 
 impl<T: ?Sized> Deref for MutexNoIrqGuard<'_, T> {
@@ -61,6 +61,19 @@ impl<T> OnceCell<T> {
         unsafe { &*(*self.value.get()).as_ptr() }
     }
 
+    /// Gets a reference to the value if initialized.
+    ///
+    /// Returns `None` if the cell has not been initialized.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        if self.initialized.load(Ordering::Acquire) {
+            // SAFETY: We've verified the cell is initialized
+            Some(unsafe { &*(*self.value.get()).as_ptr() })
+        } else {
+            None
+        }
+    }
+
     /// Gets a mutable reference to the value if initialized.
     ///
     /// Requires `&mut self` (exclusive access), making it safe for concurrency.